@@ -1,22 +1,111 @@
 // src/convo/client.rs
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use colored::{Color, Colorize};
 
-use anyhow::{anyhow, bail, Context, Result};
+use anyhow::{bail, Context, Result};
 use skychat_core::{
     manager::ConvoManager,
     manager::{ConvoInvite, ConvoMessage},
 };
 
-use skychat_server::server::ConvoUser;
+use skychat_core::changes::{Snapshot, SyncEvent};
+use skychat_core::manager::{DeliveryState, GroupSettings, MessageItem, SerializedCredentials};
+use skychat_protocol::{
+    error_codes, features, AcceptInvite, AccountExport, Capabilities, Connect, CreateAccount, CreateGroup,
+    DeleteAccount, ExportAccount, GetGroupIndex, GetMessages, GetPresence, GetProfiles,
+    GetUserKeys, InviteUser, KeyPackageStock, OpenEvents, Presence, PresenceStatus, Profile, ReaddUser,
+    Register, RejoinRequest, RemoveUser, RequestRejoin, SearchUsers, SearchUsersResponse,
+    SendMessage, SetPresence, Sync, UploadKeyPackages, UserInfo, SUPPORTED_VERSIONS,
+};
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::TryRecvError};
+
+use skychat_core::utils::ShortId;
+use tracing::{debug, instrument, warn};
 
-use skychat_core::manager::MessageItem;
+use crate::events::EventStream;
+use crate::outbox::{Encrypted, Outbox, OutboxStatus};
+use crate::sync::{SharedClient, SyncConfig, SyncEngine, SyncHandle};
+use crate::transport::{HttpTransport, RequestFailed, Transport};
 
 type GroupId = Vec<u8>;
 type SerializedMessage = Vec<u8>;
 
+// how many one-time key packages we try to keep on the server:
+const KEY_PACKAGE_POOL_SIZE: usize = 20;
+// how often (seconds) the published key packages are swapped for fresh ones, 1 week
+// (well within the manager's key package lifetime):
+pub const DEFAULT_KEY_PACKAGE_ROTATION: u64 = 60 * 60 * 24 * 7;
+
+// events a slow receiver can fall behind by before it misses some:
+const SYNC_EVENTS_CAPACITY: usize = 1024;
+// index conflicts resolved by resyncing straight away, before the message backs off:
+const MAX_CONFLICT_RETRIES: usize = 3;
+
+// how an attempt at sending an outbox message went
+enum Delivery {
+    Sent,
+    Retry { reason: String, retry_after: u64 },
+    Failed(String),
+}
+
+impl Delivery {
+    // the server refusing the message outright (we're not a member, it's too large, ...) is final,
+    // anything else (unreachable, rate limited, someone's commit got in first, a server error) is
+    // worth another try
+    fn from_error(e: anyhow::Error) -> Self {
+        let reason = format!("{:#}", e);
+        match e.downcast_ref::<RequestFailed>() {
+            Some(failed)
+                if failed.status < 500
+                    && failed.code() != Some(error_codes::RATE_LIMITED)
+                    && failed.code() != Some(error_codes::CONFLICT)
+                    && failed.code() != Some(error_codes::STALE_EPOCH) =>
+            {
+                Delivery::Failed(reason)
+            }
+            failed => Delivery::Retry {
+                reason,
+                retry_after: failed
+                    .and_then(|failed| failed.error.as_ref())
+                    .and_then(|error| error.retry_after)
+                    .unwrap_or(0),
+            },
+        }
+    }
+}
+
+fn is_conflict(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<RequestFailed>()
+        .is_some_and(|failed| failed.code() == Some(error_codes::CONFLICT))
+}
+
+fn is_not_found(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<RequestFailed>()
+        .is_some_and(|failed| failed.code() == Some(error_codes::NOT_FOUND))
+}
+
+// the server answered and turned the request down, so it stored nothing (a stale epoch, not a
+// member, rate limited, ...). no answer or a server error leaves it open
+fn is_refusal(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<RequestFailed>()
+        .is_some_and(|failed| (400..500).contains(&failed.status))
+}
+
+// another member's commit got to the server first
+fn is_stale_epoch(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<RequestFailed>()
+        .is_some_and(|failed| failed.code() == Some(error_codes::STALE_EPOCH))
+}
+
+fn current_timestamp_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
 // #[derive(Debug, Clone)]
 // pub struct PendingInvite {
 //     pub group_name: String,
@@ -28,7 +117,20 @@ pub struct ConvoClient {
     pub user_id: String,
     pub manager: ConvoManager,
     pub server_address: Option<String>,
+    // user_id -> display name, filled in from the directory as new senders show up:
     pub id_to_name: HashMap<String, String>,
+    // what the server told us it supports, None for servers that predate /api/capabilities:
+    pub capabilities: Option<Capabilities>,
+    // how we reach the server, set when connecting:
+    transport: Option<Arc<dyn Transport>>,
+    // seconds between key package rotations, 0 to never rotate:
+    pub key_package_rotation: u64,
+    // messages that haven't made it to the server yet, see outbox.rs:
+    pub outbox: Outbox,
+    // what syncing (and sending) changed, see `sync_events`:
+    sync_events: broadcast::Sender<SyncEvent>,
+    // set by `start_sync`:
+    sync_engine: Option<SyncEngine>,
 }
 
 impl ConvoClient {
@@ -38,47 +140,131 @@ impl ConvoClient {
             manager: ConvoManager::init(id.clone()),
             server_address: None,
             id_to_name: HashMap::new(),
+            capabilities: None,
+            transport: None,
+            key_package_rotation: DEFAULT_KEY_PACKAGE_ROTATION,
+            outbox: Outbox::default(),
+            sync_events: broadcast::channel(SYNC_EVENTS_CAPACITY).0,
+            sync_engine: None,
         }
     }
 
-    pub async fn create_group(&mut self, group_name: String) -> Result<GroupId> {
-        let address = self
-            .server_address
+    // a receiver for everything syncing and sending changes from here on: new messages,
+    // invites, membership, epochs, our own messages' delivery states and sync errors
+    pub fn sync_events(&self) -> broadcast::Receiver<SyncEvent> {
+        self.sync_events.subscribe()
+    }
+
+    fn emit(&self, event: SyncEvent) {
+        // nobody listening is fine:
+        let _ = self.sync_events.send(event);
+    }
+
+    pub fn transport(&self) -> Result<&dyn Transport> {
+        self.transport
+            .as_deref()
+            .context("Not connected to a server")
+    }
+
+    pub fn server_supports(&self, feature: &str) -> bool {
+        self.capabilities
             .as_ref()
-            .context("Server address is not set")?;
+            .is_some_and(|capabilities| capabilities.supports(feature))
+    }
+
+    // ask the server which wire versions / features it supports.
+    // fails if the server only speaks versions we don't.
+    pub async fn negotiate_capabilities(&mut self) -> Result<()> {
+        let capabilities = self
+            .transport()?
+            .capabilities()
+            .await
+            .context("Failed to get server capabilities")?;
+
+        if let Some(capabilities) = &capabilities {
+            if capabilities.negotiate(SUPPORTED_VERSIONS).is_none() {
+                bail!(
+                    "Incompatible server: it speaks wire versions {:?}, this client speaks {:?}",
+                    capabilities.wire_versions,
+                    SUPPORTED_VERSIONS
+                );
+            }
+        }
 
+        self.capabilities = capabilities;
+        Ok(())
+    }
+
+    pub async fn create_group(&mut self, group_name: String) -> Result<GroupId> {
+        let settings = self.manager.group_settings;
+        self.create_group_with_settings(group_name, settings).await
+    }
+
+    #[instrument(skip_all, fields(user = %self.user_id, group_name = %group_name))]
+    pub async fn create_group_with_settings(
+        &mut self,
+        group_name: String,
+        settings: GroupSettings,
+    ) -> Result<GroupId> {
         // create the local group:
         let group_id = self
             .manager
-            .create_group(group_name.clone())
+            .create_group_with_settings(group_name.clone(), settings)
             .context("Failed to create new group")?;
 
-        // send a POST request to the server/api/create_group
-        let client = reqwest::Client::new();
-        let response = client
-            .post(format!("{}/api/create_group", address))
-            .json(&serde_json::json!({
-              "group_id": group_id.clone(),
-              "group_name": group_name.clone(),
-              "sender_id": self.user_id.clone(),
-            }))
-            .send()
+        self.transport()?
+            .create_group(CreateGroup {
+                group_id: group_id.clone(),
+                group_name: group_name.clone(),
+                sender_id: self.user_id.clone(),
+            })
             .await
-            .context("Failed to send create_group request")?;
+            .context("Failed to create group")?;
 
-        if response.status().is_success() {
-            self.manager
-                .group_push_message(
-                    &group_id,
-                    "<group_created>".to_string(),
-                    "system".to_string(),
-                )
-                .context("Failed to add system message")?;
+        self.manager
+            .group_push_message(
+                &group_id,
+                "<group_created>".to_string(),
+                "system".to_string(),
+            )
+            .context("Failed to add system message")?;
 
-            Ok(group_id)
-        } else {
-            Err(anyhow!("Failed to create group: {}", response.status()))
-        }
+        Ok(group_id)
+    }
+
+    // what groups created or joined from now on start with
+    pub fn set_default_group_settings(&mut self, settings: GroupSettings) {
+        self.manager.group_settings = settings;
+    }
+
+    pub fn group_settings(&self, group_id: &GroupId) -> Result<GroupSettings> {
+        self.manager.group_get_settings(group_id)
+    }
+
+    // the manager's state plus the outbox, so unsent messages survive a restart wherever the
+    // state is kept
+    pub fn save_state(&self) -> Result<SerializedCredentials> {
+        let mut state = self.manager.save_state()?;
+        state.outbox = serde_json::to_vec(&self.outbox).context("Failed to serialize outbox")?;
+        Ok(state)
+    }
+
+    pub fn load_state(&mut self, state: SerializedCredentials) -> Result<()> {
+        // state saved by the manager alone has no outbox:
+        let outbox = match state.outbox.as_slice() {
+            [] => Outbox::default(),
+            outbox => serde_json::from_slice(outbox).context("Failed to deserialize outbox")?,
+        };
+        self.manager.load_state(state)?;
+        self.outbox = outbox;
+        Ok(())
+    }
+
+    // saved with the group, see `ConvoManager::save_state`
+    pub fn set_group_settings(&mut self, group_id: &GroupId, settings: GroupSettings) -> Result<()> {
+        self.manager
+            .group_set_settings(group_id, settings)
+            .context("Failed to change group settings")
     }
 
     // Get user key packages with anyhow error handling
@@ -86,30 +272,17 @@ impl ConvoClient {
         &self,
         user_ids: Vec<String>,
     ) -> Result<HashMap<String, Vec<u8>>> {
-        let address = self
-            .server_address
-            .as_ref()
-            .context("Server address is not set")?;
-
-        let client = reqwest::Client::new();
-        let response = client
-            .post(format!("{}/api/get_user_keys", address))
-            .json(&serde_json::json!({
-                "user_ids": user_ids.clone()
-            }))
-            .send()
-            .await
-            .context("Failed to send request to get user keys")?;
-
-        if !response.status().is_success() {
-            bail!("Failed to get key packages: {}", response.status());
-        }
-
-        let key_packages: HashMap<String, Vec<u8>> = response
-            .json()
+        let key_packages = self
+            .transport()?
+            .get_user_keys(GetUserKeys {
+                user_ids: user_ids.clone(),
+                sender_id: self.user_id.clone(),
+            })
             .await
-            .context("Failed to parse response data")?;
+            .context("Failed to get key packages")?
+            .key_packages;
 
+        // the server hands out all of them or none, so a short answer is a server bug:
         if key_packages.len() != user_ids.len() {
             bail!("Failed to get key packages for all users");
         }
@@ -118,6 +291,7 @@ impl ConvoClient {
     }
 
     // Create group with users using anyhow
+    #[instrument(skip_all, fields(user = %self.user_id))]
     pub async fn create_group_with_users(
         &mut self,
         group_name: String,
@@ -129,13 +303,13 @@ impl ConvoClient {
         // Get the group_id
         let group_id = self.get_group_id(group_name).await.expect("Failed to get group id");
 
-        println!("Getting key packages for users: {:?}", user_ids);
+        debug!(?user_ids, "fetching key packages");
 
         // Get key packages with error handling
         let key_packages_map = match self.get_user_key_packages(user_ids.clone()).await {
             Ok(map) => map,
             Err(e) => {
-                println!("Error getting key packages: {}", e);
+                warn!("failed to get key packages: {:#}", e);
                 // Add error message to the group
                 self.manager.group_push_message(
                     &group_id,
@@ -146,7 +320,7 @@ impl ConvoClient {
             }
         };
 
-        println!("Key packages map: {:?}", key_packages_map);
+        debug!(found = key_packages_map.len(), "fetched key packages");
 
         // Add system message that group was created
         self.manager.group_push_message(
@@ -173,7 +347,7 @@ impl ConvoClient {
                     let error_message = format!("<failed_to_invite_user {}: {}>", user_id, e);
                     self.manager
                         .group_push_message(&group_id, error_message, self.user_id.clone());
-                    println!("Error inviting user {}: {}", user_id, e);
+                    warn!(user_id, "failed to invite user: {:#}", e);
                 }
             }
         }
@@ -188,50 +362,191 @@ impl ConvoClient {
         Ok(group_id)
     }
 
+    #[instrument(skip_all, fields(user = %self.user_id))]
     pub async fn invite_user_to_group(
         &mut self,
         receiver_id: String,
         group_id: Vec<u8>,
         serialized_key_package: Vec<u8>,
     ) -> Result<()> {
-        let address = self
-            .server_address
-            .as_ref()
-            .context("Server address is not set")?;
+        let before = self.manager.snapshot();
+        let mut stale = None;
+        for _ in 0..=MAX_CONFLICT_RETRIES {
+            // the commit has to be made in the group's latest epoch:
+            self.fetch_groups(std::slice::from_ref(&group_id)).await?;
+
+            // Construct the invite using their key_package
+            let group_invite = self
+                .manager
+                .create_invite(&group_id, serialized_key_package.clone())
+                .context("Failed to create group invite")?;
+
+            let result = match self.transport() {
+                Ok(transport) => {
+                    transport
+                        .invite_user(InviteUser {
+                            group_id: group_id.clone(),
+                            sender_id: self.user_id.clone(),
+                            receiver_id: receiver_id.clone(),
+                            welcome_message: group_invite.welcome_message.clone(),
+                            ratchet_tree: group_invite.ratchet_tree.clone().unwrap_or_default(),
+                            fanned: group_invite.fanned.clone(),
+                        })
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+
+            let commit = group_invite.fanned.unwrap_or_default();
+            match self.settle_commit(&group_id, &commit, result) {
+                Ok(()) => {
+                    // our own commit won't come up in a sync, report it now:
+                    self.emit_changes(&before, None);
+                    return Ok(());
+                }
+                Err(e) if is_stale_epoch(&e) => {
+                    debug!("another commit got in first, resyncing");
+                    stale = Some(e);
+                }
+                Err(e) => return Err(e.context("Failed to send invite")),
+            }
+        }
 
-        // Construct the invite using their key_package
-        let group_invite = self
-            .manager
-            .create_invite(&group_id, serialized_key_package)
-            .context("Failed to create group invite")?;
-
-        let client = reqwest::Client::new();
-        let response = client
-            .post(format!("{}/api/invite_user", address))
-            .json(&serde_json::json!({
-              "group_id": group_id.clone(),
-              "sender_id": self.user_id.clone(),
-              "receiver_id": receiver_id.clone(),
-              "welcome_message": group_invite.welcome_message.clone(),
-              "ratchet_tree": group_invite.ratchet_tree.clone(),
-              "fanned": group_invite.fanned.clone(),
-            }))
-            .send()
-            .await
-            .context("Failed to send invite request")?;
+        Err(stale
+            .context("No invite attempts were made")?
+            .context("Failed to send invite"))
+    }
 
-        if response.status().is_success() {
-            // Get group and possibly update state
-            let _group = self
+    // the server took our pending commit (merge it) or turned it down (throw it away, nobody
+    // has seen it), either way the group can make the next one. without an answer either way
+    // it stays pending until the next sync looks for it in the group's log, see
+    // `ConvoManager::group_commit_unconfirmed`
+    fn settle_commit(&mut self, group_id: &GroupId, commit: &[u8], result: Result<()>) -> Result<()> {
+        match result {
+            Ok(()) => self
                 .manager
-                .groups
-                .get_mut(&group_id)
-                .context("Group not found")?;
+                .group_merge_pending_commit(group_id)
+                .context("Failed to merge our commit"),
+            // not connected, it never went out:
+            Err(e) if is_refusal(&e) || self.transport.is_none() => {
+                self.manager.group_discard_pending_commit(group_id)?;
+                Err(e)
+            }
+            Err(e) => {
+                warn!(group = %ShortId(group_id), "no answer to our commit, settling it next sync: {:#}", e);
+                self.manager
+                    .group_commit_unconfirmed(group_id, commit.to_vec())?;
+                Err(e)
+            }
+        }
+    }
 
-            Ok(())
-        } else {
-            Err(anyhow!("Failed to send invite: {}", response.status()))
+    #[instrument(skip_all, fields(user = %self.user_id, group = %ShortId(&group_id), removed = %user_id))]
+    pub async fn remove_user_from_group(&mut self, user_id: String, group_id: GroupId) -> Result<()> {
+        let before = self.manager.snapshot();
+        let mut stale = None;
+        for _ in 0..=MAX_CONFLICT_RETRIES {
+            // the commit has to be made in the group's latest epoch:
+            self.fetch_groups(std::slice::from_ref(&group_id)).await?;
+
+            match self.commit_removal(&user_id, &group_id).await {
+                Ok(()) => {
+                    self.emit_changes(&before, None);
+                    return Ok(());
+                }
+                Err(e) if is_stale_epoch(&e) => {
+                    debug!("another commit got in first, resyncing");
+                    stale = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(stale.context("No removal attempts were made")?)
+    }
+
+    // one attempt at removing a member, from wherever the group is now
+    async fn commit_removal(&mut self, user_id: &str, group_id: &GroupId) -> Result<()> {
+        let fanned = self
+            .manager
+            .remove_member(group_id, user_id)
+            .context("Failed to remove member")?;
+
+        let result = match self.transport() {
+            Ok(transport) => {
+                transport
+                    .remove_user(RemoveUser {
+                        group_id: group_id.clone(),
+                        sender_id: self.user_id.clone(),
+                        user_id: user_id.to_string(),
+                        fanned: fanned.clone(),
+                    })
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        self.settle_commit(group_id, &fanned, result)
+            .context("Failed to remove user")?;
+
+        self.manager.group_push_message(
+            group_id,
+            format!("<{}> was removed from the group", user_id),
+            "system".to_string(),
+        )
+    }
+
+    // for when our copy of a group is past repair (e.g. restored from an old backup): asks the
+    // other members to re-add us. their welcome comes in like an invite, and accepting it picks
+    // the group up where we left off, see `ConvoManager::process_invite`
+    #[instrument(skip_all, fields(user = %self.user_id, group = %ShortId(group_id)))]
+    pub async fn request_rejoin(&mut self, group_id: &GroupId) -> Result<()> {
+        if !self.server_supports(features::REJOIN) {
+            bail!("Server does not support rejoining groups");
         }
+
+        let key_package = self.manager.get_key_package()?;
+        self.transport()?
+            .request_rejoin(RequestRejoin {
+                group_id: group_id.clone(),
+                sender_id: self.user_id.clone(),
+                key_package: key_package.clone(),
+            })
+            .await
+            .context("Failed to request rejoin")?;
+        self.manager
+            .key_packages_published(std::slice::from_ref(&key_package), false)
+    }
+
+    // one attempt at answering a rejoin request, from wherever the group is now
+    async fn commit_readd(&mut self, group_id: &GroupId, request: RejoinRequest) -> Result<()> {
+        let invite = self
+            .manager
+            .readd_member(group_id, &request.user_id, request.key_package)
+            .context("Failed to re-add member")?;
+
+        let commit = invite.fanned.unwrap_or_default();
+        let result = match self.transport() {
+            Ok(transport) => {
+                transport
+                    .readd_user(ReaddUser {
+                        group_id: group_id.clone(),
+                        sender_id: self.user_id.clone(),
+                        user_id: request.user_id.clone(),
+                        welcome_message: invite.welcome_message,
+                        fanned: commit.clone(),
+                    })
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        self.settle_commit(group_id, &commit, result)
+            .context("Failed to re-add user")?;
+
+        self.manager.group_push_message(
+            group_id,
+            format!("<{}> was re-added to the group", request.user_id),
+            "system".to_string(),
+        )
     }
 
     pub async fn get_group_id(&self, group_name: String) -> Result<GroupId> {
@@ -246,54 +561,187 @@ impl ConvoClient {
         Ok(group_id.clone())
     }
 
+    #[instrument(skip_all, fields(user = %self.user_id, server = %server_address))]
     pub async fn connect_to_server(&mut self, server_address: String) -> Result<()> {
         self.server_address = Some(server_address.clone());
-        // use reqwest to send a POST request to the server/api/connect
-        let client = reqwest::Client::new();
+        self.connect_with_transport(Arc::new(HttpTransport::new(server_address)))
+            .await
+    }
+
+    // connects over any transport, e.g. a `LocalTransport` for a server in the same process
+    #[instrument(skip_all, fields(user = %self.user_id))]
+    pub async fn connect_with_transport(&mut self, transport: Arc<dyn Transport>) -> Result<()> {
+        self.transport = Some(transport);
+        self.negotiate_capabilities()
+            .await
+            .context("Failed to negotiate with server")?;
+
+        if self.server_supports(features::ACCOUNTS) {
+            self.create_account()
+                .await
+                .context("Failed to create account")?;
+        }
+
+        let key_package = self.legacy_key_package()?;
+        self.transport()?
+            .connect(Connect {
+                user_id: self.user_id.clone(),
+                serialized_key_package: key_package,
+            })
+            .await
+            .context("Failed to connect to server")?;
+
+        // the connect package alone won't last long, fill up the pool. replacing it is left to
+        // `key_package_rotation`, unless we've never published one:
+        if self.server_supports(features::KEY_PACKAGE_POOL) {
+            if self.manager.last_key_package_rotation == 0 {
+                self.rotate_key_packages().await
+            } else {
+                self.top_up_key_packages().await
+            }
+            .context("Failed to upload key packages")?;
+        }
+
+        Ok(())
+    }
+
+    // registers our user_id with the server, fine if it's already ours from an earlier session
+    #[instrument(skip_all, fields(user = %self.user_id))]
+    pub async fn create_account(&mut self) -> Result<()> {
+        let key_package = self.legacy_key_package()?;
+
+        let result = self
+            .transport()?
+            .create_account(CreateAccount {
+                user_id: self.user_id.clone(),
+                serialized_key_package: key_package,
+                signature_key: Some(self.manager.signature_public_key()),
+            })
+            .await;
+
+        match result {
+            // a conflict means the account already exists:
+            Err(e) if is_conflict(&e) => Ok(()),
+            result => result.context("Failed to create account"),
+        }
+    }
 
-        // Get key package with error handling
+    // servers without a key package pool get their one package with connect / create_account,
+    // the rest get the pool uploaded right after and nothing here
+    fn legacy_key_package(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.server_supports(features::KEY_PACKAGE_POOL) {
+            return Ok(None);
+        }
         let key_package = self
             .manager
             .get_key_package()
             .context("Failed to get key package")?;
+        self.manager
+            .key_packages_published(std::slice::from_ref(&key_package), true)?;
+        Ok(Some(key_package))
+    }
 
-        let response = client
-            .post(format!("{}/api/connect", server_address.clone()))
-            .json(&serde_json::json!({
-              "user_id": self.user_id.clone(),
-              "serialized_key_package": key_package
-            }))
-            .send()
+    // removes our account and everything the server holds for it,
+    // the other members of our groups will commit our removal
+    #[instrument(skip_all, fields(user = %self.user_id))]
+    pub async fn delete_account(&mut self) -> Result<()> {
+        self.transport()?
+            .delete_account(DeleteAccount {
+                user_id: self.user_id.clone(),
+                proof: self.manager.account_proof(DeleteAccount::ACTION)?,
+            })
             .await
-            .context("Failed to send connect request")?;
+            .context("Failed to delete account")
+    }
 
-        if response.status().is_success() {
-            Ok(())
-        } else {
-            Err(anyhow!(
-                "Failed to connect to server: {}",
-                response.status()
-            ))
+    pub async fn export_account(&self) -> Result<AccountExport> {
+        self.transport()?
+            .export_account(ExportAccount {
+                user_id: self.user_id.clone(),
+                proof: self.manager.account_proof(ExportAccount::ACTION)?,
+            })
+            .await
+            .context("Failed to export account")
+    }
+
+    // replaces everything we've published with a fresh pool and last resort package
+    // (the manager keeps when, with the rest of our state):
+    pub async fn rotate_key_packages(&mut self) -> Result<KeyPackageStock> {
+        self.upload_key_packages(KEY_PACKAGE_POOL_SIZE, true).await
+    }
+
+    // uploads however many one-time key packages the pool is short of
+    async fn top_up_key_packages(&mut self) -> Result<KeyPackageStock> {
+        let stock = self.upload_key_packages(0, false).await?;
+        let count = KEY_PACKAGE_POOL_SIZE.saturating_sub(stock.remaining as usize);
+        if count == 0 {
+            return Ok(stock);
         }
+        self.upload_key_packages(count, false).await
     }
 
-    pub async fn list_users(&mut self) -> Result<Vec<ConvoUser>> {
-        let address = self
-            .server_address
-            .as_ref()
-            .context("Server address is not set")?;
+    async fn rotate_key_packages_if_due(&mut self) -> Result<()> {
+        // keys of packages that were replaced or expired since the last sync:
+        self.manager.prune_key_packages()?;
 
-        let client = reqwest::Client::new();
-        let response = client
-            .get(format!("{}/api/list_users", address))
-            .send()
+        if self.key_package_rotation == 0 || !self.server_supports(features::KEY_PACKAGE_POOL) {
+            return Ok(());
+        }
+
+        let now = skychat_core::utils::current_timestamp();
+        if now.saturating_sub(self.manager.last_key_package_rotation) >= self.key_package_rotation {
+            self.rotate_key_packages().await?;
+        }
+        Ok(())
+    }
+
+    // uploads `count` fresh one-time key packages.
+    // with `rotate` they replace the ones already on the server, along with a new last resort package.
+    #[instrument(skip_all, fields(user = %self.user_id))]
+    pub async fn upload_key_packages(
+        &mut self,
+        count: usize,
+        rotate: bool,
+    ) -> Result<KeyPackageStock> {
+        let key_packages = self
+            .manager
+            .generate_key_packages(count)
+            .context("Failed to generate key packages")?;
+
+        let last_resort = if rotate {
+            Some(
+                self.manager
+                    .get_last_resort_key_package()
+                    .context("Failed to generate last resort key package")?,
+            )
+        } else {
+            None
+        };
+
+        let published: Vec<Vec<u8>> = key_packages.iter().chain(&last_resort).cloned().collect();
+        let stock = self
+            .transport()?
+            .upload_key_packages(UploadKeyPackages {
+                user_id: self.user_id.clone(),
+                key_packages,
+                last_resort,
+                replace: rotate,
+            })
             .await
-            .context("Failed to send list_users request")?;
+            .context("Failed to upload key packages")?;
 
-        let users: Vec<ConvoUser> = response
-            .json()
+        // with `rotate`, whatever we published before is on its way out:
+        self.manager.key_packages_published(&published, rotate)?;
+        Ok(stock)
+    }
+
+    pub async fn list_users(&mut self) -> Result<Vec<UserInfo>> {
+        let users = self
+            .transport()?
+            .list_users()
             .await
-            .context("Failed to parse response data")?;
+            .context("Failed to list users")?
+            .users;
 
         self.id_to_name
             .insert("system".to_string(), "system".to_string());
@@ -301,6 +749,172 @@ impl ConvoClient {
         Ok(users)
     }
 
+    // reserves our handle and sets our public profile, fails if the handle is taken
+    pub async fn register(
+        &mut self,
+        handle: String,
+        display_name: String,
+        fields: BTreeMap<String, String>,
+    ) -> Result<Profile> {
+        if !self.server_supports(features::DIRECTORY) {
+            bail!("Server does not support the user directory");
+        }
+
+        let profile = self
+            .transport()?
+            .register(Register {
+                user_id: self.user_id.clone(),
+                handle,
+                display_name,
+                fields,
+            })
+            .await
+            .context("Failed to register")?;
+
+        self.id_to_name
+            .insert(profile.user_id.clone(), profile.display_name.clone());
+        Ok(profile)
+    }
+
+    // one page of directory matches, pass `next_offset` back as `offset` for the next one
+    pub async fn search_users(
+        &mut self,
+        query: String,
+        offset: u64,
+        limit: u64,
+    ) -> Result<SearchUsersResponse> {
+        if !self.server_supports(features::DIRECTORY) {
+            bail!("Server does not support the user directory");
+        }
+
+        let results = self
+            .transport()?
+            .search_users(SearchUsers {
+                query,
+                offset,
+                limit,
+            })
+            .await
+            .context("Failed to search users")?;
+
+        for profile in &results.users {
+            self.id_to_name
+                .insert(profile.user_id.clone(), profile.display_name.clone());
+        }
+        Ok(results)
+    }
+
+    // the user registered with exactly this handle
+    pub async fn find_user(&mut self, handle: &str) -> Result<Profile> {
+        // an exact handle match always comes first:
+        self.search_users(handle.to_string(), 0, 1)
+            .await?
+            .users
+            .into_iter()
+            .find(|profile| profile.handle.eq_ignore_ascii_case(handle.trim()))
+            .context(format!("User not found with handle: {}", handle))
+    }
+
+    pub async fn get_profiles(&mut self, user_ids: Vec<String>) -> Result<Vec<Profile>> {
+        if !self.server_supports(features::DIRECTORY) {
+            bail!("Server does not support the user directory");
+        }
+
+        let users = self
+            .transport()?
+            .get_profiles(GetProfiles { user_ids })
+            .await
+            .context("Failed to get profiles")?
+            .users;
+
+        for profile in &users {
+            self.id_to_name
+                .insert(profile.user_id.clone(), profile.display_name.clone());
+        }
+        Ok(users)
+    }
+
+    // looks up the display names of senders / members we haven't seen before
+    async fn resolve_unknown_names(&mut self) -> Result<()> {
+        if !self.server_supports(features::DIRECTORY) {
+            return Ok(());
+        }
+
+        let mut unknown = HashSet::new();
+        for (group_id, group) in &self.manager.groups {
+            let members = self
+                .manager
+                .group_get_member_ids(group_id)
+                .unwrap_or_default();
+            let senders = group
+                .decrypted
+                .iter()
+                .map(|message| message.sender_id.clone());
+            unknown.extend(
+                members
+                    .into_iter()
+                    .chain(senders)
+                    .filter(|user_id| !self.id_to_name.contains_key(user_id)),
+            );
+        }
+
+        if !unknown.is_empty() {
+            self.get_profiles(unknown.into_iter().collect()).await?;
+        }
+        Ok(())
+    }
+
+    // who to show a message as: their display name if we know it, otherwise their user_id
+    pub fn display_name(&self, user_id: &str) -> String {
+        self.id_to_name
+            .get(user_id)
+            .cloned()
+            .unwrap_or_else(|| user_id.to_string())
+    }
+
+    // e.g. away when backgrounded, offline when logging out (any other request sets us online again)
+    pub async fn set_presence(&self, status: PresenceStatus) -> Result<()> {
+        if !self.server_supports(features::PRESENCE) {
+            bail!("Server does not support presence");
+        }
+
+        self.transport()?
+            .set_presence(SetPresence {
+                user_id: self.user_id.clone(),
+                status,
+            })
+            .await
+            .context("Failed to set presence")
+    }
+
+    // users the server has never seen are left out
+    pub async fn get_presence(&self, user_ids: Vec<String>) -> Result<Vec<Presence>> {
+        if !self.server_supports(features::PRESENCE) {
+            bail!("Server does not support presence");
+        }
+
+        Ok(self
+            .transport()?
+            .get_presence(GetPresence { user_ids })
+            .await
+            .context("Failed to get presence")?
+            .presence)
+    }
+
+    // subscribes to the server's push events (presence changes etc.), we count as online for as
+    // long as it's open
+    pub async fn open_event_stream(&self) -> Result<EventStream> {
+        if !self.server_supports(features::PUSH) {
+            bail!("Server does not support push events");
+        }
+
+        let proof = self.manager.account_proof(OpenEvents::ACTION)?;
+        self.transport()?
+            .events(OpenEvents::new(self.user_id.clone(), Some(proof)))
+            .await
+            .context("Failed to open event stream")
+    }
+
     pub async fn process_invite(&mut self, invite: ConvoInvite) -> Result<GroupId> {
         // add the welcome_message to the manager
         let group_id = self
@@ -309,25 +923,13 @@ impl ConvoClient {
             .context("Failed to process invite")?;
 
         // let the server know we have successfully processed the invite:
-        let address = self
-            .server_address
-            .as_ref()
-            .context("Server address is not set")?;
-
-        let client = reqwest::Client::new();
-        let response = client
-            .post(format!("{}/api/accept_invite", address))
-            .json(&serde_json::json!({
-              "group_id": group_id.clone(),
-              "sender_id": self.user_id.clone(),
-            }))
-            .send()
+        self.transport()?
+            .accept_invite(AcceptInvite {
+                group_id: group_id.clone(),
+                sender_id: self.user_id.clone(),
+            })
             .await
-            .context("Failed to send accept_invite request")?;
-
-        if !response.status().is_success() {
-            bail!("Failed to accept invite: {}", response.status());
-        }
+            .context("Failed to accept invite")?;
 
         Ok(group_id)
     }
@@ -341,39 +943,36 @@ impl ConvoClient {
         Ok(())
     }
 
+    // what changed comes out of `sync_events`, same as for a sync
     pub async fn check_incoming_messages(
         &mut self,
         group_id: Option<&GroupId>,
     ) -> Result<Vec<ConvoMessage>> {
-        let address = self
-            .server_address
-            .as_ref()
-            .context("Server address is not set")?;
+        let before = self.manager.snapshot();
+        let result = self.get_new_messages(group_id).await;
+        self.emit_changes(&before, result.as_ref().err());
+        result
+    }
 
+    async fn get_new_messages(&mut self, group_id: Option<&GroupId>) -> Result<Vec<ConvoMessage>> {
         let mut index = 0;
         if let Some(group_id) = group_id {
             if let Some(group) = self.manager.groups.get(group_id) {
-                index = group.global_index;
+                // refetches any gaps, already applied messages after them are skipped:
+                index = group.applied.contiguous();
             }
         }
 
-        let client = reqwest::Client::new();
-        let response = client
-            .post(format!("{}/api/get_new_messages", address))
-            .json(&serde_json::json!({
-              "group_id": group_id,
-              "sender_id": self.user_id.clone(),
-              "index": index,
-            }))
-            .send()
-            .await
-            .context("Failed to send get_new_messages request")?;
-
-        // should be a Vec<ConvoMessage>
-        let messages: Vec<ConvoMessage> = response
-            .json()
+        let messages = self
+            .transport()?
+            .get_new_messages(GetMessages {
+                group_id: group_id.cloned(),
+                sender_id: self.user_id.clone(),
+                index,
+            })
             .await
-            .context("Failed to parse response data")?;
+            .context("Failed to get new messages")?
+            .messages;
 
         // // exclude any messages from our own user_id:
         // let messages: Vec<ConvoMessage> = messages
@@ -381,114 +980,536 @@ impl ConvoClient {
         //     .filter(|message| message.sender_id != self.user_id)
         //     .collect();
 
+        self.claim_delivered(&messages);
         self.manager
-            .process_convo_messages(messages.clone(), group_id)
+            .process_convo_messages(messages.clone())
             .context("Failed to process messages")?;
 
+        // names are only cosmetic, a failed lookup is retried next time:
+        self.resolve_unknown_names().await.ok();
+
         Ok(messages)
     }
 
     pub async fn get_group_index(&mut self, group_id: &GroupId) -> Result<u64> {
-        let address = self
-            .server_address
-            .as_ref()
-            .context("Server address is not set")?;
-
-        let client = reqwest::Client::new();
-        let response = client
-            .post(format!("{}/api/group_index", address))
-            .json(&serde_json::json!({
-              "group_id": group_id.clone(),
-              "sender_id": self.user_id.clone(),
-            }))
-            .send()
-            .await
-            .context("Failed to send group_index request")?;
-
-        let group_index: u64 = response
-            .json()
+        let group_index = self
+            .transport()?
+            .group_index(GetGroupIndex {
+                group_id: group_id.clone(),
+                sender_id: self.user_id.clone(),
+            })
             .await
-            .context("Failed to parse response data")?;
+            .context("Failed to get group index")?
+            .global_index;
 
         Ok(group_index)
     }
 
+    // catch up on every group we're in, plus our mailbox:
+    pub async fn sync(&mut self) -> Result<()> {
+        let group_ids: Vec<GroupId> = self.manager.groups.keys().cloned().collect();
+        self.sync_groups(&group_ids).await
+    }
+
     pub async fn sync_group(&mut self, group_id: &GroupId) -> Result<()> {
-        // get and process any incoming messages:
-        let _messages = self.check_incoming_messages(Some(group_id)).await?;
+        self.sync_groups(std::slice::from_ref(group_id)).await
+    }
 
-        let group_index = self.get_group_index(group_id).await?;
+    // catches up on the given groups, then sends whatever is waiting in their outbox
+    pub async fn sync_groups(&mut self, group_ids: &[GroupId]) -> Result<()> {
+        self.fetch_groups(group_ids).await?;
+        self.flush_groups(group_ids).await;
+        Ok(())
+    }
 
-        // set the group_index to the group_index:
-        let group = self
-            .manager
-            .groups
-            .get_mut(group_id)
-            .context(format!("Group not found for ID: {:?}", group_id))?;
+    // starts deciding in the background when to sync (see sync.rs), replacing any earlier
+    // schedule. the app then loops over `sync_when_due` (or calls `sync_if_due` from a loop of
+    // its own) and reads what changed from `sync_events`. `start_background_sync` does the
+    // syncing too
+    pub fn start_sync(&mut self, config: SyncConfig) -> Result<()> {
+        let transport = Arc::clone(self.transport.as_ref().context("Not connected to a server")?);
+        let push =
+            self.server_supports(features::PUSH) && self.server_supports(features::GROUP_EVENTS);
+        self.sync_engine = Some(SyncEngine::start(transport, self.user_id.clone(), push, config));
+        Ok(())
+    }
+
+    // starts the schedule like `start_sync`, and a task that runs the syncs as they come due,
+    // locking the client for each one. the app only listens to `sync_events`
+    pub async fn start_background_sync(
+        client: &SharedClient,
+        config: SyncConfig,
+    ) -> Result<SyncHandle> {
+        let mut locked = client.lock().await;
+        locked.start_sync(config)?;
+        let engine = locked.sync_engine.as_mut().context("Background sync isn't running")?;
+        Ok(engine.run(client))
+    }
 
-        if group_index != group.global_index {
-            group.global_index = group_index;
+    pub fn stop_sync(&mut self) {
+        self.sync_engine = None;
+    }
+
+    // makes the next sync due right away, e.g. when the app comes back to the foreground
+    pub fn sync_soon(&self) {
+        if let Some(engine) = &self.sync_engine {
+            engine.sync_now();
         }
+    }
 
+    // waits until a sync is due, then syncs. failures come out of `sync_events`
+    pub async fn sync_when_due(&mut self) -> Result<()> {
+        self.sync_engine
+            .as_ref()
+            .context("Background sync isn't running")?
+            .wait_due()
+            .await;
+        self.scheduled_sync().await;
         Ok(())
     }
 
-    pub async fn send_message(&mut self, group_id: &GroupId, text: String) -> Result<()> {
-        // we must always sync the group before sending a message:
-        self.sync_group(group_id).await?;
+    // syncs if one is due, without waiting otherwise. returns whether it did
+    pub async fn sync_if_due(&mut self) -> bool {
+        match &self.sync_engine {
+            Some(engine) if engine.take_due() => {
+                self.scheduled_sync().await;
+                true
+            }
+            _ => false,
+        }
+    }
 
-        let msg = self
-            .manager
-            .create_message(group_id, text.clone())
-            .context("Failed to create message")?;
+    pub(crate) async fn scheduled_sync(&mut self) {
+        let mut events = self.sync_events();
+        self.sync().await.ok();
+        // e.g. messages queued for groups we're no longer in:
+        self.flush_outbox().await;
+
+        let mut active = false;
+        loop {
+            match events.try_recv() {
+                Ok(SyncEvent::Delivery { .. } | SyncEvent::SyncError { .. }) => {}
+                Ok(_) | Err(TryRecvError::Lagged(_)) => active = true,
+                Err(_) => break,
+            }
+        }
+        if active {
+            if let Some(engine) = &self.sync_engine {
+                engine.report_activity();
+            }
+        }
+    }
 
-        let mut group = self
-            .manager
-            .groups
-            .get_mut(group_id)
-            .context(format!("Group not found for ID: {:?}", group_id))?;
+    // one /sync round trip for all of the given groups (and the mailbox),
+    // falling back to a get_new_messages + group_index pair per group on older servers.
+    // whatever changed comes out of `sync_events`, and so does a failure
+    async fn fetch_groups(&mut self, group_ids: &[GroupId]) -> Result<()> {
+        let before = self.manager.snapshot();
+        let result = self.fetch_groups_inner(group_ids).await;
+        self.emit_changes(&before, result.as_ref().err());
+        result
+    }
 
-        let address = self
-            .server_address
-            .as_ref()
-            .context("Server address is not set")?;
-
-        let client = reqwest::Client::new();
-        let response = client
-            .post(format!("{}/api/send_message", address))
-            .json(&serde_json::json!({
-              "group_id": group_id,
-              "sender_id": self.user_id.clone(),
-              "message": msg.clone(),
-              "global_index": group.global_index + 1,
-            }))
-            .send()
-            .await
-            .context("Failed to send message request")?;
-
-        let timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .context("Failed to get current timestamp")?
-            .as_millis() as u64;
-
-        if response.status().is_success() {
-            // add this message to our own message list:
-            // increment the global_index of the group:
-            group.global_index += 1;
-            group.decrypted.push(MessageItem {
-                text: text.clone(),
-                sender_id: self.user_id.clone(),
-                timestamp,
+    fn emit_changes(&self, before: &Snapshot, error: Option<&anyhow::Error>) {
+        for event in self.manager.changes_since(before) {
+            self.emit(event);
+        }
+        if let Some(e) = error {
+            self.emit(SyncEvent::SyncError {
+                error: format!("{:#}", e),
             });
-            Ok(())
-        } else {
-            group.decrypted.push(MessageItem {
-                text: "<message_failed to send!>".to_string(),
+        }
+    }
+
+    #[instrument(skip_all, fields(user = %self.user_id, groups = group_ids.len()))]
+    async fn fetch_groups_inner(&mut self, group_ids: &[GroupId]) -> Result<()> {
+        if !self.server_supports(features::SYNC) {
+            for group_id in group_ids {
+                self.sync_group_legacy(group_id).await?;
+            }
+            // still pick up invites when we aren't in any groups yet:
+            if group_ids.is_empty() {
+                self.get_new_messages(None).await?;
+            }
+            return Ok(());
+        }
+
+        let mut indices = self.manager.group_indices();
+        indices.retain(|group_id, _| group_ids.contains(group_id));
+
+        let response = self
+            .transport()?
+            .sync(Sync {
                 sender_id: self.user_id.clone(),
-                timestamp,
-            });
-            Err(anyhow!("Failed to send message: {}", response.status()))
+                groups: indices,
+            })
+            .await
+            .context("Failed to sync")?;
+        let key_packages_low = response.key_packages_low;
+        let pending_removals: Vec<(GroupId, Vec<String>)> = response
+            .groups
+            .iter()
+            .filter(|(_, group)| !group.pending_removals.is_empty())
+            .map(|(group_id, group)| (group_id.clone(), group.pending_removals.clone()))
+            .collect();
+        let rejoin_requests: Vec<(GroupId, RejoinRequest)> = response
+            .groups
+            .iter()
+            .flat_map(|(group_id, group)| {
+                group
+                    .rejoin_requests
+                    .iter()
+                    .filter(|request| request.user_id != self.user_id)
+                    .map(move |request| (group_id.clone(), request.clone()))
+            })
+            .collect();
+
+        for group in response.groups.values() {
+            self.claim_delivered(&group.messages);
+        }
+        self.manager
+            .process_sync_response(response)
+            .context("Failed to process sync response")?;
+
+        // the sync itself is done, housekeeping that fails is tried again on the next one (and
+        // mustn't fail whatever we're syncing for, e.g. delivering a message):
+        self.commit_pending_removals(pending_removals).await;
+        self.answer_rejoin_requests(rejoin_requests).await;
+
+        // the server says our one-time key packages are running out, top them back up:
+        if let Some(remaining) = key_packages_low {
+            let count = KEY_PACKAGE_POOL_SIZE.saturating_sub(remaining as usize);
+            if let Err(e) = self.upload_key_packages(count, false).await {
+                warn!("failed to replenish key packages: {:#}", e);
+            }
+        }
+
+        if let Err(e) = self.rotate_key_packages_if_due().await {
+            warn!("failed to rotate key packages: {:#}", e);
         }
+
+        // names are only cosmetic, a failed lookup is retried next time:
+        self.resolve_unknown_names().await.ok();
+
+        Ok(())
+    }
+
+    // deleted accounts stay in a group's MLS tree until a member commits their removal.
+    // the remaining member with the lowest user_id does it, so only one commit goes out
+    async fn commit_pending_removals(&mut self, pending: Vec<(GroupId, Vec<String>)>) {
+        for (group_id, user_ids) in pending {
+            let Ok(members) = self.manager.group_get_member_ids(&group_id) else {
+                continue;
+            };
+
+            let remover = members.iter().filter(|id| !user_ids.contains(id)).min();
+            if remover != Some(&self.user_id) {
+                continue;
+            }
+
+            // we just synced, if someone else commits first it's tried again on the next sync:
+            for user_id in user_ids {
+                if !members.contains(&user_id) {
+                    continue;
+                }
+                match self.commit_removal(&user_id, &group_id).await {
+                    Ok(_) => {}
+                    Err(e) if is_stale_epoch(&e) => {
+                        debug!(group = %ShortId(&group_id), user_id, "someone else committed first");
+                        break;
+                    }
+                    Err(e) => {
+                        warn!(group = %ShortId(&group_id), user_id, "failed to remove deleted account: {:#}", e);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    // any member can answer a rejoin request, the first commit to reach the server wins and
+    // everyone else finds the epoch moved on. a request we can't answer (e.g. a bad key package)
+    // is left to the other members, it mustn't hold up our sync
+    async fn answer_rejoin_requests(&mut self, requests: Vec<(GroupId, RejoinRequest)>) {
+        for (group_id, request) in requests {
+            if !self.manager.groups.contains_key(&group_id) {
+                continue;
+            }
+            let user_id = request.user_id.clone();
+            match self.commit_readd(&group_id, request).await {
+                Ok(()) => debug!(group = %ShortId(&group_id), user_id, "re-added member"),
+                Err(e) if is_stale_epoch(&e) || is_not_found(&e) => {
+                    debug!(group = %ShortId(&group_id), user_id, "rejoin request already answered")
+                }
+                Err(e) => warn!(group = %ShortId(&group_id), user_id, "failed to re-add member: {:#}", e),
+            }
+        }
+    }
+
+    async fn sync_group_legacy(&mut self, group_id: &GroupId) -> Result<()> {
+        // read the index first, everything up to it is in the messages that follow, so whatever
+        // up to it the server doesn't send is a hole:
+        let group_index = self.get_group_index(group_id).await?;
+
+        let index = self.manager.group_indices().get(group_id).copied().unwrap_or(0);
+        let messages = self
+            .transport()?
+            .get_new_messages(GetMessages {
+                group_id: Some(group_id.clone()),
+                sender_id: self.user_id.clone(),
+                index,
+            })
+            .await
+            .context("Failed to get new messages")?
+            .messages;
+
+        self.claim_delivered(&messages);
+        self.manager
+            .process_group_messages(group_id, messages, group_index)
+            .context("Failed to process messages")?;
+
+        // names are only cosmetic, a failed lookup is retried next time:
+        self.resolve_unknown_names().await.ok();
+
+        Ok(())
+    }
+
+    // echoes the message into the group, queues it and tries to deliver it (and anything queued
+    // before it) right away. returns its outbox id, which is the message item's `local_id`: its
+    // state goes from composing to sent, or to queued (server unreachable, ...) or failed
+    // (e.g. we were removed) and stays in `self.outbox` until it's sent
+    #[instrument(skip_all, fields(user = %self.user_id, group = %ShortId(group_id)))]
+    pub async fn send_message(&mut self, group_id: &GroupId, text: String) -> Result<u64> {
+        if !self.manager.groups.contains_key(group_id) {
+            bail!("Group not found for ID: {:?}", group_id);
+        }
+
+        let created_at = current_timestamp_ms();
+        let id = self.outbox.push(group_id.clone(), text.clone(), created_at);
+        self.manager
+            .group_push_own_message(group_id, text, id, created_at, DeliveryState::Composing)?;
+        self.notify_delivery(group_id, id, DeliveryState::Composing);
+
+        self.flush_groups(std::slice::from_ref(group_id)).await;
+        Ok(id)
+    }
+
+    // gives a failed message another round of attempts
+    pub async fn retry_message(&mut self, id: u64) -> Result<()> {
+        if !self.outbox.requeue(id) {
+            bail!("No failed message with ID {}", id);
+        }
+        let group_id = self.outbox.get(id).context("Message is no longer queued")?.group_id.clone();
+        self.set_delivery_state(id, DeliveryState::Queued);
+
+        self.flush_groups(std::slice::from_ref(&group_id)).await;
+        Ok(())
+    }
+
+    // updates the message item of an outbox entry (echoing it again if it went missing, e.g.
+    // state saved by an older build) and lets `sync_events` receivers know
+    fn set_delivery_state(&mut self, id: u64, state: DeliveryState) {
+        let Some(entry) = self.outbox.get(id) else {
+            return;
+        };
+        let group_id = entry.group_id.clone();
+        let found = self
+            .manager
+            .group_set_message_state(&group_id, id, state.clone())
+            .unwrap_or(true); // no group, nothing to show it in
+        if !found {
+            let (text, created_at) = (entry.text.clone(), entry.created_at);
+            self.manager
+                .group_push_own_message(&group_id, text, id, created_at, state.clone())
+                .ok();
+        }
+        self.notify_delivery(&group_id, id, state);
+    }
+
+    fn notify_delivery(&self, group_id: &GroupId, id: u64, state: DeliveryState) {
+        self.emit(SyncEvent::Delivery {
+            group_id: group_id.clone(),
+            local_id: id,
+            state,
+        });
+    }
+
+    // sends every outbox message that's due, oldest first in each group.
+    // failures don't come back from here, they're in each entry's status
+    pub async fn flush_outbox(&mut self) {
+        let group_ids = self.outbox.pending_groups();
+        self.flush_groups(&group_ids).await;
+    }
+
+    async fn flush_groups(&mut self, group_ids: &[GroupId]) {
+        for group_id in group_ids {
+            let now = skychat_core::utils::current_timestamp();
+            while let Some(id) = self.outbox.next_due(group_id, now) {
+                match self.deliver(id).await {
+                    Delivery::Sent => {}
+                    Delivery::Retry { reason, retry_after } => {
+                        warn!(id, "message not sent, retrying later: {}", reason);
+                        // whatever comes after it in this group waits until it's due again:
+                        self.outbox.retry_later(id, reason, retry_after, now);
+                        let state = match self.outbox.get(id).map(|entry| &entry.status) {
+                            Some(OutboxStatus::Failed(reason)) => DeliveryState::Failed {
+                                reason: reason.clone(),
+                            },
+                            _ => DeliveryState::Queued,
+                        };
+                        self.set_delivery_state(id, state);
+                    }
+                    Delivery::Failed(reason) => {
+                        warn!(id, "message failed: {}", reason);
+                        self.outbox.fail(id, reason.clone());
+                        self.set_delivery_state(id, DeliveryState::Failed { reason });
+                    }
+                }
+            }
+        }
+    }
+
+    // one attempt at an outbox message: sync, encrypt (unless we still have a ciphertext from
+    // this epoch) and post at the next index. losing the index to another member means
+    // syncing and trying again, a few times before backing off
+    async fn deliver(&mut self, id: u64) -> Delivery {
+        let Some(entry) = self.outbox.get(id).cloned() else {
+            return Delivery::Sent;
+        };
+        let group_id = entry.group_id;
+        if !self.manager.groups.contains_key(&group_id) {
+            return Delivery::Failed("No longer in this group".to_string());
+        }
+
+        let mut conflict = None;
+        for _ in 0..=MAX_CONFLICT_RETRIES {
+            if let Err(e) = self.fetch_groups(std::slice::from_ref(&group_id)).await {
+                return Delivery::from_error(e);
+            }
+            // an earlier attempt we never got an answer for made it after all:
+            if self.outbox.get(id).is_none() {
+                return Delivery::Sent;
+            }
+
+            let encrypted = match self.encrypt_for_outbox(id, &group_id) {
+                Ok(encrypted) => encrypted,
+                Err(e) => return Delivery::Failed(format!("{:#}", e)),
+            };
+            let Ok(global_index) = self.manager.group_get_index(&group_id) else {
+                return Delivery::Failed("No longer in this group".to_string());
+            };
+            let global_index = global_index + 1;
+
+            let result = match self.transport() {
+                Ok(transport) => {
+                    transport
+                        .send_message(SendMessage {
+                            group_id: group_id.clone(),
+                            sender_id: self.user_id.clone(),
+                            message: encrypted,
+                            global_index,
+                        })
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok(()) => {
+                    self.mark_sent(id, global_index);
+                    return Delivery::Sent;
+                }
+                // someone else got the index first:
+                Err(e) if is_conflict(&e) => {
+                    debug!(id, global_index, "lost the index to another message, resyncing");
+                    conflict = Some(e);
+                }
+                Err(e) => return Delivery::from_error(e),
+            }
+        }
+
+        Delivery::Retry {
+            reason: conflict.map_or_else(String::new, |e| format!("{:#}", e)),
+            retry_after: 0,
+        }
+    }
+
+    // the server took an outbox message, it goes where the server put it, after whatever came
+    // in meanwhile
+    fn mark_sent(&mut self, id: u64, global_index: u64) {
+        let Some(entry) = self.outbox.remove(id) else {
+            return;
+        };
+        let state = DeliveryState::Sent { global_index };
+        if let Some(group) = self.manager.groups.get_mut(&entry.group_id) {
+            group.mark_applied(global_index);
+            let position = group
+                .decrypted
+                .iter()
+                .position(|item| item.local_id == Some(id));
+            let mut item = match position {
+                Some(position) => group.decrypted.remove(position),
+                None => MessageItem {
+                    text: entry.text,
+                    sender_id: self.user_id.clone(),
+                    timestamp: entry.created_at,
+                    state: state.clone(),
+                    local_id: Some(id),
+                },
+            };
+            item.state = state.clone();
+            group.decrypted.push(item);
+        }
+        self.notify_delivery(&entry.group_id, id, state);
+    }
+
+    // an attempt whose answer was lost (timeout, dropped connection, ...) may still have been
+    // stored. if its ciphertext is in the fetched messages it was, and it's marked sent at that
+    // index instead of going out a second time. call before the manager processes them, so
+    // they're skipped as already applied
+    fn claim_delivered(&mut self, messages: &[ConvoMessage]) {
+        for message in messages {
+            let Some(encrypted) = &message.encrypted else {
+                continue;
+            };
+            let delivered = self
+                .outbox
+                .entries()
+                .iter()
+                .find(|entry| {
+                    entry
+                        .encrypted
+                        .as_ref()
+                        .is_some_and(|cached| &cached.message == encrypted)
+                })
+                .map(|entry| entry.id);
+            if let Some(id) = delivered {
+                debug!(id, index = message.global_index, "an earlier attempt was delivered");
+                self.mark_sent(id, message.global_index);
+            }
+        }
+    }
+
+    // the entry's ciphertext for the group's current epoch, encrypting it if there isn't one yet
+    fn encrypt_for_outbox(&mut self, id: u64, group_id: &GroupId) -> Result<Vec<u8>> {
+        let epoch = self.manager.group_get_epoch(group_id)?.as_u64();
+        let entry = self.outbox.get_mut(id).context("Message is no longer queued")?;
+
+        if let Some(encrypted) = &entry.encrypted {
+            if encrypted.epoch == epoch {
+                return Ok(encrypted.message.clone());
+            }
+        }
+
+        let message = self
+            .manager
+            .create_message(group_id, entry.text.clone())
+            .context("Failed to create message")?;
+        entry.encrypted = Some(Encrypted {
+            epoch,
+            message: message.clone(),
+        });
+        Ok(message)
     }
 
     pub fn get_group_messages(&self, group_id: &GroupId) -> Result<&Vec<MessageItem>> {
@@ -524,12 +1545,15 @@ impl ConvoClient {
         let mut display_messages = Vec::new();
 
         for message in messages {
-            let sender_name = self
-                .id_to_name
-                .get(&message.sender_id)
-                .context(format!("Sender not found: {}", message.sender_id))?;
+            let sender_name = self.display_name(&message.sender_id);
 
-            display_messages.push(format!("{}: {}", sender_name, message.text));
+            let status = match &message.state {
+                DeliveryState::Composing | DeliveryState::Queued => " (sending...)".to_string(),
+                DeliveryState::Failed { reason } => format!(" (not sent: {})", reason),
+                DeliveryState::Sent { .. } | DeliveryState::Received => String::new(),
+            };
+
+            display_messages.push(format!("{}: {}{}", sender_name, message.text, status));
         }
 
         Ok(display_messages)
@@ -558,14 +1582,11 @@ impl ConvoClient {
         let mut display_messages = Vec::new();
 
         for message in messages {
-            let sender_name = self
-                .id_to_name
-                .get(&message.sender_id)
-                .context(format!("Sender not found: {}", message.sender_id))?;
+            let sender_name = self.display_name(&message.sender_id);
 
             let color = sender_colors
                 .get(&message.sender_id)
-                .context(format!("Color not found for sender: {}", message.sender_id))?;
+                .unwrap_or(&Color::White);
 
             display_messages.push(format!(
                 "{}: {}",
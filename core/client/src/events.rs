@@ -0,0 +1,93 @@
+// src/events.rs
+
+// reads the server's push events, either off its /events stream (server-sent events)
+// or straight from an in-process server's channel.
+
+use anyhow::{Context, Result};
+use skychat_protocol::PushEvent;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+pub struct EventStream {
+    source: Source,
+}
+
+enum Source {
+    Http {
+        response: reqwest::Response,
+        buffer: String,
+    },
+    Channel {
+        receiver: broadcast::Receiver<PushEvent>,
+        // what the subscriber gets to see, like the server's /events does per user:
+        visible: Box<dyn Fn(&PushEvent) -> bool + Send + Sync>,
+    },
+}
+
+impl EventStream {
+    pub fn new(response: reqwest::Response) -> Self {
+        Self {
+            source: Source::Http {
+                response,
+                buffer: String::new(),
+            },
+        }
+    }
+
+    pub fn from_channel(
+        receiver: broadcast::Receiver<PushEvent>,
+        visible: impl Fn(&PushEvent) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            source: Source::Channel {
+                receiver,
+                visible: Box::new(visible),
+            },
+        }
+    }
+
+    // waits for the next event, None once the server closes the stream.
+    // events that fail to parse (e.g. kinds this build doesn't know yet) are skipped
+    pub async fn next_event(&mut self) -> Result<Option<PushEvent>> {
+        let (response, buffer) = match &mut self.source {
+            Source::Http { response, buffer } => (response, buffer),
+            Source::Channel { receiver, visible } => loop {
+                match receiver.recv().await {
+                    Ok(event) if visible(&event) => return Ok(Some(event)),
+                    Ok(_) => continue,
+                    // same as over http, a slow reader just misses some:
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return Ok(None),
+                }
+            },
+        };
+
+        loop {
+            // events are separated by a blank line:
+            while let Some(end) = buffer.find("\n\n") {
+                let block: String = buffer.drain(..end + 2).collect();
+                let data = block
+                    .lines()
+                    .filter_map(|line| line.strip_prefix("data:"))
+                    .map(str::trim_start)
+                    .collect::<Vec<_>>()
+                    .join("\n");
+
+                if let Ok(event) = serde_json::from_str::<PushEvent>(&data) {
+                    return Ok(Some(event));
+                }
+            }
+
+            let chunk = response
+                .chunk()
+                .await
+                .context("Failed to read event stream")?;
+
+            match chunk {
+                Some(chunk) => {
+                    buffer.push_str(&String::from_utf8_lossy(&chunk).replace("\r\n", "\n"))
+                }
+                None => return Ok(None),
+            }
+        }
+    }
+}
@@ -1 +1,6 @@
 pub mod client;
+pub mod events;
+pub mod local;
+pub mod outbox;
+pub mod sync;
+pub mod transport;
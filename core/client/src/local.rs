@@ -0,0 +1,1382 @@
+// src/local.rs
+
+// a `Transport` that calls a `ConvoServer` in the same process, no http involved.
+// refusals come back as the same `RequestFailed` the server would have sent over the wire,
+// so clients behave the way they would against a real server. handy for tests and examples
+// where several clients share one server.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use skychat_protocol::{
+    AcceptInvite, AccountExport, Capabilities, Connect, CreateAccount, CreateGroup, DeleteAccount,
+    ExportAccount, GetGroupIndex, GetMessages, GetPresence, GetProfiles, GetUserKeys,
+    GroupIndexResponse, InviteUser, KeyPackageStock, MessagesResponse, OpenEvents,
+    PresenceResponse, Profile, ProfilesResponse, ReaddUser, Register, RemoveUser, RequestRejoin,
+    SearchUsers, SearchUsersResponse, SendMessage, SetPresence, Sync as SyncRequest, SyncResponse,
+    UploadKeyPackages, UserKeysResponse, UsersResponse,
+};
+use skychat_server::error;
+use skychat_server::server::ConvoServer;
+
+use crate::events::EventStream;
+use crate::transport::{RequestFailed, Transport};
+
+pub struct LocalTransport {
+    server: Arc<ConvoServer>,
+}
+
+impl LocalTransport {
+    pub fn new(server: Arc<ConvoServer>) -> Self {
+        Self { server }
+    }
+
+    pub fn server(&self) -> &Arc<ConvoServer> {
+        &self.server
+    }
+}
+
+// the error as the client would have received it
+fn refused(e: anyhow::Error) -> anyhow::Error {
+    let (status, error) = error::api_error(&e);
+    RequestFailed {
+        status,
+        error: Some(error),
+    }
+    .into()
+}
+
+#[async_trait]
+impl Transport for LocalTransport {
+    async fn capabilities(&self) -> Result<Option<Capabilities>> {
+        Ok(Some(skychat_server::server::capabilities()))
+    }
+
+    async fn create_account(&self, request: CreateAccount) -> Result<()> {
+        self.server
+            .client_create_account(
+                request.user_id,
+                request.serialized_key_package,
+                request.signature_key,
+            )
+            .map_err(refused)
+    }
+
+    async fn delete_account(&self, request: DeleteAccount) -> Result<()> {
+        self.server
+            .client_delete_account(request.user_id, request.proof)
+            .map_err(refused)
+    }
+
+    async fn export_account(&self, request: ExportAccount) -> Result<AccountExport> {
+        self.server
+            .client_export_account(request.user_id, request.proof)
+            .map_err(refused)
+    }
+
+    async fn connect(&self, request: Connect) -> Result<()> {
+        self.server
+            .client_connect(request.user_id, request.serialized_key_package)
+            .map_err(refused)
+    }
+
+    async fn upload_key_packages(&self, request: UploadKeyPackages) -> Result<KeyPackageStock> {
+        self.server
+            .client_upload_key_packages(
+                request.user_id,
+                request.key_packages,
+                request.last_resort,
+                request.replace,
+            )
+            .map_err(refused)
+    }
+
+    async fn list_users(&self) -> Result<UsersResponse> {
+        Ok(UsersResponse {
+            users: self.server.client_list_users(),
+        })
+    }
+
+    async fn register(&self, request: Register) -> Result<Profile> {
+        self.server
+            .client_register(
+                request.user_id,
+                request.handle,
+                request.display_name,
+                request.fields,
+            )
+            .map_err(refused)
+    }
+
+    async fn search_users(&self, request: SearchUsers) -> Result<SearchUsersResponse> {
+        Ok(self
+            .server
+            .client_search_users(request.query, request.offset, request.limit))
+    }
+
+    async fn get_profiles(&self, request: GetProfiles) -> Result<ProfilesResponse> {
+        Ok(ProfilesResponse {
+            users: self.server.client_get_profiles(request.user_ids),
+        })
+    }
+
+    async fn set_presence(&self, request: SetPresence) -> Result<()> {
+        self.server
+            .client_set_presence(request.user_id, request.status)
+            .map_err(refused)
+    }
+
+    async fn get_presence(&self, request: GetPresence) -> Result<PresenceResponse> {
+        Ok(PresenceResponse {
+            presence: self.server.client_get_presence(request.user_ids),
+        })
+    }
+
+    async fn events(&self, request: OpenEvents) -> Result<EventStream> {
+        let receiver = self.server.client_open_events(&request).map_err(refused)?;
+        let server = Arc::clone(&self.server);
+        Ok(EventStream::from_channel(receiver, move |event| {
+            server.event_visible_to(event, &request.user_id)
+        }))
+    }
+
+    async fn create_group(&self, request: CreateGroup) -> Result<()> {
+        self.server
+            .client_create_group(request.group_id, request.group_name, request.sender_id)
+            .map_err(refused)
+    }
+
+    async fn get_user_keys(&self, request: GetUserKeys) -> Result<UserKeysResponse> {
+        let key_packages = self
+            .server
+            .client_get_user_keys(request.user_ids, request.sender_id)
+            .map_err(refused)?;
+        Ok(UserKeysResponse { key_packages })
+    }
+
+    async fn invite_user(&self, request: InviteUser) -> Result<()> {
+        self.server
+            .client_invite_user(
+                request.group_id,
+                request.sender_id,
+                request.receiver_id,
+                request.welcome_message,
+                request.ratchet_tree,
+                request.fanned,
+            )
+            .map_err(refused)
+    }
+
+    async fn request_rejoin(&self, request: RequestRejoin) -> Result<()> {
+        self.server
+            .client_request_rejoin(request.group_id, request.sender_id, request.key_package)
+            .map_err(refused)
+    }
+
+    async fn readd_user(&self, request: ReaddUser) -> Result<()> {
+        self.server
+            .client_readd_user(
+                request.group_id,
+                request.sender_id,
+                request.user_id,
+                request.welcome_message,
+                request.fanned,
+            )
+            .map_err(refused)
+    }
+
+    async fn remove_user(&self, request: RemoveUser) -> Result<()> {
+        self.server
+            .client_remove_user(
+                request.group_id,
+                request.sender_id,
+                request.user_id,
+                request.fanned,
+            )
+            .map_err(refused)
+    }
+
+    async fn accept_invite(&self, request: AcceptInvite) -> Result<()> {
+        self.server
+            .client_accept_invite(request.group_id, request.sender_id)
+            .map_err(refused)
+    }
+
+    async fn get_new_messages(&self, request: GetMessages) -> Result<MessagesResponse> {
+        let messages = self
+            .server
+            .client_get_new_messages(request.group_id, request.sender_id, request.index)
+            .map_err(refused)?;
+        Ok(MessagesResponse { messages })
+    }
+
+    async fn group_index(&self, request: GetGroupIndex) -> Result<GroupIndexResponse> {
+        let global_index = self
+            .server
+            .client_get_group_index(request.group_id, request.sender_id)
+            .map_err(refused)?;
+        Ok(GroupIndexResponse { global_index })
+    }
+
+    async fn sync(&self, request: SyncRequest) -> Result<SyncResponse> {
+        self.server
+            .client_sync(request.sender_id, request.groups)
+            .map_err(refused)
+    }
+
+    async fn send_message(&self, request: SendMessage) -> Result<()> {
+        self.server
+            .client_send_message(
+                request.group_id,
+                request.sender_id,
+                request.message,
+                request.global_index,
+            )
+            .map_err(refused)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ConvoClient;
+    use crate::outbox::OutboxStatus;
+    use crate::sync::{SharedClient, SyncConfig};
+    use skychat_core::changes::SyncEvent;
+    use skychat_core::manager::{DeliveryState, GroupSettings};
+    use skychat_protocol::{error_codes, PresenceStatus, PushEvent};
+    use skychat_server::error::ServerError;
+    use skychat_server::limits::{Limits, Rate};
+    use tokio::sync::broadcast;
+
+    async fn connect(server: &Arc<ConvoServer>, user_id: &str) -> ConvoClient {
+        let mut client = ConvoClient::new(user_id.to_string());
+        client
+            .connect_with_transport(Arc::new(LocalTransport::new(Arc::clone(server))))
+            .await
+            .unwrap();
+        client
+    }
+
+    async fn invite(inviter: &mut ConvoClient, invitee: &mut ConvoClient, group_id: &[u8]) {
+        let key_packages = inviter
+            .get_user_key_packages(vec![invitee.user_id.clone()])
+            .await
+            .unwrap();
+        inviter
+            .invite_user_to_group(
+                invitee.user_id.clone(),
+                group_id.to_vec(),
+                key_packages[&invitee.user_id].clone(),
+            )
+            .await
+            .unwrap();
+
+        invitee.sync().await.unwrap();
+        invitee.accept_current_invites().await.unwrap();
+    }
+
+    fn texts(client: &ConvoClient, group_id: &[u8]) -> Vec<(String, String)> {
+        client
+            .get_group_messages(&group_id.to_vec())
+            .unwrap()
+            .iter()
+            .filter(|message| message.sender_id != "system")
+            .map(|message| (message.sender_id.clone(), message.text.clone()))
+            .collect()
+    }
+
+    fn state(client: &ConvoClient, group_id: &[u8], local_id: u64) -> DeliveryState {
+        client
+            .get_group_messages(&group_id.to_vec())
+            .unwrap()
+            .iter()
+            .find(|message| message.local_id == Some(local_id))
+            .unwrap()
+            .state
+            .clone()
+    }
+
+    // syncs whenever bob's background sync says so, until an event matching `wanted` comes up
+    async fn wait_for(
+        client: &mut ConvoClient,
+        events: &mut broadcast::Receiver<SyncEvent>,
+        wanted: impl Fn(&SyncEvent) -> bool,
+    ) -> SyncEvent {
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(10);
+        loop {
+            while let Ok(event) = events.try_recv() {
+                if wanted(&event) {
+                    return event;
+                }
+            }
+            tokio::time::timeout_at(deadline, client.sync_when_due())
+                .await
+                .expect("timed out waiting for an event")
+                .unwrap();
+        }
+    }
+
+    // alice invites bob and charlie one after the other, everyone reads everyone's messages
+    #[tokio::test]
+    async fn three_clients_share_a_group() {
+        let server = Arc::new(ConvoServer::new());
+        let mut alice = connect(&server, "alice").await;
+        let mut bob = connect(&server, "bob").await;
+        let mut charlie = connect(&server, "charlie").await;
+
+        let group_id = alice.create_group("friends".to_string()).await.unwrap();
+
+        invite(&mut alice, &mut bob, &group_id).await;
+        invite(&mut alice, &mut charlie, &group_id).await;
+
+        alice
+            .send_message(&group_id, "hi all".to_string())
+            .await
+            .unwrap();
+        bob.send_message(&group_id, "hey alice".to_string())
+            .await
+            .unwrap();
+        charlie
+            .send_message(&group_id, "hello".to_string())
+            .await
+            .unwrap();
+
+        for client in [&mut alice, &mut bob, &mut charlie] {
+            client.sync().await.unwrap();
+        }
+
+        let expected = vec![
+            ("alice".to_string(), "hi all".to_string()),
+            ("bob".to_string(), "hey alice".to_string()),
+            ("charlie".to_string(), "hello".to_string()),
+        ];
+        assert_eq!(texts(&alice, &group_id), expected);
+        assert_eq!(texts(&bob, &group_id), expected);
+        assert_eq!(texts(&charlie, &group_id), expected);
+    }
+
+    // bob's background sync hears about alice's invite and message as soon as they're pushed,
+    // well before its (here very slow) polling would
+    #[tokio::test]
+    async fn background_sync_reports_what_changed() {
+        let server = Arc::new(ConvoServer::new());
+        let mut alice = connect(&server, "alice").await;
+        let mut bob = connect(&server, "bob").await;
+        let group_id = alice.create_group("friends".to_string()).await.unwrap();
+
+        let mut bob_events = bob.sync_events();
+        let config = SyncConfig {
+            min_interval: std::time::Duration::from_secs(60),
+            ..SyncConfig::default()
+        };
+        bob.start_sync(config).unwrap();
+        // the first sync is due right away, there's nothing yet:
+        bob.sync_when_due().await.unwrap();
+
+        let mut alice_events = alice.sync_events();
+        let key_packages = alice
+            .get_user_key_packages(vec!["bob".to_string()])
+            .await
+            .unwrap();
+        alice
+            .invite_user_to_group(
+                "bob".to_string(),
+                group_id.clone(),
+                key_packages["bob"].clone(),
+            )
+            .await
+            .unwrap();
+
+        let joined: Vec<SyncEvent> = std::iter::from_fn(|| alice_events.try_recv().ok()).collect();
+        assert_eq!(
+            joined,
+            [
+                SyncEvent::MemberJoined {
+                    group_id: group_id.clone(),
+                    user_id: "bob".to_string()
+                },
+                SyncEvent::EpochChanged {
+                    group_id: group_id.clone(),
+                    epoch: 1
+                },
+            ]
+        );
+
+        let invite = wait_for(&mut bob, &mut bob_events, |event| {
+            matches!(event, SyncEvent::InviteReceived { .. })
+        })
+        .await;
+        assert!(
+            matches!(invite, SyncEvent::InviteReceived { group_name, .. } if group_name == "friends")
+        );
+        bob.accept_current_invites().await.unwrap();
+
+        alice
+            .send_message(&group_id, "hi bob".to_string())
+            .await
+            .unwrap();
+        let message = wait_for(&mut bob, &mut bob_events, |event| {
+            matches!(event, SyncEvent::NewMessage { .. })
+        })
+        .await;
+        assert!(matches!(
+            message,
+            SyncEvent::NewMessage { sender_id, text, .. } if sender_id == "alice" && text == "hi bob"
+        ));
+    }
+
+    // with a shared client the engine syncs on its own, bob only reads the events. once stopped
+    // nothing syncs anymore
+    #[tokio::test]
+    async fn background_sync_runs_by_itself_until_stopped() {
+        let server = Arc::new(ConvoServer::new());
+        let mut alice = connect(&server, "alice").await;
+        let mut bob = connect(&server, "bob").await;
+        let group_id = alice.create_group("friends".to_string()).await.unwrap();
+        invite(&mut alice, &mut bob, &group_id).await;
+
+        let bob_events = bob.sync_events();
+        let bob: SharedClient = Arc::new(tokio::sync::Mutex::new(bob));
+        let config = SyncConfig {
+            min_interval: std::time::Duration::from_secs(60),
+            ..SyncConfig::default()
+        };
+        let handle = ConvoClient::start_background_sync(&bob, config)
+            .await
+            .unwrap();
+
+        let next_message = |events: &broadcast::Receiver<SyncEvent>| {
+            let mut events = events.resubscribe();
+            async move {
+                loop {
+                    if let SyncEvent::NewMessage { text, .. } = events.recv().await.unwrap() {
+                        return text;
+                    }
+                }
+            }
+        };
+        let received = next_message(&bob_events);
+        alice
+            .send_message(&group_id, "hi bob".to_string())
+            .await
+            .unwrap();
+        let text = tokio::time::timeout(std::time::Duration::from_secs(10), received)
+            .await
+            .expect("timed out waiting for the message");
+        assert_eq!(text, "hi bob");
+
+        handle.stop().await;
+        let received = next_message(&bob_events);
+        alice
+            .send_message(&group_id, "still there?".to_string())
+            .await
+            .unwrap();
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(500), received)
+                .await
+                .is_err()
+        );
+        assert_eq!(texts(&*bob.lock().await, &group_id).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn refusals_come_back_like_http_errors() {
+        let server = Arc::new(ConvoServer::new());
+        let mut alice = connect(&server, "alice").await;
+        let mut mallory = connect(&server, "mallory").await;
+
+        let group_id = alice.create_group("private".to_string()).await.unwrap();
+
+        // mallory never joined:
+        let error = mallory
+            .transport()
+            .unwrap()
+            .group_index(GetGroupIndex {
+                group_id: group_id.clone(),
+                sender_id: "mallory".to_string(),
+            })
+            .await
+            .unwrap_err();
+        let failed = error.downcast_ref::<RequestFailed>().unwrap();
+        assert_eq!(failed.status, 403);
+        assert_eq!(failed.code(), Some(error_codes::FORBIDDEN));
+
+        // reconnecting with the same user_id is fine, the account is already ours:
+        mallory
+            .connect_with_transport(Arc::new(LocalTransport::new(Arc::clone(&server))))
+            .await
+            .unwrap();
+    }
+
+    // mallory can't delete or export alice's account by naming it, only alice can. the group
+    // alice leaves behind stays around for bob's invite
+    #[tokio::test]
+    async fn only_the_owner_can_delete_or_export_an_account() {
+        let server = Arc::new(ConvoServer::new());
+        let mut alice = connect(&server, "alice").await;
+        let mut bob = connect(&server, "bob").await;
+        let mallory = connect(&server, "mallory").await;
+        let group_id = alice.create_group("friends".to_string()).await.unwrap();
+        let key_packages = alice
+            .get_user_key_packages(vec!["bob".to_string()])
+            .await
+            .unwrap();
+        alice
+            .invite_user_to_group(
+                "bob".to_string(),
+                group_id.clone(),
+                key_packages["bob"].clone(),
+            )
+            .await
+            .unwrap();
+
+        let transport = mallory.transport().unwrap();
+        let forged = |action| mallory.manager.account_proof(action).unwrap();
+        let errors = [
+            transport
+                .delete_account(DeleteAccount {
+                    user_id: "alice".to_string(),
+                    proof: forged(DeleteAccount::ACTION),
+                })
+                .await
+                .unwrap_err(),
+            transport
+                .export_account(ExportAccount {
+                    user_id: "alice".to_string(),
+                    proof: forged(ExportAccount::ACTION),
+                })
+                .await
+                .unwrap_err(),
+            // a real proof for another route doesn't do either:
+            transport
+                .delete_account(DeleteAccount {
+                    user_id: "alice".to_string(),
+                    proof: alice.manager.account_proof(ExportAccount::ACTION).unwrap(),
+                })
+                .await
+                .unwrap_err(),
+        ];
+        for error in errors {
+            let failed = error.downcast_ref::<RequestFailed>().unwrap();
+            assert_eq!(failed.code(), Some(error_codes::FORBIDDEN));
+        }
+
+        assert_eq!(alice.export_account().await.unwrap().groups.len(), 1);
+        alice.delete_account().await.unwrap();
+
+        bob.sync().await.unwrap();
+        bob.accept_current_invites().await.unwrap();
+        assert!(bob.manager.groups.contains_key(&group_id));
+    }
+
+    // a fetch that can't be served whole hands out nothing, and one account can only fetch so
+    // many packages, whoever they belong to
+    #[tokio::test]
+    async fn key_package_fetches_are_all_or_nothing_and_limited_per_sender() {
+        let limits = Limits {
+            key_package_requests: Rate {
+                burst: 3,
+                per_minute: 1,
+            },
+            ..Limits::default()
+        };
+        let server = Arc::new(ConvoServer::new().with_limits(limits));
+        let mallory = connect(&server, "mallory").await;
+        let users = ["bob", "charlie", "dave", "erin"];
+        for user in users {
+            connect(&server, user).await;
+        }
+        let stock = |user: &str| {
+            server
+                .client_upload_key_packages(user.to_string(), Vec::new(), None, false)
+                .unwrap()
+                .remaining
+        };
+        let bob_stock = stock("bob");
+
+        let error = mallory
+            .get_user_key_packages(vec!["bob".to_string(), "nobody".to_string()])
+            .await
+            .unwrap_err();
+        let failed = error.downcast_ref::<RequestFailed>().unwrap();
+        assert_eq!(failed.code(), Some(error_codes::NOT_FOUND));
+        assert_eq!(stock("bob"), bob_stock);
+
+        // the failed fetch counted against mallory, one more package is all that's left:
+        mallory
+            .get_user_key_packages(vec!["bob".to_string()])
+            .await
+            .unwrap();
+        let error = mallory
+            .get_user_key_packages(users[1..].iter().map(|user| user.to_string()).collect())
+            .await
+            .unwrap_err();
+        let failed = error.downcast_ref::<RequestFailed>().unwrap();
+        assert_eq!(failed.code(), Some(error_codes::RATE_LIMITED));
+        assert_eq!(stock("charlie"), bob_stock);
+
+        // more than the burst can never be served, waiting won't help:
+        let error = mallory
+            .get_user_key_packages(users.iter().map(|user| user.to_string()).collect())
+            .await
+            .unwrap_err();
+        let failed = error.downcast_ref::<RequestFailed>().unwrap();
+        assert_eq!(failed.code(), Some(error_codes::BAD_REQUEST));
+    }
+
+    // a message the server turns away for its index doesn't count against the sender's rate
+    #[tokio::test]
+    async fn refused_sends_dont_use_up_the_rate_limit() {
+        let limits = Limits {
+            user_sends: Rate {
+                burst: 1,
+                per_minute: 1,
+            },
+            ..Limits::default()
+        };
+        let server = Arc::new(ConvoServer::new().with_limits(limits));
+        connect(&server, "alice").await;
+        let group_id = b"notes".to_vec();
+        server
+            .client_create_group(group_id.clone(), "notes".to_string(), "alice".to_string())
+            .unwrap();
+        let send = |global_index: u64| {
+            server.client_send_message(
+                group_id.clone(),
+                "alice".to_string(),
+                b"hi".to_vec(),
+                global_index,
+            )
+        };
+
+        for global_index in [0, 0, 5] {
+            assert!(send(global_index).is_err());
+        }
+        send(1).unwrap();
+        let error = send(2).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ServerError>(),
+            Some(ServerError::RateLimited { .. })
+        ));
+    }
+
+    // only the account's owner is marked online for opening an event stream, and presence only
+    // reaches people who share a group with the user
+    #[tokio::test]
+    async fn event_streams_only_put_their_owner_online() {
+        let server = Arc::new(ConvoServer::new());
+        let mut alice = connect(&server, "alice").await;
+        let mut bob = connect(&server, "bob").await;
+        let mallory = connect(&server, "mallory").await;
+        let group_id = alice.create_group("friends".to_string()).await.unwrap();
+        invite(&mut alice, &mut bob, &group_id).await;
+        let mut bob_events = bob.open_event_stream().await.unwrap();
+        let mut mallory_events = mallory.open_event_stream().await.unwrap();
+        alice.set_presence(PresenceStatus::Offline).await.unwrap();
+        let status = || server.client_get_presence(vec!["alice".to_string()])[0].status;
+
+        let transport = mallory.transport().unwrap();
+        transport
+            .events(OpenEvents::new("alice".to_string(), None))
+            .await
+            .unwrap();
+        let forged = mallory.manager.account_proof(OpenEvents::ACTION).unwrap();
+        let error = transport
+            .events(OpenEvents::new("alice".to_string(), Some(forged)))
+            .await
+            .err()
+            .unwrap();
+        let failed = error.downcast_ref::<RequestFailed>().unwrap();
+        assert_eq!(failed.code(), Some(error_codes::FORBIDDEN));
+        assert_eq!(status(), PresenceStatus::Offline);
+
+        alice.open_event_stream().await.unwrap();
+        assert_eq!(status(), PresenceStatus::Online);
+        mallory.set_presence(PresenceStatus::Away).await.unwrap();
+
+        let presence = |events: Vec<PushEvent>| -> Vec<(String, PresenceStatus)> {
+            events
+                .into_iter()
+                .filter_map(|event| match event {
+                    PushEvent::Presence(presence) => Some((presence.user_id, presence.status)),
+                    _ => None,
+                })
+                .collect()
+        };
+        let mut seen = Vec::new();
+        while !presence(seen.clone()).contains(&("alice".to_string(), PresenceStatus::Online)) {
+            seen.push(bob_events.next_event().await.unwrap().unwrap());
+        }
+        assert_eq!(
+            presence(seen),
+            [
+                ("alice".to_string(), PresenceStatus::Offline),
+                ("alice".to_string(), PresenceStatus::Online),
+            ]
+        );
+
+        // mallory going away comes after alice's changes, so she'd have seen them by then:
+        let mut seen = Vec::new();
+        while !presence(seen.clone()).contains(&("mallory".to_string(), PresenceStatus::Away)) {
+            seen.push(mallory_events.next_event().await.unwrap().unwrap());
+        }
+        assert!(presence(seen)
+            .iter()
+            .all(|(user_id, _)| user_id == "mallory"));
+    }
+
+    // reconnecting tops the pool up instead of replacing it, rotating is left to the schedule
+    // (which a sync checks) and when we last did is saved with the state
+    #[tokio::test]
+    async fn reconnecting_tops_up_key_packages_without_rotating() {
+        let server = Arc::new(ConvoServer::new());
+        let alice = connect(&server, "alice").await;
+        let bob = connect(&server, "bob").await;
+        let stock = || {
+            server
+                .client_upload_key_packages("alice".to_string(), Vec::new(), None, false)
+                .unwrap()
+                .remaining
+        };
+        let full = stock();
+        assert_ne!(alice.manager.last_key_package_rotation, 0);
+
+        bob.get_user_key_packages(vec!["alice".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(stock(), full - 1);
+
+        let mut restored = ConvoClient::new("alice".to_string());
+        restored.load_state(alice.save_state().unwrap()).unwrap();
+        assert_eq!(
+            restored.manager.last_key_package_rotation,
+            alice.manager.last_key_package_rotation
+        );
+        restored.manager.last_key_package_rotation = 1;
+        restored.key_package_rotation = 0;
+        restored
+            .connect_with_transport(Arc::new(LocalTransport::new(Arc::clone(&server))))
+            .await
+            .unwrap();
+        restored.sync().await.unwrap();
+
+        assert_eq!(stock(), full);
+        assert_eq!(restored.manager.last_key_package_rotation, 1);
+    }
+
+    // the server stored an attempt but its answer never arrived: the next attempt finds it in
+    // the group's log instead of posting it a second time. the outbox is saved with the state
+    #[tokio::test]
+    async fn a_delivered_attempt_is_not_sent_again() {
+        let limits = Limits {
+            user_sends: Rate {
+                burst: 1,
+                per_minute: 6000,
+            },
+            ..Limits::default()
+        };
+        let server = Arc::new(ConvoServer::new().with_limits(limits));
+        let mut alice = connect(&server, "alice").await;
+        let mut bob = connect(&server, "bob").await;
+        let group_id = alice.create_group("notes".to_string()).await.unwrap();
+        invite(&mut alice, &mut bob, &group_id).await;
+
+        alice
+            .send_message(&group_id, "one".to_string())
+            .await
+            .unwrap();
+        let two = alice
+            .send_message(&group_id, "two".to_string())
+            .await
+            .unwrap();
+        assert_eq!(state(&alice, &group_id, two), DeliveryState::Queued);
+
+        let mut restored = ConvoClient::new("alice".to_string());
+        restored.load_state(alice.save_state().unwrap()).unwrap();
+        assert_eq!(restored.outbox, alice.outbox);
+
+        // the retry alice never heard back from:
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let encrypted = alice.outbox.get(two).unwrap().encrypted.clone().unwrap();
+        let index = server
+            .client_get_group_index(group_id.clone(), "alice".to_string())
+            .unwrap()
+            + 1;
+        server
+            .client_send_message(
+                group_id.clone(),
+                "alice".to_string(),
+                encrypted.message,
+                index,
+            )
+            .unwrap();
+
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        alice.outbox.retry_now();
+        alice.flush_outbox().await;
+
+        assert!(alice.outbox.is_empty());
+        assert_eq!(
+            state(&alice, &group_id, two),
+            DeliveryState::Sent {
+                global_index: index
+            }
+        );
+        assert_eq!(
+            server
+                .client_get_group_index(group_id.clone(), "alice".to_string())
+                .unwrap(),
+            index
+        );
+        bob.sync().await.unwrap();
+        let received: Vec<String> = texts(&bob, &group_id)
+            .into_iter()
+            .map(|(_, text)| text)
+            .collect();
+        assert_eq!(received, ["one", "two"]);
+    }
+
+    // a commit whose post never got an answer stays pending. the next sync merges it if the
+    // server has it, and throws it away if it doesn't
+    #[tokio::test]
+    async fn unconfirmed_commits_are_settled_by_the_next_sync() {
+        let server = Arc::new(ConvoServer::new());
+        let mut alice = connect(&server, "alice").await;
+        let mut bob = connect(&server, "bob").await;
+        let mut charlie = connect(&server, "charlie").await;
+        let group_id = alice.create_group("friends".to_string()).await.unwrap();
+        invite(&mut alice, &mut bob, &group_id).await;
+        invite(&mut alice, &mut charlie, &group_id).await;
+        let epoch = alice.manager.group_get_epoch(&group_id).unwrap().as_u64();
+
+        // never got to the server:
+        let lost = alice.manager.remove_member(&group_id, "charlie").unwrap();
+        alice
+            .manager
+            .group_commit_unconfirmed(&group_id, lost)
+            .unwrap();
+        // nothing new can be committed until it's settled:
+        assert!(alice.manager.remove_member(&group_id, "charlie").is_err());
+        alice.sync().await.unwrap();
+        assert_eq!(
+            alice.manager.group_get_epoch(&group_id).unwrap().as_u64(),
+            epoch
+        );
+        assert!(alice
+            .manager
+            .group_get_member_ids(&group_id)
+            .unwrap()
+            .contains(&"charlie".to_string()));
+
+        // stored, but the answer was lost:
+        let stored = alice.manager.remove_member(&group_id, "charlie").unwrap();
+        server
+            .client_remove_user(
+                group_id.clone(),
+                "alice".to_string(),
+                "charlie".to_string(),
+                stored.clone(),
+            )
+            .unwrap();
+        alice
+            .manager
+            .group_commit_unconfirmed(&group_id, stored)
+            .unwrap();
+        alice.sync().await.unwrap();
+        assert_eq!(
+            alice.manager.group_get_epoch(&group_id).unwrap().as_u64(),
+            epoch + 1
+        );
+        assert!(!alice
+            .manager
+            .group_get_member_ids(&group_id)
+            .unwrap()
+            .contains(&"charlie".to_string()));
+
+        alice
+            .send_message(&group_id, "just us".to_string())
+            .await
+            .unwrap();
+        bob.sync().await.unwrap();
+        assert_eq!(
+            texts(&bob, &group_id).last().unwrap().1,
+            "just us".to_string()
+        );
+    }
+
+    // rate limited messages stay queued, in order, until they can go out
+    #[tokio::test]
+    async fn outbox_holds_messages_until_they_can_be_sent() {
+        let limits = Limits {
+            user_sends: Rate {
+                burst: 2,
+                per_minute: 6000,
+            },
+            ..Limits::default()
+        };
+        let server = Arc::new(ConvoServer::new().with_limits(limits));
+        let mut alice = connect(&server, "alice").await;
+        let group_id = alice.create_group("notes".to_string()).await.unwrap();
+        let mut events = alice.sync_events();
+
+        for text in ["one", "two"] {
+            let id = alice
+                .send_message(&group_id, text.to_string())
+                .await
+                .unwrap();
+            assert!(alice.outbox.get(id).is_none());
+            assert!(matches!(
+                state(&alice, &group_id, id),
+                DeliveryState::Sent { .. }
+            ));
+        }
+        let three = alice
+            .send_message(&group_id, "three".to_string())
+            .await
+            .unwrap();
+        let four = alice
+            .send_message(&group_id, "four".to_string())
+            .await
+            .unwrap();
+
+        // "three" is waiting out the rate limit, "four" waits behind it:
+        assert_eq!(
+            alice.outbox.get(three).unwrap().status,
+            OutboxStatus::Pending
+        );
+        assert_eq!(alice.outbox.get(three).unwrap().attempts, 1);
+        assert_eq!(alice.outbox.get(four).unwrap().attempts, 0);
+        assert_eq!(state(&alice, &group_id, three), DeliveryState::Queued);
+        assert_eq!(state(&alice, &group_id, four), DeliveryState::Composing);
+
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        alice.outbox.retry_now();
+        alice.flush_outbox().await;
+
+        assert!(alice.outbox.is_empty());
+        let sent: Vec<String> = texts(&alice, &group_id)
+            .into_iter()
+            .map(|(_, text)| text)
+            .collect();
+        assert_eq!(sent, ["one", "two", "three", "four"]);
+
+        // "three" went composing -> queued -> sent, the index it got is the one after "two":
+        let mut three_states = vec![];
+        while let Ok(event) = events.try_recv() {
+            if let SyncEvent::Delivery {
+                local_id, state, ..
+            } = event
+            {
+                if local_id == three {
+                    three_states.push(state);
+                }
+            }
+        }
+        let two_index = match state(&alice, &group_id, 2) {
+            DeliveryState::Sent { global_index } => global_index,
+            other => panic!("unexpected state {:?}", other),
+        };
+        assert_eq!(
+            three_states,
+            [
+                DeliveryState::Composing,
+                DeliveryState::Queued,
+                DeliveryState::Sent {
+                    global_index: two_index + 1
+                },
+            ]
+        );
+    }
+
+    // a message nobody can read doesn't stop bob's group at its index, he reads on past it
+    // and doesn't ask for it again
+    #[tokio::test]
+    async fn unreadable_messages_dont_stall_a_group() {
+        let server = Arc::new(ConvoServer::new());
+        let mut alice = connect(&server, "alice").await;
+        let mut bob = connect(&server, "bob").await;
+        let group_id = alice.create_group("friends".to_string()).await.unwrap();
+        invite(&mut alice, &mut bob, &group_id).await;
+
+        let global_index = alice.get_group_index(&group_id).await.unwrap();
+        server
+            .client_send_message(
+                group_id.clone(),
+                "alice".to_string(),
+                b"not mls".to_vec(),
+                global_index + 1,
+            )
+            .unwrap();
+        alice
+            .manager
+            .group_set_index(&group_id, global_index + 1)
+            .unwrap();
+        alice
+            .send_message(&group_id, "still there?".to_string())
+            .await
+            .unwrap();
+
+        bob.sync().await.unwrap();
+        assert_eq!(
+            texts(&bob, &group_id),
+            [("alice".to_string(), "still there?".to_string())]
+        );
+        assert!(bob.manager.group_gaps(&group_id).unwrap().is_empty());
+        assert_eq!(bob.manager.group_indices()[&group_id], global_index + 2);
+        assert_eq!(bob.manager.dropped.len(), 1);
+    }
+
+    // bob gets alice's message before the commit adding charlie that it was sent after,
+    // it's held until the commit is merged instead of being lost
+    #[tokio::test]
+    async fn messages_from_a_future_epoch_wait_for_their_commit() {
+        let server = Arc::new(ConvoServer::new());
+        let mut alice = connect(&server, "alice").await;
+        let mut bob = connect(&server, "bob").await;
+        let mut charlie = connect(&server, "charlie").await;
+        let group_id = alice.create_group("friends".to_string()).await.unwrap();
+        invite(&mut alice, &mut bob, &group_id).await;
+
+        let index = bob.manager.group_indices()[&group_id];
+        invite(&mut alice, &mut charlie, &group_id).await;
+        alice
+            .send_message(&group_id, "welcome charlie".to_string())
+            .await
+            .unwrap();
+
+        let mut messages = server
+            .client_get_new_messages(Some(group_id.clone()), "bob".to_string(), index)
+            .unwrap();
+        messages.reverse();
+        bob.manager.process_convo_messages(messages).unwrap();
+
+        assert_eq!(
+            texts(&bob, &group_id),
+            [("alice".to_string(), "welcome charlie".to_string())]
+        );
+        assert!(bob.manager.groups[&group_id].buffered.is_empty());
+        assert!(bob.manager.dropped.is_empty());
+    }
+
+    // dave merges the commit adding charlie before bob's message sent just ahead of it,
+    // the group keeps the past epoch's secrets around long enough to still read it
+    #[tokio::test]
+    async fn messages_from_just_before_a_commit_still_decrypt() {
+        let server = Arc::new(ConvoServer::new());
+        let mut alice = connect(&server, "alice").await;
+        let mut bob = connect(&server, "bob").await;
+        let mut charlie = connect(&server, "charlie").await;
+        let mut dave = connect(&server, "dave").await;
+        let settings = GroupSettings {
+            max_past_epochs: 2,
+            ..GroupSettings::default()
+        };
+        dave.set_default_group_settings(settings);
+
+        let group_id = alice.create_group("friends".to_string()).await.unwrap();
+        invite(&mut alice, &mut bob, &group_id).await;
+        invite(&mut alice, &mut dave, &group_id).await;
+        bob.sync().await.unwrap();
+        assert_eq!(dave.group_settings(&group_id).unwrap(), settings);
+
+        let index = dave.manager.group_indices()[&group_id];
+        bob.send_message(&group_id, "just before".to_string())
+            .await
+            .unwrap();
+        invite(&mut alice, &mut charlie, &group_id).await;
+
+        let mut messages = server
+            .client_get_new_messages(Some(group_id.clone()), "dave".to_string(), index)
+            .unwrap();
+        messages.reverse();
+        dave.manager.process_convo_messages(messages).unwrap();
+        assert_eq!(
+            texts(&dave, &group_id),
+            [("bob".to_string(), "just before".to_string())]
+        );
+
+        // the settings are saved with the group:
+        let mut restored = skychat_core::manager::ConvoManager::init("dave".to_string());
+        restored
+            .load_state(dave.manager.save_state().unwrap())
+            .unwrap();
+        assert_eq!(restored.group_get_settings(&group_id).unwrap(), settings);
+    }
+
+    // alice and bob invite someone at the same time, bob's commit loses: the server turns it
+    // down instead of letting the group fork, and bob's client makes it again on top of alice's
+    #[tokio::test]
+    async fn concurrent_commits_dont_fork_the_group() {
+        let server = Arc::new(ConvoServer::new());
+        let mut alice = connect(&server, "alice").await;
+        let mut bob = connect(&server, "bob").await;
+        let mut charlie = connect(&server, "charlie").await;
+        let mut dave = connect(&server, "dave").await;
+        let group_id = alice.create_group("friends".to_string()).await.unwrap();
+        invite(&mut alice, &mut bob, &group_id).await;
+        bob.sync().await.unwrap();
+
+        let key_packages = bob
+            .get_user_key_packages(vec!["charlie".to_string()])
+            .await
+            .unwrap();
+        let losing = bob
+            .manager
+            .create_invite(&group_id, key_packages["charlie"].clone())
+            .unwrap();
+        invite(&mut alice, &mut dave, &group_id).await;
+
+        let e = server
+            .client_invite_user(
+                group_id.clone(),
+                "bob".to_string(),
+                "charlie".to_string(),
+                losing.welcome_message,
+                vec![],
+                losing.fanned,
+            )
+            .unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<ServerError>(),
+            Some(ServerError::StaleEpoch(_))
+        ));
+        bob.manager.group_discard_pending_commit(&group_id).unwrap();
+
+        bob.invite_user_to_group(
+            "charlie".to_string(),
+            group_id.clone(),
+            key_packages["charlie"].clone(),
+        )
+        .await
+        .unwrap();
+        charlie.sync().await.unwrap();
+        charlie.accept_current_invites().await.unwrap();
+
+        for client in [&mut alice, &mut bob, &mut charlie, &mut dave] {
+            client.sync().await.unwrap();
+            let user_id = client.user_id.clone();
+            client.send_message(&group_id, user_id).await.unwrap();
+        }
+        for client in [&mut alice, &mut bob, &mut charlie, &mut dave] {
+            client.sync().await.unwrap();
+        }
+
+        let epochs: Vec<u64> = [&alice, &bob, &charlie, &dave]
+            .iter()
+            .map(|client| client.manager.groups[&group_id].mls_group.epoch().as_u64())
+            .collect();
+        assert!(epochs.iter().all(|&epoch| epoch == epochs[0]));
+        for client in [&alice, &bob, &charlie, &dave] {
+            assert_eq!(
+                texts(client, &group_id)
+                    .into_iter()
+                    .map(|(_, text)| text)
+                    .collect::<Vec<_>>(),
+                ["alice", "bob", "charlie", "dave"]
+            );
+        }
+    }
+
+    // alice and bob each merge a different commit for the same epoch behind the server's back,
+    // bob notices alice's messages come from another copy of the group and stops trying them
+    #[tokio::test]
+    async fn diverged_group_states_are_reported_as_a_fork() {
+        let server = Arc::new(ConvoServer::new());
+        let mut alice = connect(&server, "alice").await;
+        let mut bob = connect(&server, "bob").await;
+        let charlie = connect(&server, "charlie").await;
+        let dave = connect(&server, "dave").await;
+        let group_id = alice.create_group("friends".to_string()).await.unwrap();
+        invite(&mut alice, &mut bob, &group_id).await;
+        bob.sync().await.unwrap();
+        let mut events = bob.sync_events();
+
+        for (client, invitee) in [(&mut alice, &charlie), (&mut bob, &dave)] {
+            let key_package = invitee.manager.get_key_package().unwrap();
+            client
+                .manager
+                .create_invite(&group_id, key_package)
+                .unwrap();
+            client
+                .manager
+                .group_merge_pending_commit(&group_id)
+                .unwrap();
+        }
+        alice
+            .send_message(&group_id, "can you read this?".to_string())
+            .await
+            .unwrap();
+        bob.sync().await.unwrap();
+
+        let forks: Vec<SyncEvent> = std::iter::from_fn(|| events.try_recv().ok())
+            .filter(|event| matches!(event, SyncEvent::ForkDetected { .. }))
+            .collect();
+        assert_eq!(
+            forks,
+            [SyncEvent::ForkDetected {
+                group_id: group_id.clone(),
+                epoch: 2,
+                messages: 1,
+            }]
+        );
+        assert!(texts(&bob, &group_id).is_empty());
+        assert_eq!(bob.manager.dropped.len(), 1);
+        assert!(bob.manager.group_gaps(&group_id).unwrap().is_empty());
+    }
+
+    // bob's copy of the group never applies the commit adding charlie (as if restored from a
+    // backup made just before it), so nothing sent since can be read. bob asks to rejoin, alice's
+    // next sync re-adds bob, and bob picks up again with the group's history still there
+    #[tokio::test]
+    async fn stale_members_rejoin_with_their_history() {
+        let server = Arc::new(ConvoServer::new());
+        let mut alice = connect(&server, "alice").await;
+        let mut bob = connect(&server, "bob").await;
+        let mut charlie = connect(&server, "charlie").await;
+        let group_id = alice.create_group("friends".to_string()).await.unwrap();
+        invite(&mut alice, &mut bob, &group_id).await;
+        alice
+            .send_message(&group_id, "before".to_string())
+            .await
+            .unwrap();
+        bob.sync().await.unwrap();
+
+        let index = bob.manager.group_indices()[&group_id];
+        invite(&mut alice, &mut charlie, &group_id).await;
+        bob.manager
+            .groups
+            .get_mut(&group_id)
+            .unwrap()
+            .applied
+            .insert(index + 1);
+        alice
+            .send_message(&group_id, "lost".to_string())
+            .await
+            .unwrap();
+        bob.sync().await.unwrap();
+        assert_eq!(bob.manager.groups[&group_id].buffered.len(), 1);
+
+        bob.request_rejoin(&group_id).await.unwrap();
+        alice.sync().await.unwrap();
+        charlie.sync().await.unwrap();
+        bob.sync().await.unwrap();
+        bob.accept_current_invites().await.unwrap();
+        assert!(bob.manager.groups[&group_id].buffered.is_empty());
+        assert_eq!(bob.manager.dropped.len(), 1);
+
+        alice
+            .send_message(&group_id, "welcome back".to_string())
+            .await
+            .unwrap();
+        bob.sync().await.unwrap();
+        bob.send_message(&group_id, "thanks".to_string())
+            .await
+            .unwrap();
+        for client in [&mut alice, &mut bob, &mut charlie] {
+            client.sync().await.unwrap();
+        }
+
+        let epochs: Vec<u64> = [&alice, &bob, &charlie]
+            .iter()
+            .map(|client| client.manager.groups[&group_id].mls_group.epoch().as_u64())
+            .collect();
+        assert!(epochs.iter().all(|&epoch| epoch == epochs[0]));
+        let message = |sender: &str, text: &str| (sender.to_string(), text.to_string());
+        assert_eq!(
+            texts(&bob, &group_id),
+            [
+                message("alice", "before"),
+                message("alice", "welcome back"),
+                message("bob", "thanks"),
+            ]
+        );
+        assert_eq!(
+            texts(&charlie, &group_id),
+            [
+                message("alice", "lost"),
+                message("alice", "welcome back"),
+                message("bob", "thanks"),
+            ]
+        );
+        assert!(bob.manager.group_gaps(&group_id).unwrap().is_empty());
+    }
+
+    // the server takes a rejoin request from anyone claiming to be a member, and a basic
+    // credential is only a name: mallory's key package for "bob" must not replace bob's leaf
+    #[tokio::test]
+    async fn forged_rejoin_requests_are_refused() {
+        let server = Arc::new(ConvoServer::new());
+        let mut alice = connect(&server, "alice").await;
+        let mut bob = connect(&server, "bob").await;
+        let mallory = connect(&server, "mallory").await;
+        let group_id = alice.create_group("friends".to_string()).await.unwrap();
+        invite(&mut alice, &mut bob, &group_id).await;
+        let epoch = alice.manager.groups[&group_id].mls_group.epoch();
+
+        let forged = skychat_core::manager::ConvoManager::init("bob".to_string())
+            .get_key_package()
+            .unwrap();
+        mallory
+            .transport()
+            .unwrap()
+            .request_rejoin(RequestRejoin {
+                group_id: group_id.clone(),
+                sender_id: "bob".to_string(),
+                key_package: forged.clone(),
+            })
+            .await
+            .unwrap();
+        alice.sync().await.unwrap();
+
+        assert_eq!(alice.manager.groups[&group_id].mls_group.epoch(), epoch);
+        assert!(alice
+            .manager
+            .readd_member(&group_id, "bob", forged)
+            .is_err());
+        alice
+            .send_message(&group_id, "still you?".to_string())
+            .await
+            .unwrap();
+        bob.sync().await.unwrap();
+        assert_eq!(
+            texts(&bob, &group_id),
+            [("alice".to_string(), "still you?".to_string())]
+        );
+    }
+
+    // a message the server refuses for good is marked failed, it never shows up as sent
+    #[tokio::test]
+    async fn refused_messages_are_marked_failed() {
+        let server = Arc::new(ConvoServer::new());
+        let mut alice = connect(&server, "alice").await;
+        let mut bob = connect(&server, "bob").await;
+        let group_id = alice.create_group("friends".to_string()).await.unwrap();
+        invite(&mut alice, &mut bob, &group_id).await;
+
+        alice
+            .remove_user_from_group("bob".to_string(), group_id.clone())
+            .await
+            .unwrap();
+
+        let id = bob
+            .send_message(&group_id, "still here?".to_string())
+            .await
+            .unwrap();
+        let entry = bob.outbox.get(id).unwrap();
+        assert!(matches!(&entry.status, OutboxStatus::Failed(reason) if reason.contains("403")));
+        assert!(matches!(
+            state(&bob, &group_id, id),
+            DeliveryState::Failed { reason } if reason.contains("403")
+        ));
+
+        // and it stays failed, flushing again doesn't send it:
+        bob.flush_outbox().await;
+        assert!(matches!(
+            bob.outbox.get(id).unwrap().status,
+            OutboxStatus::Failed(_)
+        ));
+    }
+}
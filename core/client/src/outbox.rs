@@ -0,0 +1,158 @@
+// src/outbox.rs
+
+// messages we've written but the server hasn't taken yet. `ConvoClient::send_message` queues
+// into here and `ConvoClient::flush_outbox` delivers, in order per group: an entry waits until
+// every earlier one in its group has gone out (or failed for good).
+// the outbox is plain data, `ConvoClient::save_state` saves it with the manager's state so
+// unsent messages survive restarts.
+
+use serde::{Deserialize, Serialize};
+
+type GroupId = Vec<u8>;
+
+// how long (seconds) to wait before the first retry, doubled for every attempt after that:
+const BASE_BACKOFF: u64 = 1;
+const MAX_BACKOFF: u64 = 5 * 60;
+// transient failures (server unreachable, rate limited, ...) are retried this many times
+// before the message is marked as failed:
+pub const MAX_ATTEMPTS: u32 = 10;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum OutboxStatus {
+    Pending,        // waiting for its next attempt
+    Failed(String), // given up on, with the reason. `Outbox::requeue` puts it back
+}
+
+// the ciphertext of an earlier attempt, reused as long as the group is still in that epoch
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Encrypted {
+    pub epoch: u64,
+    pub message: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    pub id: u64, // local only, increases with every message queued
+    pub group_id: GroupId,
+    pub text: String,
+    pub created_at: u64, // unix ms
+    pub status: OutboxStatus,
+    pub attempts: u32,
+    pub next_attempt_at: u64, // unix seconds, 0 for right away
+    #[serde(default)]
+    pub encrypted: Option<Encrypted>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Outbox {
+    next_id: u64,
+    entries: Vec<OutboxEntry>, // in the order they were queued
+}
+
+impl Outbox {
+    pub fn push(&mut self, group_id: GroupId, text: String, created_at: u64) -> u64 {
+        self.next_id += 1;
+        self.entries.push(OutboxEntry {
+            id: self.next_id,
+            group_id,
+            text,
+            created_at,
+            status: OutboxStatus::Pending,
+            attempts: 0,
+            next_attempt_at: 0,
+            encrypted: None,
+        });
+        self.next_id
+    }
+
+    pub fn get(&self, id: u64) -> Option<&OutboxEntry> {
+        self.entries.iter().find(|entry| entry.id == id)
+    }
+
+    pub(crate) fn get_mut(&mut self, id: u64) -> Option<&mut OutboxEntry> {
+        self.entries.iter_mut().find(|entry| entry.id == id)
+    }
+
+    pub fn entries(&self) -> &[OutboxEntry] {
+        &self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn for_group<'a>(&'a self, group_id: &'a GroupId) -> impl Iterator<Item = &'a OutboxEntry> {
+        self.entries
+            .iter()
+            .filter(move |entry| &entry.group_id == group_id)
+    }
+
+    // e.g. once a failed message was read by the user
+    pub fn remove(&mut self, id: u64) -> Option<OutboxEntry> {
+        let position = self.entries.iter().position(|entry| entry.id == id)?;
+        Some(self.entries.remove(position))
+    }
+
+    // gives a failed message another round of attempts
+    pub fn requeue(&mut self, id: u64) -> bool {
+        match self.get_mut(id) {
+            Some(entry) if entry.status != OutboxStatus::Pending => {
+                entry.status = OutboxStatus::Pending;
+                entry.attempts = 0;
+                entry.next_attempt_at = 0;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // skips the backoff, e.g. once the app sees the network come back
+    pub fn retry_now(&mut self) {
+        for entry in &mut self.entries {
+            if entry.status == OutboxStatus::Pending {
+                entry.next_attempt_at = 0;
+            }
+        }
+    }
+
+    // groups with pending messages, in the order their oldest one was queued
+    pub(crate) fn pending_groups(&self) -> Vec<GroupId> {
+        let mut groups: Vec<GroupId> = vec![];
+        for entry in &self.entries {
+            if entry.status == OutboxStatus::Pending && !groups.contains(&entry.group_id) {
+                groups.push(entry.group_id.clone());
+            }
+        }
+        groups
+    }
+
+    // the group's oldest pending message, if it's due. later ones wait behind it
+    pub(crate) fn next_due(&self, group_id: &GroupId, now: u64) -> Option<u64> {
+        self.entries
+            .iter()
+            .find(|entry| &entry.group_id == group_id && entry.status == OutboxStatus::Pending)
+            .filter(|entry| entry.next_attempt_at <= now)
+            .map(|entry| entry.id)
+    }
+
+    // a transient failure: try again later, or give up once we're out of attempts
+    pub(crate) fn retry_later(&mut self, id: u64, reason: String, retry_after: u64, now: u64) {
+        let Some(entry) = self.get_mut(id) else {
+            return;
+        };
+        entry.attempts += 1;
+        if entry.attempts >= MAX_ATTEMPTS {
+            entry.status = OutboxStatus::Failed(reason);
+            return;
+        }
+        let backoff = (BASE_BACKOFF << (entry.attempts - 1).min(16)).min(MAX_BACKOFF);
+        entry.next_attempt_at = now + backoff.max(retry_after);
+    }
+
+    pub(crate) fn fail(&mut self, id: u64, reason: String) {
+        if let Some(entry) = self.get_mut(id) {
+            entry.attempts += 1;
+            entry.status = OutboxStatus::Failed(reason);
+        }
+    }
+}
@@ -0,0 +1,231 @@
+// src/sync.rs
+
+// decides when a `ConvoClient` should sync: right away when the server pushes that one of our
+// groups (or our mailbox) has something new, otherwise on a timer that backs off while nothing
+// is happening. started with `ConvoClient::start_sync`, runs in the background.
+// an app that shares its client (`SharedClient`) lets `ConvoClient::start_background_sync` run
+// the syncs too, on a task of their own. one that keeps the client to itself syncs in
+// `ConvoClient::sync_when_due` / `sync_if_due`. either way what was found comes out of
+// `ConvoClient::sync_events`.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use anyhow::Result;
+use skychat_protocol::{OpenEvents, PushEvent};
+use tokio::sync::{Mutex, Notify};
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+use crate::client::ConvoClient;
+use crate::events::EventStream;
+use crate::transport::Transport;
+
+// a client the app and the background sync take turns with
+pub type SharedClient = Arc<Mutex<ConvoClient>>;
+
+#[derive(Debug, Clone)]
+pub struct SyncConfig {
+    // the polling interval right after something happened, doubled every quiet sync:
+    pub min_interval: Duration,
+    pub max_interval: Duration,
+    // with push events there's little to poll for, it only catches events missed while
+    // the stream was reconnecting:
+    pub push_interval: Duration,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        Self {
+            min_interval: Duration::from_secs(2),
+            max_interval: Duration::from_secs(60),
+            push_interval: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    due: AtomicBool,
+    wake: Notify,
+    // a sync turned something up, poll quickly again:
+    activity: Notify,
+}
+
+pub(crate) struct SyncEngine {
+    shared: Arc<Shared>,
+    task: JoinHandle<()>,
+    // syncing on its own, see `run`:
+    runner: Option<JoinHandle<()>>,
+}
+
+// returned by `ConvoClient::start_background_sync`. dropping it leaves the sync running
+pub struct SyncHandle {
+    shared: Arc<Shared>,
+    client: Weak<Mutex<ConvoClient>>,
+}
+
+impl SyncHandle {
+    // makes the next sync due right away, e.g. when the app comes back to the foreground
+    pub fn sync_now(&self) {
+        mark_due(&self.shared);
+    }
+
+    // stops both tasks, waiting for a sync that's under way to finish first
+    pub async fn stop(self) {
+        if let Some(client) = self.client.upgrade() {
+            client.lock().await.stop_sync();
+        }
+    }
+}
+
+impl SyncEngine {
+    // `push` is whether the server streams group / mailbox updates
+    pub(crate) fn start(
+        transport: Arc<dyn Transport>,
+        user_id: String,
+        push: bool,
+        config: SyncConfig,
+    ) -> Self {
+        let shared = Arc::new(Shared::default());
+        // catch up right away:
+        shared.due.store(true, Ordering::SeqCst);
+
+        let task = tokio::spawn(schedule(
+            Arc::clone(&shared),
+            transport,
+            user_id,
+            push,
+            config,
+        ));
+        Self {
+            shared,
+            task,
+            runner: None,
+        }
+    }
+
+    // syncs `client` whenever one is due from now on, until the engine is dropped
+    pub(crate) fn run(&mut self, client: &SharedClient) -> SyncHandle {
+        let weak = Arc::downgrade(client);
+        if let Some(runner) = self.runner.take() {
+            runner.abort();
+        }
+        self.runner = Some(tokio::spawn(run(Arc::clone(&self.shared), weak.clone())));
+        SyncHandle {
+            shared: Arc::clone(&self.shared),
+            client: weak,
+        }
+    }
+
+    pub(crate) fn sync_now(&self) {
+        self.shared.due.store(true, Ordering::SeqCst);
+        self.shared.wake.notify_one();
+    }
+
+    // true (once) if a sync is due, clearing it
+    pub(crate) fn take_due(&self) -> bool {
+        self.shared.due.swap(false, Ordering::SeqCst)
+    }
+
+    pub(crate) async fn wait_due(&self) {
+        wait_due(&self.shared).await;
+    }
+
+    pub(crate) fn report_activity(&self) {
+        self.shared.activity.notify_one();
+    }
+}
+
+impl Drop for SyncEngine {
+    fn drop(&mut self) {
+        self.task.abort();
+        if let Some(runner) = &self.runner {
+            runner.abort();
+        }
+    }
+}
+
+// the client is only locked while a sync runs. it holds the engine and so this task, only a
+// weak reference goes the other way: once the app lets go of the client, this stops
+async fn run(shared: Arc<Shared>, client: Weak<Mutex<ConvoClient>>) {
+    loop {
+        wait_due(&shared).await;
+        let Some(client) = client.upgrade() else {
+            return;
+        };
+        client.lock().await.scheduled_sync().await;
+    }
+}
+
+async fn schedule(
+    shared: Arc<Shared>,
+    transport: Arc<dyn Transport>,
+    user_id: String,
+    push: bool,
+    config: SyncConfig,
+) {
+    let mut interval = config.min_interval;
+    let mut stream: Option<EventStream> = None;
+
+    loop {
+        if push && stream.is_none() {
+            // only listening for work, this one doesn't keep us online:
+            stream = transport
+                .events(OpenEvents::new(user_id.clone(), None))
+                .await
+                .ok();
+            if stream.is_some() {
+                // anything that happened while we weren't listening:
+                mark_due(&shared);
+            }
+        }
+        let wait = if stream.is_some() {
+            config.push_interval
+        } else {
+            interval
+        };
+
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {
+                interval = (interval * 2).min(config.max_interval);
+            }
+            event = next_push(&mut stream) => match event {
+                Ok(Some(PushEvent::GroupUpdated { .. } | PushEvent::MailboxUpdated { .. })) => {}
+                Ok(Some(_)) => continue,
+                Ok(None) | Err(_) => {
+                    debug!("event stream closed, polling until it's back");
+                    stream = None;
+                    // don't hammer a server that just went away:
+                    tokio::time::sleep(interval).await;
+                    continue;
+                }
+            },
+            _ = shared.activity.notified() => {
+                interval = config.min_interval;
+                continue;
+            }
+        }
+
+        mark_due(&shared);
+    }
+}
+
+async fn wait_due(shared: &Shared) {
+    while !shared.due.swap(false, Ordering::SeqCst) {
+        shared.wake.notified().await;
+    }
+}
+
+fn mark_due(shared: &Shared) {
+    shared.due.store(true, Ordering::SeqCst);
+    shared.wake.notify_one();
+}
+
+async fn next_push(stream: &mut Option<EventStream>) -> Result<Option<PushEvent>> {
+    match stream {
+        Some(stream) => stream.next_event().await,
+        None => std::future::pending().await,
+    }
+}
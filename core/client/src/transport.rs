@@ -0,0 +1,418 @@
+// src/transport.rs
+
+// how `ConvoClient` reaches the delivery service. every call the client makes goes through
+// `Transport`, taking and returning the protocol's own request / response types.
+// `HttpTransport` talks to a server over its http api, `LocalTransport` (see local.rs) calls a
+// `ConvoServer` in the same process.
+
+use std::fmt;
+use std::sync::{PoisonError, RwLock};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::header::{ACCEPT, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, RETRY_AFTER};
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::warn;
+
+use skychat_protocol::codec::{self, WireFormat, COMPRESSION_THRESHOLD, DEFLATE_ENCODING};
+use skychat_protocol::{
+    features, AcceptInvite, AccountExport, ApiError, Capabilities, Connect, CreateAccount,
+    CreateGroup, DeleteAccount, ExportAccount, GetGroupIndex, GetMessages, GetPresence,
+    GetProfiles, GetUserKeys, GroupIndexResponse, InviteUser, KeyPackageStock, MessagesResponse, OpenEvents,
+    PresenceResponse, Profile, ProfilesResponse, ReaddUser, Register, RemoveUser, RequestRejoin, SearchUsers,
+    SearchUsersResponse, SendMessage, SetPresence, Sync as SyncRequest, SyncResponse,
+    UploadKeyPackages, UserKeysResponse, UsersResponse, Versioned, API_PREFIX, LEGACY_API_PREFIX,
+};
+
+use crate::events::EventStream;
+
+// rate limited requests are retried this many times, as long as the server asks us
+// to wait no longer than MAX_RETRY_AFTER seconds:
+const MAX_RATE_LIMIT_RETRIES: u32 = 3;
+const MAX_RETRY_AFTER: u64 = 30;
+
+#[async_trait]
+pub trait Transport: Send + Sync {
+    // what the server supports, None for servers that predate capabilities
+    async fn capabilities(&self) -> Result<Option<Capabilities>>;
+
+    async fn create_account(&self, request: CreateAccount) -> Result<()>;
+    async fn delete_account(&self, request: DeleteAccount) -> Result<()>;
+    async fn export_account(&self, request: ExportAccount) -> Result<AccountExport>;
+    async fn connect(&self, request: Connect) -> Result<()>;
+    async fn upload_key_packages(&self, request: UploadKeyPackages) -> Result<KeyPackageStock>;
+
+    async fn list_users(&self) -> Result<UsersResponse>;
+    async fn register(&self, request: Register) -> Result<Profile>;
+    async fn search_users(&self, request: SearchUsers) -> Result<SearchUsersResponse>;
+    async fn get_profiles(&self, request: GetProfiles) -> Result<ProfilesResponse>;
+
+    async fn set_presence(&self, request: SetPresence) -> Result<()>;
+    async fn get_presence(&self, request: GetPresence) -> Result<PresenceResponse>;
+    async fn events(&self, request: OpenEvents) -> Result<EventStream>;
+
+    async fn create_group(&self, request: CreateGroup) -> Result<()>;
+    async fn get_user_keys(&self, request: GetUserKeys) -> Result<UserKeysResponse>;
+    async fn invite_user(&self, request: InviteUser) -> Result<()>;
+    async fn remove_user(&self, request: RemoveUser) -> Result<()>;
+    async fn request_rejoin(&self, request: RequestRejoin) -> Result<()>;
+    async fn readd_user(&self, request: ReaddUser) -> Result<()>;
+    async fn accept_invite(&self, request: AcceptInvite) -> Result<()>;
+
+    async fn get_new_messages(&self, request: GetMessages) -> Result<MessagesResponse>;
+    async fn group_index(&self, request: GetGroupIndex) -> Result<GroupIndexResponse>;
+    async fn sync(&self, request: SyncRequest) -> Result<SyncResponse>;
+    async fn send_message(&self, request: SendMessage) -> Result<()>;
+}
+
+/// A request the server refused, with its reason if it sent one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestFailed {
+    pub status: u16,
+    pub error: Option<ApiError>,
+}
+
+impl RequestFailed {
+    // the error code the server sent, e.g. `error_codes::CONFLICT`
+    pub fn code(&self) -> Option<&str> {
+        self.error.as_ref().map(|error| error.code.as_str())
+    }
+}
+
+// e.g. "Not a member of this group (403 Forbidden)"
+impl fmt::Display for RequestFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = reqwest::StatusCode::from_u16(self.status)
+            .map(|status| status.to_string())
+            .unwrap_or_else(|_| self.status.to_string());
+
+        match &self.error {
+            Some(ApiError {
+                message,
+                retry_after: Some(retry_after),
+                ..
+            }) => write!(f, "{} ({}), retry in {}s", message, status, retry_after),
+            Some(error) => write!(f, "{} ({})", error.message, status),
+            None => write!(f, "{}", status),
+        }
+    }
+}
+
+impl std::error::Error for RequestFailed {}
+
+// the routes and encoding picked once the server's capabilities are known
+struct Negotiated {
+    capabilities: Option<Capabilities>,
+    api_prefix: &'static str,
+}
+
+pub struct HttpTransport {
+    http: reqwest::Client,
+    address: String,
+    negotiated: RwLock<Negotiated>,
+}
+
+impl HttpTransport {
+    pub fn new(address: String) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            address,
+            negotiated: RwLock::new(Negotiated {
+                capabilities: None,
+                api_prefix: API_PREFIX,
+            }),
+        }
+    }
+
+    fn url(&self, route: &str) -> String {
+        let api_prefix = self
+            .negotiated
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .api_prefix;
+        format!("{}{}/{}", self.address, api_prefix, route)
+    }
+
+    fn supports(&self, feature: &str) -> bool {
+        self.negotiated
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .capabilities
+            .as_ref()
+            .is_some_and(|capabilities| capabilities.supports(feature))
+    }
+
+    // attach a versioned request body in the negotiated format (cbor if the server speaks it,
+    // json otherwise), deflated if it's worth it, and ask for the response in the same format:
+    fn encode_body<T: Serialize>(
+        &self,
+        request: reqwest::RequestBuilder,
+        body: &T,
+    ) -> Result<reqwest::RequestBuilder> {
+        let format = if self.supports(features::CBOR) {
+            WireFormat::Cbor
+        } else {
+            WireFormat::Json
+        };
+        let mut bytes = codec::encode(&Versioned::new(body), format)?;
+        let mut request = request
+            .header(CONTENT_TYPE, format.content_type())
+            .header(ACCEPT, format.content_type());
+
+        if self.supports(features::DEFLATE) {
+            request = request.header(ACCEPT_ENCODING, DEFLATE_ENCODING);
+            if bytes.len() >= COMPRESSION_THRESHOLD {
+                bytes = codec::compress(&bytes)?;
+                request = request.header(CONTENT_ENCODING, DEFLATE_ENCODING);
+            }
+        }
+
+        Ok(request.body(bytes))
+    }
+
+    async fn decode_response<T: DeserializeOwned>(response: reqwest::Response) -> Result<T> {
+        let headers = response.headers();
+        let format = WireFormat::from_media_type(
+            headers
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok()),
+        );
+        let compressed = headers
+            .get(CONTENT_ENCODING)
+            .is_some_and(|value| value == DEFLATE_ENCODING);
+
+        let bytes = response
+            .bytes()
+            .await
+            .context("Failed to read response body")?;
+
+        if compressed {
+            codec::decode(&codec::decompress(&bytes)?, format)
+        } else {
+            codec::decode(&bytes, format)
+        }
+    }
+
+    // turns a failed response into a `RequestFailed`
+    async fn check(response: reqwest::Response) -> Result<reqwest::Response> {
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let error = Self::decode_response::<ApiError>(response).await.ok();
+        Err(RequestFailed {
+            status: status.as_u16(),
+            error,
+        }
+        .into())
+    }
+
+    // sends the request, waiting out rate limit (429) responses for as long as the server's
+    // Retry-After asks. the 429 comes back as-is once we run out of retries or it's too long a wait
+    async fn send_with_retry(
+        mut request: reqwest::RequestBuilder,
+    ) -> reqwest::Result<reqwest::Response> {
+        let mut retries = 0;
+        loop {
+            let next = request.try_clone();
+            let response = request.send().await?;
+
+            if response.status() != reqwest::StatusCode::TOO_MANY_REQUESTS
+                || retries >= MAX_RATE_LIMIT_RETRIES
+            {
+                return Ok(response);
+            }
+
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<u64>().ok())
+                .unwrap_or(1);
+
+            match next {
+                Some(next) if retry_after <= MAX_RETRY_AFTER => {
+                    warn!(retry_after, retries, "rate limited, retrying");
+                    tokio::time::sleep(std::time::Duration::from_secs(retry_after)).await;
+                    request = next;
+                    retries += 1;
+                }
+                _ => return Ok(response),
+            }
+        }
+    }
+
+    async fn send<B: Serialize + Sync>(&self, route: &str, body: &B) -> Result<reqwest::Response> {
+        let request = self.encode_body(self.http.post(self.url(route)), body)?;
+        let response = Self::send_with_retry(request)
+            .await
+            .with_context(|| format!("Failed to send {} request", route))?;
+        Self::check(response).await
+    }
+
+    async fn post<B: Serialize + Sync, R: DeserializeOwned>(
+        &self,
+        route: &str,
+        body: &B,
+    ) -> Result<R> {
+        let response = self.send(route, body).await?;
+        Self::decode_response(response)
+            .await
+            .context("Failed to parse response data")
+    }
+
+    // for routes that answer with an empty body:
+    async fn post_unit<B: Serialize + Sync>(&self, route: &str, body: &B) -> Result<()> {
+        self.send(route, body).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Transport for HttpTransport {
+    // also picks the routes to use: the versioned ones if the server has them,
+    // the unversioned /api otherwise
+    async fn capabilities(&self) -> Result<Option<Capabilities>> {
+        let response = self
+            .http
+            .get(format!(
+                "{}{}/capabilities",
+                self.address, LEGACY_API_PREFIX
+            ))
+            .send()
+            .await
+            .context("Failed to send capabilities request")?;
+
+        // the server predates versioning, fall back to its unversioned routes:
+        let capabilities = if response.status() == reqwest::StatusCode::NOT_FOUND {
+            None
+        } else {
+            let response = Self::check(response).await?;
+            Some(
+                response
+                    .json::<Capabilities>()
+                    .await
+                    .context("Failed to parse capabilities")?,
+            )
+        };
+
+        let mut negotiated = self
+            .negotiated
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        negotiated.api_prefix = match &capabilities {
+            Some(capabilities) if capabilities.supports(features::VERSIONED_API) => API_PREFIX,
+            _ => LEGACY_API_PREFIX,
+        };
+        negotiated.capabilities = capabilities.clone();
+        Ok(capabilities)
+    }
+
+    async fn create_account(&self, request: CreateAccount) -> Result<()> {
+        self.post_unit("create_account", &request).await
+    }
+
+    async fn delete_account(&self, request: DeleteAccount) -> Result<()> {
+        self.post_unit("delete_account", &request).await
+    }
+
+    async fn export_account(&self, request: ExportAccount) -> Result<AccountExport> {
+        self.post("export_account", &request).await
+    }
+
+    async fn connect(&self, request: Connect) -> Result<()> {
+        self.post_unit("connect", &request).await
+    }
+
+    async fn upload_key_packages(&self, request: UploadKeyPackages) -> Result<KeyPackageStock> {
+        self.post("upload_key_packages", &request).await
+    }
+
+    async fn list_users(&self) -> Result<UsersResponse> {
+        let response = self
+            .http
+            .get(self.url("list_users"))
+            .send()
+            .await
+            .context("Failed to send list_users request")?;
+        Self::decode_response(Self::check(response).await?)
+            .await
+            .context("Failed to parse response data")
+    }
+
+    async fn register(&self, request: Register) -> Result<Profile> {
+        self.post("register", &request).await
+    }
+
+    async fn search_users(&self, request: SearchUsers) -> Result<SearchUsersResponse> {
+        self.post("search_users", &request).await
+    }
+
+    async fn get_profiles(&self, request: GetProfiles) -> Result<ProfilesResponse> {
+        self.post("get_profiles", &request).await
+    }
+
+    async fn set_presence(&self, request: SetPresence) -> Result<()> {
+        self.post_unit("presence", &request).await
+    }
+
+    async fn get_presence(&self, request: GetPresence) -> Result<PresenceResponse> {
+        self.post("get_presence", &request).await
+    }
+
+    async fn events(&self, request: OpenEvents) -> Result<EventStream> {
+        let response = self
+            .http
+            .get(self.url("events"))
+            .query(&request)
+            .send()
+            .await
+            .context("Failed to open event stream")?;
+        Ok(EventStream::new(Self::check(response).await?))
+    }
+
+    async fn create_group(&self, request: CreateGroup) -> Result<()> {
+        self.post_unit("create_group", &request).await
+    }
+
+    async fn get_user_keys(&self, request: GetUserKeys) -> Result<UserKeysResponse> {
+        self.post("get_user_keys", &request).await
+    }
+
+    async fn invite_user(&self, request: InviteUser) -> Result<()> {
+        self.post_unit("invite_user", &request).await
+    }
+
+    async fn remove_user(&self, request: RemoveUser) -> Result<()> {
+        self.post_unit("remove_user", &request).await
+    }
+
+    async fn request_rejoin(&self, request: RequestRejoin) -> Result<()> {
+        self.post_unit("request_rejoin", &request).await
+    }
+
+    async fn readd_user(&self, request: ReaddUser) -> Result<()> {
+        self.post_unit("readd_user", &request).await
+    }
+
+    async fn accept_invite(&self, request: AcceptInvite) -> Result<()> {
+        self.post_unit("accept_invite", &request).await
+    }
+
+    async fn get_new_messages(&self, request: GetMessages) -> Result<MessagesResponse> {
+        self.post("get_new_messages", &request).await
+    }
+
+    async fn group_index(&self, request: GetGroupIndex) -> Result<GroupIndexResponse> {
+        self.post("group_index", &request).await
+    }
+
+    async fn sync(&self, request: SyncRequest) -> Result<SyncResponse> {
+        self.post("sync", &request).await
+    }
+
+    async fn send_message(&self, request: SendMessage) -> Result<()> {
+        self.post_unit("send_message", &request).await
+    }
+}
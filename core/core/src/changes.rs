@@ -0,0 +1,191 @@
+// src/changes.rs
+
+// what changed in a manager between two points in time, as typed events.
+// take a `Snapshot` before processing a sync and hand it to `ConvoManager::changes_since`
+// afterwards, instead of every app diffing groups and messages itself.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::forks::Fork;
+use crate::manager::{ConvoManager, DeliveryState};
+
+type GroupId = Vec<u8>;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SyncEvent {
+    NewMessage {
+        group_id: GroupId,
+        sender_id: String,
+        text: String,
+        timestamp: u64,
+    },
+    // `welcome_message` is what `ConvoManager::accept_pending_invite` takes
+    InviteReceived {
+        group_name: String,
+        welcome_message: Vec<u8>,
+    },
+    MemberJoined {
+        group_id: GroupId,
+        user_id: String,
+    },
+    MemberLeft {
+        group_id: GroupId,
+        user_id: String,
+    },
+    EpochChanged {
+        group_id: GroupId,
+        epoch: u64,
+    },
+    GroupRenamed {
+        group_id: GroupId,
+        name: String,
+    },
+    // one of our own messages moved along, see `MessageItem::local_id`
+    Delivery {
+        group_id: GroupId,
+        local_id: u64,
+        state: DeliveryState,
+    },
+    // a message we couldn't process and won't try again, e.g. one from an epoch whose commit
+    // never arrived
+    MessageDropped {
+        group_id: GroupId,
+        global_index: u64,
+        reason: String,
+    },
+    // messages for `epoch` came from a copy of the group that split from ours, see `forks`.
+    // whoever is on the other copy can't be told from them: someone has to send us (or them) a
+    // fresh Welcome
+    ForkDetected {
+        group_id: GroupId,
+        epoch: u64,
+        messages: usize,
+    },
+    // a sync failed, it's retried on the next one
+    SyncError {
+        error: String,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct GroupSnapshot {
+    name: String,
+    epoch: u64,
+    members: Vec<String>,
+    messages: usize,
+    fork: Option<Fork>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    groups: HashMap<GroupId, GroupSnapshot>,
+    pending_invites: usize,
+    dropped: usize,
+}
+
+impl ConvoManager {
+    pub fn snapshot(&self) -> Snapshot {
+        let groups = self
+            .groups
+            .iter()
+            .map(|(group_id, group)| {
+                let snapshot = GroupSnapshot {
+                    name: group.name.clone(),
+                    epoch: group.mls_group.epoch().as_u64(),
+                    members: self.group_get_member_ids(group_id).unwrap_or_default(),
+                    messages: group.decrypted.len(),
+                    fork: group.fork.clone(),
+                };
+                (group_id.clone(), snapshot)
+            })
+            .collect();
+
+        Snapshot {
+            groups,
+            pending_invites: self.pending_invites.len(),
+            dropped: self.dropped.len(),
+        }
+    }
+
+    // groups we joined since the snapshot don't report anything, there's nothing to compare to
+    pub fn changes_since(&self, before: &Snapshot) -> Vec<SyncEvent> {
+        let mut events = vec![];
+
+        // invites are only ever appended until they're accepted or rejected:
+        for invite in self.pending_invites.iter().skip(before.pending_invites) {
+            events.push(SyncEvent::InviteReceived {
+                group_name: invite.group_name.clone(),
+                welcome_message: invite.welcome_message.clone(),
+            });
+        }
+
+        for dropped in self.dropped.iter().skip(before.dropped) {
+            events.push(SyncEvent::MessageDropped {
+                group_id: dropped.group_id.clone(),
+                global_index: dropped.global_index,
+                reason: dropped.reason.clone(),
+            });
+        }
+
+        for (group_id, group) in &self.groups {
+            let Some(old) = before.groups.get(group_id) else {
+                continue;
+            };
+
+            if group.name != old.name {
+                events.push(SyncEvent::GroupRenamed {
+                    group_id: group_id.clone(),
+                    name: group.name.clone(),
+                });
+            }
+
+            let epoch = group.mls_group.epoch().as_u64();
+            if epoch != old.epoch {
+                let members = self.group_get_member_ids(group_id).unwrap_or_default();
+                for user_id in members.iter().filter(|id| !old.members.contains(id)) {
+                    events.push(SyncEvent::MemberJoined {
+                        group_id: group_id.clone(),
+                        user_id: user_id.clone(),
+                    });
+                }
+                for user_id in old.members.iter().filter(|id| !members.contains(id)) {
+                    events.push(SyncEvent::MemberLeft {
+                        group_id: group_id.clone(),
+                        user_id: user_id.clone(),
+                    });
+                }
+                events.push(SyncEvent::EpochChanged {
+                    group_id: group_id.clone(),
+                    epoch,
+                });
+            }
+
+            if let Some(fork) = group
+                .fork
+                .as_ref()
+                .filter(|fork| Some(*fork) != old.fork.as_ref())
+            {
+                events.push(SyncEvent::ForkDetected {
+                    group_id: group_id.clone(),
+                    epoch: fork.epoch,
+                    messages: fork.messages,
+                });
+            }
+
+            // our own messages report through `SyncEvent::Delivery` instead:
+            let new_messages = group.decrypted.iter().skip(old.messages);
+            for message in new_messages.filter(|message| message.local_id.is_none()) {
+                events.push(SyncEvent::NewMessage {
+                    group_id: group_id.clone(),
+                    sender_id: message.sender_id.clone(),
+                    text: message.text.clone(),
+                    timestamp: message.timestamp,
+                });
+            }
+        }
+
+        events
+    }
+}
@@ -0,0 +1,84 @@
+// src/forks.rs
+
+// noticing that our copy of a group and someone else's have split, e.g. two commits merged in
+// the same epoch or a bad merge. every message we send carries a hash of our epoch
+// authenticator in its authenticated data, which a PrivateMessage keeps in the clear: a message
+// for our epoch whose hash differs comes from a diverged copy, it won't decrypt and there's no
+// point in trying it again. the tag is readable by anyone holding the ciphertext, so it names no
+// one, and a forked message can't be decrypted to learn its sender: a fork is recorded by epoch
+// alone, and only once the message has failed. the way back is rejoining from a fresh Welcome, which
+// `ConvoManager::process_invite` takes for a group we're already in.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::manager::ConvoManager;
+use crate::utils::{self, ShortId};
+
+type GroupId = Vec<u8>;
+
+// what a message says about the state it was sent from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EpochTag {
+    pub epoch: u64,
+    pub authenticator_hash: Vec<u8>,
+}
+
+// messages for `epoch` that came from a state of the group that isn't ours
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fork {
+    pub epoch: u64,
+    pub messages: usize,
+}
+
+impl ConvoManager {
+    pub fn epoch_tag(&self, group_id: &GroupId) -> Result<EpochTag> {
+        let group = self
+            .groups
+            .get(group_id)
+            .context(format!("Group not found for ID: {:?}", group_id))?;
+
+        Ok(EpochTag {
+            epoch: group.mls_group.epoch().as_u64(),
+            authenticator_hash: self.group_authenticator_hash(group_id)?,
+        })
+    }
+
+    // true if the message says it was sent from a copy of the group that split from ours.
+    // messages from other epochs, and untagged ones from older clients, can't be told apart and
+    // aren't. the tag isn't authenticated, so this alone proves nothing: see `record_fork`
+    pub(crate) fn check_epoch_tag(&self, group_id: &GroupId, serialized: &[u8]) -> bool {
+        let Some(tag) =
+            utils::peek_aad(serialized).and_then(|aad| bincode::deserialize::<EpochTag>(&aad).ok())
+        else {
+            return false;
+        };
+        let (Some(group), Ok(ours)) = (
+            self.groups.get(group_id),
+            self.group_authenticator_hash(group_id),
+        ) else {
+            return false;
+        };
+
+        tag.epoch == group.mls_group.epoch().as_u64() && tag.authenticator_hash != ours
+    }
+
+    // a message whose tag didn't match our state also failed to decrypt, so it really is from
+    // another copy of the group
+    pub(crate) fn record_fork(&mut self, group_id: &GroupId) {
+        let Some(group) = self.groups.get_mut(group_id) else {
+            return;
+        };
+
+        let epoch = group.mls_group.epoch().as_u64();
+        let fork = group.fork.get_or_insert(Fork { epoch, messages: 0 });
+        if fork.epoch != epoch {
+            *fork = Fork { epoch, messages: 0 };
+        }
+        if fork.messages == 0 {
+            warn!(group = %ShortId(group_id), epoch, "our copy of the group split from someone else's");
+        }
+        fork.messages += 1;
+    }
+}
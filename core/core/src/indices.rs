@@ -0,0 +1,105 @@
+// src/indices.rs
+
+// which of a group's global indices we've dealt with (applied, or given up on).
+// anything past `contiguous` that isn't in `above` is a gap: a sync asks the server for
+// everything after `contiguous`, so missing messages are fetched again along with the
+// ones after them (which are then skipped as already applied).
+
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AppliedIndices {
+    contiguous: u64,      // every index up to and including this one is dealt with
+    above: BTreeSet<u64>, // dealt with, past a gap
+}
+
+impl AppliedIndices {
+    // e.g. joining a group at `index`, nothing before it is ours to apply
+    pub fn up_to(index: u64) -> Self {
+        Self {
+            contiguous: index,
+            above: BTreeSet::new(),
+        }
+    }
+
+    // what to ask the server for messages after
+    pub fn contiguous(&self) -> u64 {
+        self.contiguous
+    }
+
+    pub fn highest(&self) -> u64 {
+        self.above.last().copied().unwrap_or(self.contiguous)
+    }
+
+    pub fn contains(&self, index: u64) -> bool {
+        index <= self.contiguous || self.above.contains(&index)
+    }
+
+    pub fn insert(&mut self, index: u64) {
+        if self.contains(index) {
+            return;
+        }
+        self.above.insert(index);
+        // close the gap if that was the last one missing:
+        while self.above.remove(&(self.contiguous + 1)) {
+            self.contiguous += 1;
+        }
+    }
+
+    // forgets everything after `index`, and counts everything up to it as dealt with
+    pub fn reset_to(&mut self, index: u64) {
+        self.contiguous = index;
+        self.above.retain(|&applied| applied > index);
+        while self.above.remove(&(self.contiguous + 1)) {
+            self.contiguous += 1;
+        }
+    }
+
+    // the missing ranges (inclusive) up to `until`
+    pub fn gaps(&self, until: u64) -> Vec<(u64, u64)> {
+        let mut gaps = vec![];
+        let mut next = self.contiguous + 1;
+        for &applied in self.above.range(next..) {
+            if applied > until {
+                break;
+            }
+            if applied > next {
+                gaps.push((next, applied - 1));
+            }
+            next = applied + 1;
+        }
+        if next <= until {
+            gaps.push((next, until));
+        }
+        gaps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gaps_close_as_missing_indices_arrive() {
+        let mut applied = AppliedIndices::up_to(2);
+        applied.insert(5);
+        applied.insert(6);
+        applied.insert(9);
+
+        assert_eq!(applied.contiguous(), 2);
+        assert_eq!(applied.highest(), 9);
+        assert_eq!(applied.gaps(10), [(3, 4), (7, 8), (10, 10)]);
+        assert!(applied.contains(6) && !applied.contains(7));
+
+        applied.insert(3);
+        applied.insert(4);
+        assert_eq!(applied.contiguous(), 6);
+        assert_eq!(applied.gaps(9), [(7, 8)]);
+
+        applied.reset_to(8);
+        assert_eq!(applied.contiguous(), 9);
+        assert!(applied.gaps(9).is_empty());
+    }
+}
@@ -0,0 +1,151 @@
+// src/key_packages.rs
+
+// the key packages we've published and the private keys behind them. building a package stores
+// its private keys (in our storage, and so in save_state) until an invite using it is processed,
+// which for most packages never happens: they're replaced by the next rotation or expire on the
+// server. those keys are deleted here, so old init keys don't pile up and a rotation actually
+// retires them.
+
+use std::collections::HashSet;
+
+use anyhow::{Context, Result};
+use openmls::prelude::tls_codec::Deserialize as _;
+use openmls::prelude::*;
+use openmls_traits::storage::StorageProvider as _;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::manager::ConvoManager;
+use crate::utils;
+
+// an inviter may have fetched a package just before we replaced it, its welcome can still be
+// on the way for this long
+pub const REPLACED_KEY_PACKAGE_GRACE: u64 = 60 * 60 * 24;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PublishedKeyPackage {
+    pub hash_ref: KeyPackageRef,
+    pub expires: u64, // unix seconds
+    // when a rotation replaced it on the server:
+    pub replaced_at: Option<u64>,
+}
+
+impl ConvoManager {
+    // records key packages the server now holds. `replace` if they replace everything
+    // published before (a rotation)
+    pub fn key_packages_published(
+        &mut self,
+        key_packages: &[Vec<u8>],
+        replace: bool,
+    ) -> Result<()> {
+        let now = utils::current_timestamp();
+        if replace {
+            for published in self.published_key_packages.iter_mut() {
+                published.replaced_at.get_or_insert(now);
+            }
+            self.last_key_package_rotation = now;
+        }
+
+        for serialized in key_packages {
+            let key_package = KeyPackageIn::tls_deserialize_exact(serialized)
+                .context("Error deserializing key package")?
+                .validate(self.provider.crypto(), ProtocolVersion::Mls10)
+                .context("Invalid KeyPackage")?;
+            let (_not_before, expires) = utils::key_package_lifetime(&key_package)?;
+
+            self.published_key_packages.push(PublishedKeyPackage {
+                hash_ref: key_package
+                    .hash_ref(self.provider.crypto())
+                    .context("Failed to hash key package")?,
+                expires,
+                replaced_at: None,
+            });
+        }
+        Ok(())
+    }
+
+    // deletes the private keys of packages that expired, or were replaced more than
+    // REPLACED_KEY_PACKAGE_GRACE ago. packages a pending invite was made with are kept until
+    // it's accepted. returns how many were deleted
+    pub fn prune_key_packages(&mut self) -> Result<usize> {
+        let now = utils::current_timestamp();
+        let invited = self.pending_invite_key_packages();
+
+        let (retired, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut self.published_key_packages)
+            .into_iter()
+            .partition(|published| {
+                let replaced = published
+                    .replaced_at
+                    .is_some_and(|at| now.saturating_sub(at) >= REPLACED_KEY_PACKAGE_GRACE);
+                (replaced || published.expires <= now) && !invited.contains(&published.hash_ref)
+            });
+        self.published_key_packages = kept;
+
+        // packages already used for an invite are gone from storage, deleting them is a no-op:
+        for published in &retired {
+            self.provider
+                .storage()
+                .delete_key_package(&published.hash_ref)
+                .map_err(|e| anyhow::anyhow!("Failed to delete key package: {:?}", e))?;
+        }
+        if !retired.is_empty() {
+            debug!(count = retired.len(), "deleted retired key packages");
+        }
+        Ok(retired.len())
+    }
+
+    // the packages the welcomes we haven't accepted yet were made for
+    fn pending_invite_key_packages(&self) -> HashSet<KeyPackageRef> {
+        self.pending_invites
+            .iter()
+            .filter_map(|invite| MlsMessageIn::tls_deserialize_exact(&invite.welcome_message).ok())
+            .filter_map(|message| match message.extract() {
+                MlsMessageBodyIn::Welcome(welcome) => Some(welcome),
+                _ => None,
+            })
+            .flat_map(|welcome| {
+                welcome
+                    .secrets()
+                    .iter()
+                    .map(|secrets| secrets.new_member())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaced_and_expired_key_packages_lose_their_keys() {
+        let mut manager = ConvoManager::init("alice".to_string());
+        let old = manager.generate_key_packages(2).unwrap();
+        manager.key_packages_published(&old, true).unwrap();
+        let new = manager.generate_key_packages(1).unwrap();
+        manager.key_packages_published(&new, true).unwrap();
+
+        // still within the grace period, an invite could be using them:
+        assert_eq!(manager.prune_key_packages().unwrap(), 0);
+
+        let now = utils::current_timestamp();
+        for published in manager.published_key_packages.iter_mut() {
+            if let Some(replaced_at) = published.replaced_at.as_mut() {
+                *replaced_at = now - REPLACED_KEY_PACKAGE_GRACE;
+            }
+        }
+        assert_eq!(manager.prune_key_packages().unwrap(), 2);
+
+        manager.published_key_packages[0].expires = now;
+        assert_eq!(manager.prune_key_packages().unwrap(), 1);
+        assert!(manager.published_key_packages.is_empty());
+
+        let stored = manager.provider.storage().values.read().unwrap().len();
+        let fresh = ConvoManager::init("alice".to_string());
+        assert_eq!(
+            stored,
+            fresh.provider.storage().values.read().unwrap().len()
+        );
+    }
+}
@@ -1,2 +1,6 @@
+pub mod changes;
+pub mod forks;
+pub mod indices;
+pub mod key_packages;
 pub mod manager;
 pub mod utils;
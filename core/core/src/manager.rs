@@ -6,46 +6,213 @@ use base64::Engine;
 use openmls::prelude::tls_codec::{Deserialize as _, Serialize as _};
 use openmls::prelude::{tls_codec::*, *};
 use openmls_basic_credential::SignatureKeyPair;
+use openmls_traits::signatures::Signer as _;
 use openmls_rust_crypto::OpenMlsRustCrypto;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use std::collections::HashMap;
-
-use crate::utils::{extract_sender_id_from_credential, generate_credential_with_key, generate_key_package};
+use std::collections::{HashMap, HashSet};
+
+use crate::utils::{
+    self, extract_sender_id_from_credential, generate_credential_with_key, generate_key_package,
+    Redacted, ShortId,
+};
+use crate::forks::Fork;
+use crate::indices::AppliedIndices;
+use crate::key_packages::PublishedKeyPackage;
+use tracing::{debug, instrument, warn};
 use openmls::prelude::{MlsMessageBodyIn, MlsMessageIn};
 
+// the wire types live in the protocol crate, re-exported here so existing paths keep working:
+pub use skychat_protocol::{ConvoInvite, ConvoMessage};
+use skychat_protocol::{AccountProof, SyncResponse};
+
 type GroupId = Vec<u8>;
 type SerializedMessage = Vec<u8>;
 type SerializedProposal = Vec<u8>;
 
+/// How long the key packages we publish stay valid for (seconds), 4 weeks.
+pub const DEFAULT_KEY_PACKAGE_LIFETIME: u64 = 60 * 60 * 24 * 28;
+/// Key packages claiming to be valid for longer than this (seconds) are rejected, ~3 months.
+pub const MAX_KEY_PACKAGE_LIFETIME: u64 = 60 * 60 * 24 * 28 * 3 + 60 * 60;
+/// A message that fails to process is fetched and tried again this many times before it's
+/// skipped, later messages in its group wait for it until then.
+pub const MAX_MESSAGE_ATTEMPTS: u32 = 3;
+/// Messages from an epoch we haven't reached yet are held (per group) until the commit that
+/// starts it is merged, at most this many of them...
+pub const MAX_BUFFERED_MESSAGES: usize = 100;
+/// ...and for at most this long (seconds).
+pub const MAX_BUFFERED_AGE: u64 = 60 * 15;
+
+/// How forgiving a group is towards messages arriving late or out of order, each of these
+/// keeps secrets around for longer than strictly needed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupSettings {
+    // epochs after a commit whose application messages can still be decrypted, so messages
+    // sent just before it aren't lost to members who merged it first:
+    pub max_past_epochs: usize,
+    // how far back (in a sender's messages of one epoch) a late message can be:
+    pub out_of_order_tolerance: u32,
+    // how many of a sender's messages can be skipped, e.g. ones the server dropped:
+    pub maximum_forward_distance: u32,
+}
+
+impl Default for GroupSettings {
+    fn default() -> Self {
+        Self {
+            max_past_epochs: 3,
+            out_of_order_tolerance: 5,
+            maximum_forward_distance: 1000,
+        }
+    }
+}
+
+impl GroupSettings {
+    // what groups were created and joined with before they were configurable
+    pub const LEGACY: Self = Self {
+        max_past_epochs: 0,
+        out_of_order_tolerance: 5,
+        maximum_forward_distance: 1000,
+    };
+
+    fn sender_ratchet(&self) -> SenderRatchetConfiguration {
+        SenderRatchetConfiguration::new(self.out_of_order_tolerance, self.maximum_forward_distance)
+    }
+
+    // welcomes carry the ratchet tree, an invite goes out before its commit is merged and the
+    // tree it would be exported from exists
+    pub fn join_config(&self) -> MlsGroupJoinConfig {
+        MlsGroupJoinConfig::builder()
+            .max_past_epochs(self.max_past_epochs)
+            .sender_ratchet_configuration(self.sender_ratchet())
+            .use_ratchet_tree_extension(true)
+            .build()
+    }
+
+    pub fn create_config(&self) -> MlsGroupCreateConfig {
+        MlsGroupCreateConfig::builder()
+            .max_past_epochs(self.max_past_epochs)
+            .sender_ratchet_configuration(self.sender_ratchet())
+            .use_ratchet_tree_extension(true)
+            .build()
+    }
+}
+
+/// Where one of our own messages is on its way to the group (or that it came from someone else).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeliveryState {
+    Composing, // shown locally, not handed over for sending yet
+    Queued,    // waiting to go out, e.g. while the server is unreachable
+    Sent { global_index: u64 },
+    Failed { reason: String }, // given up on, it can be queued again
+    Received,                  // someone else's message (or a local system note)
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MessageItem {
     pub text: String,
     pub sender_id: String,
     pub timestamp: u64,
+    #[serde(default = "received")]
+    pub state: DeliveryState,
+    // ties our own messages to their entry in the sender's outbox:
+    #[serde(default)]
+    pub local_id: Option<u64>,
+}
+
+fn received() -> DeliveryState {
+    DeliveryState::Received
+}
+
+// a message from an epoch we haven't reached yet, its index already counts as dealt with
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BufferedMessage {
+    pub global_index: u64,
+    pub epoch: u64,
+    pub message: SerializedMessage,
+    pub received_at: u64, // unix seconds
+}
+
+// a message we gave up on, see `SyncEvent::MessageDropped`
+#[derive(Debug, Clone)]
+pub struct DroppedMessage {
+    pub group_id: GroupId,
+    pub global_index: u64,
+    pub reason: String,
 }
 
 #[derive(Debug)]
 pub struct LocalGroup {
     pub id: Vec<u8>,
     pub name: String,
-    pub global_index: u64,
+    pub global_index: u64, // the highest index we've applied (or sent)
     pub mls_group: MlsGroup,
     pub decrypted: Vec<MessageItem>,
+    pub applied: AppliedIndices,
+    pub settings: GroupSettings,
+    // failed attempts at messages we haven't applied yet, by index:
+    attempts: HashMap<u64, u32>,
+    // oldest first:
+    pub buffered: Vec<BufferedMessage>,
+    // the one-time key package our pending commit uses, only used up once the commit is merged:
+    pending_init_key: Option<Vec<u8>>,
+    // our pending commit as posted, when the post failed without saying whether the server
+    // took it. the next sync settles it, see `group_commit_unconfirmed`
+    pub unconfirmed_commit: Option<Vec<u8>>,
+    // messages we've seen from a different copy of the group, see `forks`:
+    pub fork: Option<Fork>,
 }
 
 impl LocalGroup {
-    pub fn new(name: String, mls_group: MlsGroup) -> Self {
+    pub fn new(name: String, mls_group: MlsGroup, settings: GroupSettings) -> Self {
+        Self::joined_at(name, mls_group, settings, 0)
+    }
+
+    // everything up to `global_index` happened before we were in the group
+    pub fn joined_at(
+        name: String,
+        mls_group: MlsGroup,
+        settings: GroupSettings,
+        global_index: u64,
+    ) -> Self {
         Self {
             id: mls_group.group_id().to_vec(),
             name,
-            global_index: 0,
+            global_index,
             mls_group: mls_group,
             decrypted: Vec::new(),
+            applied: AppliedIndices::up_to(global_index),
+            settings,
+            attempts: HashMap::new(),
+            buffered: Vec::new(),
+            pending_init_key: None,
+            unconfirmed_commit: None,
+            fork: None,
         }
     }
+
+    pub fn mark_applied(&mut self, index: u64) {
+        self.applied.insert(index);
+        self.attempts.remove(&index);
+        self.global_index = self.global_index.max(index);
+    }
+}
+
+// where we are in a group, saved with the rest of the state so a restart doesn't refetch
+// (and fail to decrypt) everything
+#[derive(Serialize, Deserialize)]
+pub struct GroupProgress {
+    pub group_id: GroupId,
+    pub global_index: u64,
+    pub applied: AppliedIndices,
+    #[serde(default)]
+    pub buffered: Vec<BufferedMessage>,
+    #[serde(default)]
+    pub settings: Option<GroupSettings>,
+    #[serde(default)]
+    pub unconfirmed_commit: Option<Vec<u8>>,
 }
 
 pub struct ProcessedResults {
+    pub group_id: GroupId, // the group the message belonged to
     pub message: Option<String>,
     // pub welcome: Option<Vec<u8>>,
     pub invite: Option<ConvoInvite>,
@@ -58,35 +225,43 @@ pub struct SerializedCredentials {
     pub storage: HashMap<String, Vec<u8>>,
     pub group_names: Vec<String>,
     pub group_name_to_id: HashMap<String, GroupId>,
+    // init keys of one-time key packages we've already added to a group:
+    #[serde(default)]
+    pub used_init_keys: Vec<Vec<u8>>,
+    #[serde(default)]
+    pub group_progress: Vec<GroupProgress>,
+    #[serde(default)]
+    pub published_key_packages: Vec<PublishedKeyPackage>,
+    // unix seconds, 0 if we've never published a full pool:
+    #[serde(default)]
+    pub last_key_package_rotation: u64,
+    // the client's unsent messages (JSON, see skychat_client's outbox.rs), kept here so they're
+    // saved wherever the rest is. the manager itself leaves it empty, `ConvoClient::save_state`
+    // fills it in
+    #[serde(default)]
+    pub outbox: Vec<u8>,
     // pub sig_id_map: HashMap<Vec<u8>, String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-
-pub struct ConvoInvite {
-    pub group_name: String,
-    pub welcome_message: Vec<u8>,
-    pub ratchet_tree: Option<Vec<u8>>,
-    pub global_index: u64,
-    pub fanned: Option<Vec<u8>>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ConvoMessage {
-    pub global_index: u64,
-    pub unix_timestamp: u64,
-    pub encrypted: Option<Vec<u8>>,
-    pub invite: Option<ConvoInvite>,
-}
-
 pub struct ConvoManager {
     pub id: String,
-    provider: OpenMlsRustCrypto,
+    pub(crate) provider: OpenMlsRustCrypto,
     ciphersuite: Ciphersuite,
     signer: SignatureKeyPair,
     credential_with_key: CredentialWithKey,
     pub groups: HashMap<GroupId, LocalGroup>,
     pub pending_invites: Vec<ConvoInvite>,
+    // lifetime (seconds) of the key packages we generate:
+    pub key_package_lifetime: u64,
+    // what groups we create or join from now on start with:
+    pub group_settings: GroupSettings,
+    used_init_keys: HashSet<Vec<u8>>,
+    // what we've uploaded, until its private keys are deleted (see `key_packages`):
+    pub(crate) published_key_packages: Vec<PublishedKeyPackage>,
+    // when we last replaced everything we'd published (unix seconds, 0 for never):
+    pub last_key_package_rotation: u64,
+    // everything given up on since we started, only ever appended to:
+    pub dropped: Vec<DroppedMessage>,
     // pub sig_id_map: HashMap<Vec<u8>, String>,
 }
 
@@ -109,6 +284,12 @@ impl ConvoManager {
             credential_with_key: credential_with_key,
             groups: HashMap::new(),
             pending_invites: Vec::new(),
+            key_package_lifetime: DEFAULT_KEY_PACKAGE_LIFETIME,
+            group_settings: GroupSettings::default(),
+            used_init_keys: HashSet::new(),
+            published_key_packages: Vec::new(),
+            last_key_package_rotation: 0,
+            dropped: Vec::new(),
             // sig_id_map: HashMap::new(),
         }
     }
@@ -145,6 +326,22 @@ impl ConvoManager {
                 .iter()
                 .map(|(k, v)| (v.name.clone(), k.clone()))
                 .collect(),
+            used_init_keys: self.used_init_keys.iter().cloned().collect(),
+            group_progress: self
+                .groups
+                .iter()
+                .map(|(group_id, group)| GroupProgress {
+                    group_id: group_id.clone(),
+                    global_index: group.global_index,
+                    applied: group.applied.clone(),
+                    buffered: group.buffered.clone(),
+                    settings: Some(group.settings),
+                    unconfirmed_commit: group.unconfirmed_commit.clone(),
+                })
+                .collect(),
+            published_key_packages: self.published_key_packages.clone(),
+            last_key_package_rotation: self.last_key_package_rotation,
+            outbox: Vec::new(),
             // sig_id_map: self.sig_id_map.clone(),
         };
         Ok(serialized)
@@ -181,6 +378,10 @@ impl ConvoManager {
             .map_err(|e| anyhow::anyhow!("Failed to write to provider storage: {}", e))? =
             converted_storage;
 
+        self.used_init_keys = serialized.used_init_keys.into_iter().collect();
+        self.published_key_packages = serialized.published_key_packages;
+        self.last_key_package_rotation = serialized.last_key_package_rotation;
+
         // load the groups:
         for group_name in serialized.group_names {
             let group_id = serialized
@@ -195,20 +396,53 @@ impl ConvoManager {
             .context(format!("Failed to load group: {}", group_name))?;
 
             if let Some(group) = group {
-                self.groups
-                    .insert(group_id.clone(), LocalGroup::new(group_name, group));
+                // the MLS group keeps its own copy of the settings in storage:
+                self.groups.insert(
+                    group_id.clone(),
+                    LocalGroup::new(group_name, group, GroupSettings::LEGACY),
+                );
+            }
+        }
+
+        // state saved before progress was, starts over from index 0 like it used to:
+        for progress in serialized.group_progress {
+            if let Some(group) = self.groups.get_mut(&progress.group_id) {
+                group.global_index = progress.global_index;
+                group.applied = progress.applied;
+                group.buffered = progress.buffered;
+                group.settings = progress.settings.unwrap_or(GroupSettings::LEGACY);
+                group.unconfirmed_commit = progress.unconfirmed_commit;
             }
         }
 
         Ok(())
     }
 
+    // the public half of our credential's key, what the server checks `AccountProof`s with
+    pub fn signature_public_key(&self) -> Vec<u8> {
+        self.signer.public().to_vec()
+    }
+
+    // signs a request only the account's owner may make (e.g. deleting it)
+    pub fn account_proof(&self, action: &str) -> Result<AccountProof> {
+        let timestamp = utils::current_timestamp();
+        let signature = self
+            .signer
+            .sign(&AccountProof::payload(action, &self.id, timestamp))
+            .map_err(|e| anyhow::anyhow!("Failed to sign request: {:?}", e))?;
+        Ok(AccountProof {
+            timestamp,
+            signature,
+        })
+    }
+
     pub fn get_key_package(&self) -> Result<Vec<u8>> {
         let key_package = generate_key_package(
             self.ciphersuite,
             &self.provider,
             &self.signer,
             self.credential_with_key.clone(),
+            self.key_package_lifetime,
         );
 
         // serialize the key package:
@@ -221,6 +455,43 @@ impl ConvoManager {
         Ok(serialized_key_package)
     }
 
+    // a batch of one-time key packages to upload to the server.
+    // building them stores the matching private keys in our storage (and so in save_state),
+    // where they stay until an invite using the package is processed.
+    pub fn generate_key_packages(&self, count: usize) -> Result<Vec<Vec<u8>>> {
+        (0..count).map(|_| self.get_key_package()).collect()
+    }
+
+    // a key package the server can hand out again and again once the one-time ones run out,
+    // its private keys are kept after being used for an invite:
+    pub fn get_last_resort_key_package(&self) -> Result<Vec<u8>> {
+        let key_package = KeyPackage::builder()
+            .key_package_lifetime(Lifetime::new(self.key_package_lifetime))
+            // the leaf has to advertise the extension for the package to validate:
+            .leaf_node_capabilities(Capabilities::new(
+                None,
+                None,
+                Some(&[ExtensionType::LastResort]),
+                None,
+                None,
+            ))
+            .mark_as_last_resort()
+            .build(
+                self.ciphersuite,
+                &self.provider,
+                &self.signer,
+                self.credential_with_key.clone(),
+            )
+            .context("Error building last resort key package")?;
+
+        let serialized_key_package = key_package
+            .key_package()
+            .tls_serialize_detached()
+            .context("Error serializing key package")?;
+
+        Ok(serialized_key_package)
+    }
+
     pub fn process_raw_invite(
         &mut self,
         group_name: String,
@@ -254,7 +525,7 @@ impl ConvoManager {
         let mut ratchet_tree_deserialized: Option<RatchetTreeIn> = None;
 
         // if we have a ratchet tree:
-        if let Some(ratchet_tree) = invite.ratchet_tree {
+        if let Some(ratchet_tree) = invite.ratchet_tree.filter(|tree| !tree.is_empty()) {
             ratchet_tree_deserialized = Some(
                 RatchetTreeIn::tls_deserialize(&mut ratchet_tree.as_slice())
                     .context("Error deserializing ratchet tree")?,
@@ -273,17 +544,14 @@ impl ConvoManager {
         .context("Error creating a staged join from Welcome")?;
 
         let sender_lead_node = bob_staged_join.welcome_sender().context("Failed to get sender id")?;
-        let sender_id_bytes = sender_lead_node.credential().tls_serialize_detached().context("Failed to serialize sender id")?;
-        let sender_id = String::from_utf8(sender_id_bytes).context("Failed to convert sender id to string")?;
-        Ok(sender_id)
+        extract_sender_id_from_credential(sender_lead_node.credential().clone())
     }
 
     pub fn get_invite_sender_id_from_credential(&self, credential: Credential) -> Result<String> {
-        let sender_id_bytes = credential.tls_serialize_detached().context("Failed to serialize sender id")?;
-        let sender_id = String::from_utf8(sender_id_bytes).context("Failed to convert sender id to string")?;
-        Ok(sender_id)
+        extract_sender_id_from_credential(credential)
     }
 
+    #[instrument(skip_all, fields(user = %self.id, group_name = %invite.group_name))]
     pub fn process_invite(&mut self, invite: ConvoInvite) -> Result<GroupId> {
         
         // bob can now de-serialize the message as an [`MlsMessageIn`] ...
@@ -300,7 +568,7 @@ impl ConvoManager {
         let mut ratchet_tree_deserialized: Option<RatchetTreeIn> = None;
 
         // if we have a ratchet tree:
-        if let Some(ratchet_tree) = invite.ratchet_tree {
+        if let Some(ratchet_tree) = invite.ratchet_tree.filter(|tree| !tree.is_empty()) {
             ratchet_tree_deserialized = Some(
                 RatchetTreeIn::tls_deserialize(&mut ratchet_tree.as_slice())
                     .context("Error deserializing ratchet tree")?,
@@ -310,7 +578,7 @@ impl ConvoManager {
         // Now bob can build a staged join for the group in order to inspect the welcome
         let bob_staged_join = StagedWelcome::new_from_welcome(
             &self.provider,
-            &MlsGroupJoinConfig::default(),
+            &self.group_settings.join_config(),
             welcome,
             // The public tree is need and transferred out of band.
             // It is also possible to use the [`RatchetTreeExtension`]
@@ -319,7 +587,7 @@ impl ConvoManager {
         .context("Error creating a staged join from Welcome")?;
 
         let sender_lead_node = bob_staged_join.welcome_sender().context("Failed to get sender id")?;
-        let sender_id = sender_lead_node.credential().tls_serialize_detached().context("Failed to serialize sender id")?;
+        let sender_id = extract_sender_id_from_credential(sender_lead_node.credential().clone())?;
     
         // Finally, bob can create the group
         let new_group = bob_staged_join
@@ -335,37 +603,74 @@ impl ConvoManager {
         // }
 
         // create the group:
-        let group = LocalGroup {
-            id: new_group.group_id().to_vec(),
-            global_index: invite.global_index.clone(),
-            name: invite.group_name.clone(),
-            mls_group: new_group,
-            decrypted: Vec::new(),
-        };
+        let mut group = LocalGroup::joined_at(
+            invite.group_name.clone(),
+            new_group,
+            self.group_settings,
+            invite.global_index,
+        );
 
         let group_id = group.mls_group.group_id().to_vec();
 
+        // a fresh welcome for a group we're already in (e.g. after our copy of it forked)
+        // replaces the MLS state, what we've read and where we are in the group stay:
+        if let Some(old) = self.groups.remove(&group_id) {
+            group
+                .mls_group
+                .set_configuration(self.provider.storage(), &old.settings.join_config())
+                .map_err(|e| anyhow::anyhow!("Failed to store group settings: {:?}", e))?;
+            group.settings = old.settings;
+            group.name = old.name;
+            group.decrypted = old.decrypted;
+            group.global_index = old.global_index.max(invite.global_index);
+            group.applied = old.applied;
+            if group.applied.contiguous() < invite.global_index {
+                group.applied.reset_to(invite.global_index);
+            }
+            // whatever was waiting on the old state's epochs never will be read now:
+            let (lost, kept): (Vec<_>, Vec<_>) = old
+                .buffered
+                .into_iter()
+                .partition(|buffered| buffered.global_index <= invite.global_index);
+            group.buffered = kept;
+            for buffered in lost {
+                self.drop_message(
+                    &group_id,
+                    buffered.global_index,
+                    "sent before we rejoined the group".to_string(),
+                );
+            }
+            debug!(group = %ShortId(&group_id), epoch = group.mls_group.epoch().as_u64(), %sender_id, "rejoined group");
+        } else {
+            debug!(group = %ShortId(&group_id), %sender_id, "joined group");
+        }
+
         self.groups.insert(group_id.clone(), group);
+        self.retry_buffered(&group_id)?;
         Ok(group_id)
     }
 
     pub fn create_group(&mut self, name: String) -> Result<Vec<u8>> {
+        self.create_group_with_settings(name, self.group_settings)
+    }
+
+    #[instrument(skip_all, fields(user = %self.id))]
+    pub fn create_group_with_settings(
+        &mut self,
+        name: String,
+        settings: GroupSettings,
+    ) -> Result<Vec<u8>> {
         let alice_group = MlsGroup::new(
             &self.provider,
             &self.signer,
-            &MlsGroupCreateConfig::default(),
+            &settings.create_config(),
             self.credential_with_key.clone(),
         )
         .context("Failed to create MLS group")?;
 
-        let group = LocalGroup {
-            id: alice_group.group_id().to_vec(),
-            name: name.clone(),
-            mls_group: alice_group,
-            global_index: 0,
-            decrypted: Vec::new(),
-        };
+        let group = LocalGroup::new(name.clone(), alice_group, settings);
         let group_id = group.mls_group.group_id().to_vec();
+        debug!(group = %ShortId(&group_id), "created group");
         self.groups.insert(group_id.clone(), group);
         Ok(group_id)
     }
@@ -374,18 +679,47 @@ impl ConvoManager {
         self.groups.remove(group_id);
     }
 
+    // the checks `KeyPackageIn::validate` leaves to us: the package is for our ciphersuite,
+    // is currently valid (and not for an unreasonably long time), and isn't a one-time package
+    // we've already used.
+    pub fn check_key_package(&self, key_package: &KeyPackage) -> Result<()> {
+        if key_package.ciphersuite() != self.ciphersuite {
+            bail!(
+                "Key package uses ciphersuite {:?}, expected {:?}",
+                key_package.ciphersuite(),
+                self.ciphersuite
+            );
+        }
+
+        let (not_before, not_after) = utils::key_package_lifetime(key_package)?;
+        let now = utils::current_timestamp();
+        if now < not_before {
+            bail!("Key package is not valid yet");
+        }
+        if now >= not_after {
+            bail!("Key package has expired");
+        }
+        if not_after.saturating_sub(not_before) > MAX_KEY_PACKAGE_LIFETIME {
+            bail!("Key package lifetime is too long");
+        }
+
+        if !key_package.last_resort()
+            && self
+                .used_init_keys
+                .contains(key_package.hpke_init_key().as_slice())
+        {
+            bail!("Key package has already been used");
+        }
+
+        Ok(())
+    }
+
+    #[instrument(skip_all, fields(user = %self.id, group = %ShortId(group_id)))]
     pub fn create_invite(
         &mut self,
         group_id: &GroupId,
         serialized_key_package: Vec<u8>,
     ) -> Result<ConvoInvite> {
-        let group = self
-            .groups
-            .get_mut(group_id)
-            .context(format!("Group not found for ID: {:?}", group_id))?;
-
-        let mls_group = &mut group.mls_group;
-
         let key_package_in = KeyPackageIn::tls_deserialize_exact(&serialized_key_package)
             .context("Error deserializing key package")?;
 
@@ -393,24 +727,38 @@ impl ConvoManager {
             .validate(self.provider.crypto(), ProtocolVersion::Mls10)
             .context("Invalid KeyPackage")?;
 
-        let (fanned, welcome_out, group_info) = mls_group
+        self.check_key_package(&key_package)?;
+        let init_key = key_package.hpke_init_key().as_slice().to_vec();
+        let last_resort = key_package.last_resort();
+
+        self.discard_stale_commit(group_id)?;
+        let group = self
+            .groups
+            .get_mut(group_id)
+            .context(format!("Group not found for ID: {:?}", group_id))?;
+
+        // groups from before welcomes carried the tree get it turned on:
+        group
+            .mls_group
+            .set_configuration(self.provider.storage(), &group.settings.join_config())
+            .map_err(|e| anyhow::anyhow!("Failed to store group settings: {:?}", e))?;
+        let mls_group = &mut group.mls_group;
+
+        // the commit adding bob stays pending until the server has accepted it,
+        // see `group_merge_pending_commit`:
+        let (fanned, welcome_out, _group_info) = mls_group
             .add_members(&self.provider, &self.signer, &[key_package])
             .context("Could not add members")?;
 
-        // alice merges the pending commit that adds bob.
-        mls_group
-            .merge_pending_commit(&self.provider)
-            .context("Error merging pending commit")?;
+        // one-time key packages must never be used twice:
+        if !last_resort {
+            group.pending_init_key = Some(init_key);
+        }
 
         let serialized_welcome = welcome_out
             .tls_serialize_detached()
             .context("Error serializing welcome")?;
 
-        let ratchet_tree = mls_group
-            .export_ratchet_tree()
-            .tls_serialize_detached()
-            .context("Error serializing ratchet tree")?;
-
         let serialized_fanned = fanned
             .tls_serialize_detached()
             .context("Error serializing fanned")?;
@@ -418,13 +766,17 @@ impl ConvoManager {
         Ok(ConvoInvite {
             group_name: group.name.clone(),
             welcome_message: serialized_welcome,
-            ratchet_tree: Some(ratchet_tree),
+            ratchet_tree: None, // in the welcome
             global_index: 1, // TODO: this should be a parameter:
             fanned: Some(serialized_fanned),
         })
     }
 
+    #[instrument(skip_all, fields(user = %self.id, group = %ShortId(group_id)))]
     pub fn create_message(&mut self, group_id: &GroupId, message: String) -> Result<Vec<u8>> {
+        // lets everyone check they're on the same state of the group as us, see `forks`:
+        let tag = bincode::serialize(&self.epoch_tag(group_id)?)
+            .context("Failed to serialize epoch tag")?;
         let group = self
             .groups
             .get_mut(group_id)
@@ -432,6 +784,7 @@ impl ConvoManager {
 
         let mls_group = &mut group.mls_group;
 
+        mls_group.set_aad(tag);
         let mls_message_out = mls_group
             .create_message(&self.provider, &self.signer, &message.as_bytes())
             .context("Error creating application message")?;
@@ -458,11 +811,10 @@ impl ConvoManager {
         let mls_group = &group.mls_group;
         let credential = mls_group.member(member_index).context("Failed to get member")?;
         // get id from credential:
-        let member_id_bytes = credential.tls_serialize_detached().context("Failed to serialize sender id")?;
-        let member_id = String::from_utf8(member_id_bytes).context("Failed to convert sender id to string")?;
-        Ok(member_id)
+        extract_sender_id_from_credential(credential.clone())
     }
 
+    #[instrument(skip_all, fields(user = %self.id, group = tracing::field::Empty))]
     pub fn process_message(
         &mut self,
         serialized_message: SerializedMessage
@@ -477,6 +829,7 @@ impl ConvoManager {
             .context("Expected a PublicMessage or a PrivateMessage")?;
 
         let group_id = protocol_message.group_id().to_vec();
+        tracing::Span::current().record("group", tracing::field::display(ShortId(&group_id)));
 
         let processed_message = {
             let group = self
@@ -493,13 +846,12 @@ impl ConvoManager {
         
         // check if sender is an enum of type Member:
         if let Sender::Member(sender_index) = sender {
-            println!("sender is a member {:?}", sender_index);
             // let sender_pub = self.get_member_public_key(&group_id, sender_index).context("Failed to get sender public key")?;
             let sender_pub = self.get_member_public_key(&group_id, sender_index).unwrap();
             let sender_id = self.get_member_id(&group_id, sender_index).context("Failed to get sender credential")?;
-            println!("{}", format!("Sender: {:?} {:?}", sender_id, sender_pub));
+            debug!(sender_id, sender_key = %Redacted(&sender_pub), leaf = sender_index.u32(), "message from a member");
         } else {
-            println!("sender is not a member");
+            debug!("message from a non-member");
         }
 
         // let sender_test = String::from_utf8(sender.tls_serialize_detached().context("Failed to serialize sender data")?).context("Failed to convert sender id to string")?;
@@ -535,27 +887,32 @@ impl ConvoManager {
                     text: text.clone(),
                     sender_id: sender_id.clone(),
                     timestamp: timestamp,
+                    state: DeliveryState::Received,
+                    local_id: None,
                 });
 
                 ProcessedResults {
+                    group_id: group_id.clone(),
                     message: Some(text),
                     invite: None,
                 }
             }
             ProcessedMessageContent::ProposalMessage(_) => bail!("Unexpected proposal message"),
             ProcessedMessageContent::ExternalJoinProposalMessage(proposal) => {
+                // the welcome carries the tree, like an invite's:
+                mls_group
+                    .set_configuration(self.provider.storage(), &group.settings.join_config())
+                    .map_err(|e| anyhow::anyhow!("Failed to store group settings: {:?}", e))?;
                 mls_group
                     .store_pending_proposal(self.provider.storage(), *proposal)
                     .context("Failed to store pending proposal")?;
 
-                let (_commit, welcome, _group_info) = mls_group
+                // like an invite, the commit stays pending until the server has accepted it.
+                // the invite's `fanned` is the commit to post with the welcome:
+                let (commit, welcome, _group_info) = mls_group
                     .commit_to_pending_proposals(&self.provider, &self.signer)
                     .context("Could not commit to pending proposals")?;
 
-                mls_group
-                    .merge_pending_commit(&self.provider)
-                    .context("Could not merge commit")?;
-
                 // convert the Option<MlsMessageOut> to MlsMessageOut:
                 let welcome = welcome.context("Welcome was not returned")?;
 
@@ -563,21 +920,19 @@ impl ConvoManager {
                 let serialized_welcome = welcome
                     .tls_serialize_detached()
                     .context("Error serializing welcome")?;
-
-                // export the ratchet tree to go along with the invite:
-                let ratchet_tree = mls_group
-                    .export_ratchet_tree()
+                let serialized_commit = commit
                     .tls_serialize_detached()
-                    .context("Error serializing ratchet tree")?;
+                    .context("Error serializing commit")?;
 
                 ProcessedResults {
+                    group_id: group_id.clone(),
                     message: None,
                     invite: Some(ConvoInvite {
                         group_name: group.name.clone(),
                         welcome_message: serialized_welcome,
-                        ratchet_tree: Some(ratchet_tree),
+                        ratchet_tree: None, // in the welcome
                         global_index: group.global_index,
-                        fanned: None,
+                        fanned: Some(serialized_commit),
                     }),
                 }
             }
@@ -588,6 +943,7 @@ impl ConvoManager {
                     .context("Error merging staged commit")?;
 
                 ProcessedResults {
+                    group_id: group_id.clone(),
                     message: None,
                     invite: None,
                 }
@@ -597,11 +953,14 @@ impl ConvoManager {
         Ok(processed_results)
     }
 
+    // pending until the server has accepted the commit, like `remove_member`'s
+    #[instrument(skip_all, fields(user = %self.id, group = %ShortId(&group_id)))]
     pub fn kick_member(
         &mut self,
         group_id: GroupId,
         serialized_key_package: Vec<u8>,
     ) -> Result<(SerializedMessage, Option<Vec<u8>>)> {
+        self.discard_stale_commit(&group_id)?;
         let group = self
             .groups
             .get_mut(&group_id)
@@ -616,11 +975,6 @@ impl ConvoManager {
             .remove_members(&self.provider, &self.signer, &[member_index])
             .context("Error kicking member")?;
 
-        // merge the pending commit:
-        mls_group
-            .merge_pending_commit(&self.provider)
-            .context("Error merging pending commit")?;
-
         // serialize the message & welcome (if it exists)
         let fanned = mls_message_out
             .tls_serialize_detached()
@@ -637,6 +991,208 @@ impl ConvoManager {
         Ok((fanned, None))
     }
 
+    // removes the member with the given user id, returns the commit to fan out to the rest of the group.
+    // like an invite, it's only merged once the server has accepted it
+    #[instrument(skip_all, fields(user = %self.id, group = %ShortId(group_id), removed = user_id))]
+    pub fn remove_member(&mut self, group_id: &GroupId, user_id: &str) -> Result<SerializedMessage> {
+        self.discard_stale_commit(group_id)?;
+        let group = self
+            .groups
+            .get_mut(group_id)
+            .context(format!("Group not found for ID: {:?}", group_id))?;
+
+        let member_index = group
+            .mls_group
+            .members()
+            .find(|member| member.credential.serialized_content() == user_id.as_bytes())
+            .map(|member| member.index)
+            .context(format!("Member not found: {}", user_id))?;
+
+        let (mls_message_out, _welcome, _group_info) = group
+            .mls_group
+            .remove_members(&self.provider, &self.signer, &[member_index])
+            .context("Error removing member")?;
+
+        let fanned = mls_message_out
+            .tls_serialize_detached()
+            .context("Error serializing message")?;
+
+        Ok(fanned)
+    }
+
+    // a member who lost track of the group (see `RejoinRequest`) gets their old leaf replaced
+    // with `serialized_key_package` in one commit, pending like an invite's until the server
+    // takes it. the invite's welcome is for them, the commit for everyone else
+    #[instrument(skip_all, fields(user = %self.id, group = %ShortId(group_id), readded = %user_id))]
+    pub fn readd_member(
+        &mut self,
+        group_id: &GroupId,
+        user_id: &str,
+        serialized_key_package: Vec<u8>,
+    ) -> Result<ConvoInvite> {
+        let key_package = KeyPackageIn::tls_deserialize_exact(&serialized_key_package)
+            .context("Error deserializing key package")?
+            .validate(self.provider.crypto(), ProtocolVersion::Mls10)
+            .context("Invalid KeyPackage")?;
+
+        self.check_key_package(&key_package)?;
+        // anyone can post a key package, it has to be the member's own:
+        if key_package.leaf_node().credential().serialized_content() != user_id.as_bytes() {
+            bail!("Key package doesn't belong to {}", user_id);
+        }
+        let init_key = key_package.hpke_init_key().as_slice().to_vec();
+        let last_resort = key_package.last_resort();
+
+        self.discard_stale_commit(group_id)?;
+        let group = self
+            .groups
+            .get_mut(group_id)
+            .context(format!("Group not found for ID: {:?}", group_id))?;
+
+        let member = group
+            .mls_group
+            .members()
+            .find(|member| member.credential.serialized_content() == user_id.as_bytes())
+            .context(format!("Member not found: {}", user_id))?;
+        // a basic credential is just a name, only the member's signing key proves it's them:
+        if member.signature_key != key_package.leaf_node().signature_key().as_slice() {
+            bail!("Key package isn't signed by {}'s key", user_id);
+        }
+        let member_index = member.index;
+
+        group
+            .mls_group
+            .set_configuration(self.provider.storage(), &group.settings.join_config())
+            .map_err(|e| anyhow::anyhow!("Failed to store group settings: {:?}", e))?;
+
+        // by value, so the proposals travel inside the commit:
+        let mls_group = &mut group.mls_group;
+        let committed = (|| -> Result<_> {
+            mls_group
+                .propose_remove_member_by_value(&self.provider, &self.signer, member_index)
+                .context("Error proposing the member's removal")?;
+            mls_group
+                .propose_add_member_by_value(&self.provider, &self.signer, key_package)
+                .context("Error proposing the member's new leaf")?;
+            mls_group
+                .commit_to_pending_proposals(&self.provider, &self.signer)
+                .context("Error committing the re-add")
+        })();
+        if committed.is_err() {
+            // they'd end up in our next commit:
+            mls_group.clear_pending_proposals(self.provider.storage()).ok();
+        }
+        let (fanned, welcome_out, _group_info) = committed?;
+        let welcome_out = welcome_out.context("Re-add commit has no welcome")?;
+
+        if !last_resort {
+            group.pending_init_key = Some(init_key);
+        }
+
+        Ok(ConvoInvite {
+            group_name: group.name.clone(),
+            welcome_message: welcome_out
+                .tls_serialize_detached()
+                .context("Error serializing welcome")?,
+            ratchet_tree: None, // in the welcome
+            global_index: group.global_index,
+            fanned: Some(
+                fanned
+                    .tls_serialize_detached()
+                    .context("Error serializing fanned")?,
+            ),
+        })
+    }
+
+    // the server accepted our commit, it's now the group's next epoch
+    #[instrument(skip_all, fields(user = %self.id, group = %ShortId(group_id)))]
+    pub fn group_merge_pending_commit(&mut self, group_id: &GroupId) -> Result<()> {
+        let group = self
+            .groups
+            .get_mut(group_id)
+            .context(format!("Group not found for ID: {:?}", group_id))?;
+
+        group
+            .mls_group
+            .merge_pending_commit(&self.provider)
+            .context("Error merging pending commit")?;
+        if let Some(init_key) = group.pending_init_key.take() {
+            self.used_init_keys.insert(init_key);
+        }
+        group.unconfirmed_commit = None;
+        debug!(epoch = group.mls_group.epoch().as_u64(), "merged our commit");
+
+        self.retry_buffered(group_id)
+    }
+
+    // the server turned our commit down, nobody else has seen it
+    pub fn group_discard_pending_commit(&mut self, group_id: &GroupId) -> Result<()> {
+        let group = self
+            .groups
+            .get_mut(group_id)
+            .context(format!("Group not found for ID: {:?}", group_id))?;
+
+        group
+            .mls_group
+            .clear_pending_commit(self.provider.storage())
+            .map_err(|e| anyhow::anyhow!("Failed to clear pending commit: {:?}", e))?;
+        // a re-add's proposals would otherwise end up in our next commit:
+        group
+            .mls_group
+            .clear_pending_proposals(self.provider.storage())
+            .map_err(|e| anyhow::anyhow!("Failed to clear pending proposals: {:?}", e))?;
+        group.pending_init_key = None;
+        group.unconfirmed_commit = None;
+        Ok(())
+    }
+
+    // posting our pending commit failed without an answer (timeout, dropped connection, a server
+    // error), the server may or may not have taken it. it stays pending, and the next sync looks
+    // for `commit` in the group's log: merged if it's there, discarded if it isn't
+    pub fn group_commit_unconfirmed(&mut self, group_id: &GroupId, commit: Vec<u8>) -> Result<()> {
+        let group = self
+            .groups
+            .get_mut(group_id)
+            .context(format!("Group not found for ID: {:?}", group_id))?;
+
+        if group.mls_group.pending_commit().is_none() {
+            bail!("No pending commit to confirm");
+        }
+        group.unconfirmed_commit = Some(commit);
+        Ok(())
+    }
+
+    // see `group_commit_unconfirmed`. the server keeps one commit per epoch, so ours is either
+    // among the messages since our index or it never will be. if it is, it's merged once
+    // `process_convo_messages` gets to it, after whatever came before it
+    fn settle_unconfirmed_commit(&mut self, group_id: &GroupId, messages: &[ConvoMessage]) -> Result<()> {
+        let Some(commit) = self.groups.get(group_id).and_then(|group| group.unconfirmed_commit.as_ref()) else {
+            return Ok(());
+        };
+        if !messages.iter().any(|message| message.encrypted.as_ref() == Some(commit)) {
+            debug!(group = %ShortId(group_id), "our unconfirmed commit never made it, discarding it");
+            self.group_discard_pending_commit(group_id)?;
+        }
+        Ok(())
+    }
+
+    // a commit left pending by an earlier attempt (e.g. the app quit while it was in flight)
+    // would stop the group from making a new one. one the server may have taken has to be
+    // settled by a sync first, a new commit could otherwise fork the group
+    fn discard_stale_commit(&mut self, group_id: &GroupId) -> Result<()> {
+        let Some(group) = self.groups.get(group_id) else {
+            return Ok(());
+        };
+        if group.unconfirmed_commit.is_some() {
+            bail!("Our last commit hasn't been confirmed yet, sync first");
+        }
+        if group.mls_group.pending_commit().is_some() {
+            warn!(group = %ShortId(group_id), "discarding a commit we never heard back about");
+            self.group_discard_pending_commit(group_id)?;
+        }
+        Ok(())
+    }
+
     pub fn request_join(&mut self, group_id: &GroupId, epoch: &GroupEpoch) -> Result<Vec<u8>> {
         let key_package_in = KeyPackageIn::tls_deserialize_exact(self.get_key_package()?)
             .context("Error deserializing key package")?;
@@ -672,40 +1228,242 @@ impl ConvoManager {
     // higher level functions that make this class easier to use:
     // generally uses "Convo" objects instead of "Mls" objects
 
-    pub fn process_convo_messages(
-        &mut self,
-        messages: Vec<ConvoMessage>,
-        group_id: Option<&GroupId>,
-    ) -> Result<()> {
-        // if the message's sender_id is from ourself, skip it: (make a new vector with the filtered messages):
-        // let filtered_messages: Vec<ConvoMessage> = messages
-        //     .iter()
-        //     .filter(|m| m.sender_id != self.id)
-        //     .map(|m| m.clone())
-        //     .collect();
-
-        // if the message is an invite, process it:
+    // processes messages from any number of groups (and the mailbox), in order.
+    // a message that fails holds back the rest of its group (a sync fetches them again, see
+    // indices.rs) but not the other groups, until it's been tried MAX_MESSAGE_ATTEMPTS times.
+    // messages from an epoch we've already moved past can't be decrypted anymore (e.g. our own
+    // commits coming back), those are skipped right away
+    #[instrument(skip_all, fields(user = %self.id, count = messages.len()))]
+    pub fn process_convo_messages(&mut self, messages: Vec<ConvoMessage>) -> Result<()> {
+        let mut held_back: HashSet<GroupId> = HashSet::new();
+        self.expire_buffered();
+
         for message in messages {
+            // if the message is an invite, process it:
             if let Some(invite) = message.invite {
                 self.pending_invites.push(invite);
             }
 
-            // if the message is a message, process it:
-            if let Some(enc) = message.encrypted {
-                self.process_message(enc)?;
+            let Some(enc) = message.encrypted else {
+                continue;
+            };
+            let index = message.global_index;
+
+            let (group_id, epoch) = match utils::peek_message(&enc) {
+                Ok(peeked) => peeked,
+                Err(e) => {
+                    warn!(index, "skipping unreadable message: {:#}", e);
+                    continue;
+                }
+            };
+            let Some(group) = self.groups.get(&group_id) else {
+                warn!(group = %ShortId(&group_id), index, "skipping message for a group we're not in");
+                continue;
+            };
+            if held_back.contains(&group_id) || group.applied.contains(index) {
+                continue;
             }
+            // the server did take our commit after all:
+            if group.unconfirmed_commit.as_ref() == Some(&enc) {
+                debug!(group = %ShortId(&group_id), index, "found our unconfirmed commit, merging it");
+                self.group_merge_pending_commit(&group_id)?;
+                if let Some(group) = self.groups.get_mut(&group_id) {
+                    group.mark_applied(index);
+                }
+                continue;
+            }
+            let current_epoch = group.mls_group.epoch().as_u64();
+            let forked = self.check_epoch_tag(&group_id, &enc);
+
+            let result = self.process_message(enc.clone());
 
-            if let Some(group_id) = group_id {
-                let group = self
-                    .groups
-                    .get_mut(group_id)
-                    .context(format!("Group not found for ID: {:?}", group_id))?;
+            let group = self
+                .groups
+                .get_mut(&group_id)
+                .context(format!("Group not found for ID: {:?}", group_id))?;
+
+            match result {
+                // answering a join request takes posting its commit and welcome, which only
+                // whoever called `process_message` themselves can do:
+                Ok(ProcessedResults { invite: Some(ConvoInvite { fanned: Some(_), .. }), .. }) => {
+                    warn!(group = %ShortId(&group_id), index, "not answering a join request found while syncing");
+                    group.mark_applied(index);
+                    self.group_discard_pending_commit(&group_id)?;
+                }
+                Ok(_) => {
+                    group.mark_applied(index);
+                    if group.mls_group.epoch().as_u64() != current_epoch {
+                        self.retry_buffered(&group_id)?;
+                    }
+                }
+                // no amount of retrying gets us onto their copy of the group:
+                Err(e) if forked => {
+                    group.mark_applied(index);
+                    self.record_fork(&group_id);
+                    self.drop_message(&group_id, index, format!("sent from a copy of the group that split from ours: {:#}", e));
+                }
+                Err(e) if epoch < current_epoch => {
+                    debug!(group = %ShortId(&group_id), index, epoch, "skipping message from a past epoch: {:#}", e);
+                    group.mark_applied(index);
+                }
+                // the commit starting its epoch hasn't come in yet (e.g. it's in a gap):
+                Err(e) if epoch > current_epoch => {
+                    debug!(group = %ShortId(&group_id), index, epoch, "holding message from a future epoch: {:#}", e);
+                    group.mark_applied(index);
+                    group.buffered.push(BufferedMessage {
+                        global_index: index,
+                        epoch,
+                        message: enc,
+                        received_at: utils::current_timestamp(),
+                    });
+                    if group.buffered.len() > MAX_BUFFERED_MESSAGES {
+                        let oldest = group.buffered.remove(0);
+                        self.drop_message(&group_id, oldest.global_index, "too many messages waiting for their epoch".to_string());
+                    }
+                }
+                Err(e) => {
+                    let attempts = group.attempts.entry(index).or_default();
+                    *attempts += 1;
+                    if *attempts >= MAX_MESSAGE_ATTEMPTS {
+                        group.mark_applied(index);
+                        self.drop_message(&group_id, index, format!("{:#}", e));
+                    } else {
+                        warn!(group = %ShortId(&group_id), index, "message failed, trying again next sync: {:#}", e);
+                        held_back.insert(group_id);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // a commit was merged, whatever was waiting for the epoch it started can go now
+    // (and might start the next one)
+    fn retry_buffered(&mut self, group_id: &GroupId) -> Result<()> {
+        loop {
+            let group = self
+                .groups
+                .get_mut(group_id)
+                .context(format!("Group not found for ID: {:?}", group_id))?;
+            let current_epoch = group.mls_group.epoch().as_u64();
+
+            let Some(position) = group
+                .buffered
+                .iter()
+                .position(|buffered| buffered.epoch <= current_epoch)
+            else {
+                return Ok(());
+            };
+            let buffered = group.buffered.remove(position);
+
+            match self.process_message(buffered.message) {
+                Ok(_) => {
+                    debug!(group = %ShortId(group_id), index = buffered.global_index, "applied message held for its epoch")
+                }
+                Err(e) => self.drop_message(group_id, buffered.global_index, format!("{:#}", e)),
+            }
+        }
+    }
 
-                if message.global_index > group.global_index {
-                    group.global_index = message.global_index;
+    // held messages whose epoch never came
+    fn expire_buffered(&mut self) {
+        let now = utils::current_timestamp();
+        let mut expired = vec![];
+        for (group_id, group) in self.groups.iter_mut() {
+            group.buffered.retain(|buffered| {
+                let keep = now.saturating_sub(buffered.received_at) <= MAX_BUFFERED_AGE;
+                if !keep {
+                    expired.push((group_id.clone(), buffered.global_index));
                 }
+                keep
+            });
+        }
+        for (group_id, index) in expired {
+            self.drop_message(&group_id, index, "its epoch never arrived".to_string());
+        }
+    }
+
+    fn drop_message(&mut self, group_id: &GroupId, index: u64, reason: String) {
+        warn!(group = %ShortId(group_id), index, "giving up on message: {}", reason);
+        self.dropped.push(DroppedMessage {
+            group_id: group_id.clone(),
+            global_index: index,
+            reason,
+        });
+    }
+
+    // where to sync every group from: the first index we're missing, so gaps are fetched again
+    pub fn group_indices(&self) -> HashMap<GroupId, u64> {
+        self.groups
+            .iter()
+            .map(|(group_id, group)| (group_id.clone(), group.applied.contiguous()))
+            .collect()
+    }
+
+    // a server's answer to "everything in this group after `group_indices()`": the messages,
+    // and the group's index when they were read. indices up to that index it didn't send don't
+    // exist on the server (e.g. compacted away), there's nothing to wait for
+    pub fn process_group_messages(
+        &mut self,
+        group_id: &GroupId,
+        messages: Vec<ConvoMessage>,
+        server_index: u64,
+    ) -> Result<()> {
+        let returned: HashSet<u64> = messages.iter().map(|message| message.global_index).collect();
+        self.settle_unconfirmed_commit(group_id, &messages)?;
+
+        // nothing can ever make sense of a message that isn't MLS, don't refetch it forever:
+        let (readable, unreadable): (Vec<_>, Vec<_>) =
+            messages.into_iter().partition(|message| match &message.encrypted {
+                Some(enc) => utils::peek_message(enc).is_ok(),
+                None => true,
+            });
+
+        self.process_convo_messages(readable)?;
+
+        let group = self
+            .groups
+            .get_mut(group_id)
+            .context(format!("Group not found for ID: {:?}", group_id))?;
+
+        for message in &unreadable {
+            group.mark_applied(message.global_index);
+        }
+        for message in unreadable {
+            self.drop_message(group_id, message.global_index, "not an MLS message".to_string());
+        }
+
+        let group = self
+            .groups
+            .get_mut(group_id)
+            .context(format!("Group not found for ID: {:?}", group_id))?;
+
+        for (first, last) in group.applied.gaps(server_index) {
+            for index in (first..=last).filter(|index| !returned.contains(index)) {
+                group.mark_applied(index);
             }
         }
+        Ok(())
+    }
+
+    #[instrument(skip_all, fields(user = %self.id, groups = response.groups.len(), mailbox = response.mailbox.len()))]
+    pub fn process_sync_response(&mut self, response: SyncResponse) -> Result<()> {
+        for (group_id, group_messages) in response.groups {
+            if !self.groups.contains_key(&group_id) {
+                continue; // e.g. deleted locally while the sync was in flight
+            }
+
+            self.process_group_messages(
+                &group_id,
+                group_messages.messages,
+                group_messages.global_index,
+            )
+            .context(format!("Failed to process messages for group: {:?}", group_id))?;
+        }
+
+        self.process_convo_messages(response.mailbox)
+            .context("Failed to process mailbox")?;
 
         Ok(())
     }
@@ -766,6 +1524,70 @@ impl ConvoManager {
             .context(format!("Group not found for ID: {:?}", group_id))?;
 
         group.global_index = index;
+        group.applied.reset_to(index);
+        group.attempts.retain(|&failed, _| failed > index);
+        Ok(())
+    }
+
+    // e.g. for a message we sent ourselves
+    pub fn group_mark_applied(&mut self, group_id: &GroupId, index: u64) -> Result<()> {
+        let group = self
+            .groups
+            .get_mut(group_id)
+            .context(format!("Group not found for ID: {:?}", group_id))?;
+
+        group.mark_applied(index);
+        Ok(())
+    }
+
+    // the ranges (inclusive) of indices up to the group's global_index we haven't applied
+    pub fn group_gaps(&self, group_id: &GroupId) -> Result<Vec<(u64, u64)>> {
+        let group = self
+            .groups
+            .get(group_id)
+            .context(format!("Group not found for ID: {:?}", group_id))?;
+
+        Ok(group.applied.gaps(group.global_index))
+    }
+
+    // a hash of our epoch authenticator, the same for everyone on the same state of the group
+    pub fn group_authenticator_hash(&self, group_id: &GroupId) -> Result<Vec<u8>> {
+        let group = self
+            .groups
+            .get(group_id)
+            .context(format!("Group not found for ID: {:?}", group_id))?;
+
+        let authenticator = group.mls_group.epoch_authenticator();
+        let hash = self
+            .provider
+            .crypto()
+            .hash(self.ciphersuite.hash_algorithm(), authenticator.as_slice())
+            .map_err(|e| anyhow::anyhow!("Failed to hash epoch authenticator: {:?}", e))?;
+        // enough to tell states apart:
+        Ok(hash[..16].to_vec())
+    }
+
+    pub fn group_get_settings(&self, group_id: &GroupId) -> Result<GroupSettings> {
+        let group = self
+            .groups
+            .get(group_id)
+            .context(format!("Group not found for ID: {:?}", group_id))?;
+
+        Ok(group.settings)
+    }
+
+    // only affects what's received from now on, secrets already deleted don't come back
+    pub fn group_set_settings(&mut self, group_id: &GroupId, settings: GroupSettings) -> Result<()> {
+        let group = self
+            .groups
+            .get_mut(group_id)
+            .context(format!("Group not found for ID: {:?}", group_id))?;
+
+        group
+            .mls_group
+            .set_configuration(self.provider.storage(), &settings.join_config())
+            .map_err(|e| anyhow::anyhow!("Failed to store group settings: {:?}", e))?;
+        group.settings = settings;
         Ok(())
     }
 
@@ -798,11 +1620,62 @@ impl ConvoManager {
             text: message,
             sender_id,
             timestamp,
+            state: DeliveryState::Received,
+            local_id: None,
         });
 
         Ok(())
     }
 
+    // echoes one of our own messages into the group, `local_id` is how its state is updated later
+    pub fn group_push_own_message(
+        &mut self,
+        group_id: &GroupId,
+        message: String,
+        local_id: u64,
+        timestamp: u64,
+        state: DeliveryState,
+    ) -> Result<()> {
+        let group = self
+            .groups
+            .get_mut(group_id)
+            .context(format!("Group not found for ID: {:?}", group_id))?;
+
+        group.decrypted.push(MessageItem {
+            text: message,
+            sender_id: self.id.clone(),
+            timestamp,
+            state,
+            local_id: Some(local_id),
+        });
+        Ok(())
+    }
+
+    // false if there's no message with that local_id in the group (anymore)
+    pub fn group_set_message_state(
+        &mut self,
+        group_id: &GroupId,
+        local_id: u64,
+        state: DeliveryState,
+    ) -> Result<bool> {
+        let group = self
+            .groups
+            .get_mut(group_id)
+            .context(format!("Group not found for ID: {:?}", group_id))?;
+
+        match group
+            .decrypted
+            .iter_mut()
+            .find(|item| item.local_id == Some(local_id))
+        {
+            Some(item) => {
+                item.state = state;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
     pub fn group_get_member_ids(&self, group_id: &GroupId) -> Result<Vec<String>> {
         let group = self
             .groups
@@ -1,6 +1,6 @@
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use openmls::prelude::{tls_codec::Serialize as TlsSerialize, *};
+use openmls::prelude::{tls_codec::Deserialize as _, *};
 use openmls_basic_credential::SignatureKeyPair;
 use serde_json;
 use anyhow::{Context, Result};
@@ -31,18 +31,130 @@ pub fn generate_credential_with_key(
 }
 
 // A helper to create key package bundles.
+// `lifetime` is in seconds, see `key_package_lifetime` for checking it on the other end.
 pub fn generate_key_package(
     ciphersuite: Ciphersuite,
     provider: &impl OpenMlsProvider,
     signer: &SignatureKeyPair,
     credential_with_key: CredentialWithKey,
+    lifetime: u64,
 ) -> KeyPackageBundle {
     // Create the key package
     KeyPackage::builder()
+        .key_package_lifetime(Lifetime::new(lifetime))
         .build(ciphersuite, provider, signer, credential_with_key)
         .unwrap()
 }
 
+// (not_before, not_after) of a key package, in unix seconds.
+// openmls 0.6 keeps `LeafNode::life_time()` crate-private and doesn't export `LeafNodeSource`,
+// but both it and `Lifetime` are serde types, so the lifetime is read back through those:
+pub fn key_package_lifetime(key_package: &KeyPackage) -> Result<(u64, u64)> {
+    #[derive(serde::Deserialize)]
+    struct Lifetime {
+        not_before: u64,
+        not_after: u64,
+    }
+    #[derive(serde::Deserialize)]
+    enum LeafNodeSource {
+        KeyPackage(Lifetime),
+        Update,
+        Commit(serde::de::IgnoredAny),
+    }
+
+    let source = serde_json::to_value(key_package.leaf_node().leaf_node_source())
+        .context("Failed to read leaf node source")?;
+    match serde_json::from_value(source).context("Failed to read leaf node source")? {
+        LeafNodeSource::KeyPackage(lifetime) => Ok((lifetime.not_before, lifetime.not_after)),
+        LeafNodeSource::Update | LeafNodeSource::Commit(_) => {
+            anyhow::bail!("Key package has no lifetime")
+        }
+    }
+}
+
+// verifies a serialized key package and returns when it expires (unix seconds),
+// for the server, which doesn't otherwise look inside key packages:
+pub fn key_package_expiry(serialized_key_package: &[u8]) -> Result<u64> {
+    let key_package = KeyPackageIn::tls_deserialize_exact(serialized_key_package)
+        .context("Error deserializing key package")?
+        .validate(
+            &openmls_rust_crypto::RustCrypto::default(),
+            ProtocolVersion::Mls10,
+        )
+        .context("Invalid KeyPackage")?;
+
+    let (_not_before, not_after) = key_package_lifetime(&key_package)?;
+    Ok(not_after)
+}
+
+// who a serialized key package is for: the identity in its credential and the key it's signed
+// with, for the server to tie an account to that key
+pub fn key_package_owner(serialized_key_package: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let key_package = KeyPackageIn::tls_deserialize_exact(serialized_key_package)
+        .context("Error deserializing key package")?
+        .validate(
+            &openmls_rust_crypto::RustCrypto::default(),
+            ProtocolVersion::Mls10,
+        )
+        .context("Invalid KeyPackage")?;
+
+    let leaf_node = key_package.leaf_node();
+    Ok((
+        leaf_node.credential().serialized_content().to_vec(),
+        leaf_node.signature_key().as_slice().to_vec(),
+    ))
+}
+
+// checks an `AccountProof` was signed with `signature_key` (the timestamp is the caller's to check)
+pub fn verify_account_proof(
+    signature_key: &[u8],
+    action: &str,
+    user_id: &str,
+    proof: &skychat_protocol::AccountProof,
+) -> Result<()> {
+    let payload = skychat_protocol::AccountProof::payload(action, user_id, proof.timestamp);
+    openmls_rust_crypto::RustCrypto::default()
+        .verify_signature(
+            SignatureScheme::ED25519,
+            &payload,
+            signature_key,
+            &proof.signature,
+        )
+        .map_err(|e| anyhow::anyhow!("Invalid signature: {:?}", e))
+}
+
+// the group and epoch an MLS message is for, without processing it (both are in the clear,
+// even for a PrivateMessage), so the server can read them too:
+pub fn peek_message(serialized: &[u8]) -> Result<(Vec<u8>, u64)> {
+    let mls_message =
+        MlsMessageIn::tls_deserialize_exact(serialized).context("Could not deserialize message")?;
+    let protocol_message: ProtocolMessage = mls_message
+        .try_into_protocol_message()
+        .context("Expected a PublicMessage or a PrivateMessage")?;
+
+    Ok((
+        protocol_message.group_id().to_vec(),
+        protocol_message.epoch().as_u64(),
+    ))
+}
+
+// the authenticated data of a PrivateMessage, which is in the clear too. openmls only hands
+// it out after decrypting, too late for telling why a message doesn't decrypt. None for
+// anything else
+pub fn peek_aad(serialized: &[u8]) -> Option<Vec<u8>> {
+    let mut bytes = serialized;
+    let _version = u16::tls_deserialize(&mut bytes).ok()?;
+    let wire_format = u16::tls_deserialize(&mut bytes).ok()?;
+    if wire_format != 2 {
+        return None; // not mls_private_message
+    }
+    let _group_id = VLBytes::tls_deserialize(&mut bytes).ok()?;
+    let _epoch = u64::tls_deserialize(&mut bytes).ok()?;
+    let _content_type = u8::tls_deserialize(&mut bytes).ok()?;
+    let aad = VLBytes::tls_deserialize(&mut bytes).ok()?;
+    Some(aad.as_slice().to_vec())
+}
+
 pub fn current_timestamp() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -50,6 +162,34 @@ pub fn current_timestamp() -> u64 {
         .as_secs()
 }
 
+/// Bytes that must never end up in a log (key packages, keys, ciphertexts):
+/// formats as its length only.
+pub struct Redacted<'a>(pub &'a [u8]);
+
+impl std::fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<redacted, {} bytes>", self.0.len())
+    }
+}
+
+impl std::fmt::Debug for Redacted<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(self, f)
+    }
+}
+
+/// A group id in logs: the first 8 bytes as hex, enough to tell groups apart.
+pub struct ShortId<'a>(pub &'a [u8]);
+
+impl std::fmt::Display for ShortId<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for byte in self.0.iter().take(8) {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
 use base64::{engine::general_purpose, Engine as _};
 use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
 use std::error::Error;
@@ -109,7 +249,8 @@ impl BufferConverter {
 
 
 pub fn extract_sender_id_from_credential(credential: Credential) -> Result<String> {
-    let sender_id_bytes = credential.tls_serialize_detached().context("Failed to serialize sender id")?;
+    // the identity itself, not the tls encoding of the whole credential (type and length prefix):
+    let sender_id_bytes = credential.serialized_content().to_vec();
     let sender_id = String::from_utf8(sender_id_bytes).context("Failed to convert sender id to string")?;
     Ok(sender_id)
 }
\ No newline at end of file
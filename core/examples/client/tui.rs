@@ -12,20 +12,27 @@ use ratatui::{
     widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Tabs},
     Frame, Terminal,
 };
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     error::Error,
     fs, io,
     time::{Duration, Instant},
 };
+use tokio::sync::broadcast;
 
 use skychat_client::client::ConvoClient;
-use skychat_core::manager::ConvoMessage;
+use skychat_client::sync::SyncConfig;
+use skychat_core::changes::SyncEvent;
+use skychat_core::manager::DeliveryState;
 use skychat_core::manager::SerializedCredentials;
 
 type GroupId = Vec<u8>;
 
+// how many directory entries the users tab shows:
+const USERS_PAGE_SIZE: u64 = 50;
+
 #[derive(PartialEq)]
 enum InputMode {
     Normal,
@@ -51,7 +58,14 @@ struct GroupInfo {
 
 struct User {
     user_id: String,
-    key_package: Vec<u8>,
+    handle: String,
+    display_name: String,
+}
+
+impl User {
+    fn label(&self) -> String {
+        format!("{} (@{})", self.display_name, self.handle)
+    }
 }
 
 struct App {
@@ -62,6 +76,8 @@ struct App {
     messages: Vec<String>,
     groups: Vec<GroupInfo>,
     client: Option<ConvoClient>,
+    // what the client's background sync turned up, see `App::sync`:
+    sync_events: Option<broadcast::Receiver<SyncEvent>>,
     current_group_id: Option<GroupId>,
     incoming_alert: Option<String>,
     messages_scroll: ListState,
@@ -70,6 +86,7 @@ struct App {
     invites_scroll: ListState,
     server_address: String,
     name: String,
+    handle: String,
 }
 
 pub struct Settings {
@@ -98,6 +115,7 @@ impl Default for App {
             messages: Vec::new(),
             groups: Vec::new(),
             client: None,
+            sync_events: None,
             current_group_id: None,
             incoming_alert: None,
             messages_scroll,
@@ -105,20 +123,24 @@ impl Default for App {
             groups_scroll,
             invites_scroll,
             name: "".to_string(),
+            handle: "".to_string(),
         }
     }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct SerializedSettings {
-    name: String,
+    name: String, // our user_id
     server_address: String,
+    #[serde(default)]
+    handle: String,
 }
 
 impl App {
     async fn load_state(&mut self, path: &str) {
         let keys_path = format!("{}/keys.json", path);
         let settings_path = format!("{}/settings.json", path);
+        let outbox_path = format!("{}/outbox.json", path);
         // read settings.json:
         if let Ok(settings_json) = fs::read_to_string(settings_path) {
             let serialized_settings: SerializedSettings =
@@ -131,6 +153,15 @@ impl App {
                 serde_json::from_str(&keys_json).unwrap();
             self.load_credentials(serialized_credentials).await;
         }
+        // unsent messages are in keys.json now, outbox.json is from before they were:
+        if let Ok(outbox_json) = fs::read_to_string(&outbox_path) {
+            if let (Some(client), Ok(outbox)) = (&mut self.client, serde_json::from_str(&outbox_json)) {
+                if client.outbox.is_empty() {
+                    client.outbox = outbox;
+                }
+            }
+            fs::remove_file(outbox_path).ok();
+        }
     }
 
     async fn save_state(&mut self, path: &str) {
@@ -140,7 +171,8 @@ impl App {
         let settings_json = serde_json::to_string(&serialized_settings).unwrap();
         fs::write(settings_path, settings_json).unwrap();
 
-        let serialized_credentials = self.client.as_mut().unwrap().manager.save_state();
+        // includes the outbox, messages that weren't sent before we quit:
+        let serialized_credentials = self.client.as_ref().unwrap().save_state().unwrap();
         let keys_json = serde_json::to_string(&serialized_credentials).unwrap();
         fs::write(keys_path, keys_json).unwrap();
     }
@@ -153,11 +185,11 @@ impl App {
 
     async fn load_credentials(&mut self, serialized: SerializedCredentials) {
         if let Some(client) = &mut self.client {
-            client.manager.load_state(serialized).unwrap();
+            client.load_state(serialized).unwrap();
         } else {
             // create a new client
             let mut client = ConvoClient::new(self.name.clone());
-            client.manager.load_state(serialized).unwrap();
+            client.load_state(serialized).unwrap();
             self.client = Some(client);
 
             let res = self
@@ -168,6 +200,16 @@ impl App {
                 .await;
 
             if res.is_ok() {
+                self.start_sync();
+                // the directory doesn't outlive the server, claim our handle again:
+                if !self.handle.is_empty() {
+                    let handle = self.handle.clone();
+                    let client = self.client.as_mut().unwrap();
+                    client
+                        .register(handle.clone(), handle, BTreeMap::new())
+                        .await
+                        .ok();
+                }
                 self.input.clear();
                 self.update_users().await;
                 self.input_mode = InputMode::Normal;
@@ -184,19 +226,23 @@ impl App {
         SerializedSettings {
             name: self.client.as_ref().unwrap().user_id.clone(),
             server_address: self.server_address.clone(),
+            handle: self.handle.clone(),
         }
     }
 
     async fn load_settings(&mut self, serialized: SerializedSettings) {
         self.name = serialized.name;
         self.server_address = serialized.server_address;
+        self.handle = serialized.handle;
     }
 
     fn scroll_messages(&mut self, up: bool) {
         if let Some(client) = &self.client {
             if let Some(group_id) = &self.current_group_id {
-                let messages = client.get_group_messages(&group_id);
-                let len = messages.len();
+                let len = client
+                    .get_group_messages(group_id)
+                    .map(|messages| messages.len())
+                    .unwrap_or_default();
 
                 if len == 0 {
                     return;
@@ -281,35 +327,51 @@ impl App {
 
     async fn update_users(&mut self) {
         if let Some(client) = &mut self.client {
-            let users_list = client.list_users().await;
-            self.users = users_list
+            let Ok(results) = client.search_users(String::new(), 0, USERS_PAGE_SIZE).await else {
+                return;
+            };
+            self.users = results
+                .users
                 .into_iter()
                 .filter(|u| u.user_id != client.user_id)
                 .map(|u| User {
-                    // name: u.name,
                     user_id: u.user_id,
-                    key_package: u.serialized_key_package,
+                    handle: u.handle,
+                    display_name: u.display_name,
                 })
                 .collect();
         }
     }
 
+    // fetches one of the user's key packages (each can only be used once) and invites them
+    async fn invite(client: &mut ConvoClient, user_id: String, group_id: GroupId) -> bool {
+        let Ok(mut key_packages) = client.get_user_key_packages(vec![user_id.clone()]).await else {
+            return false;
+        };
+        let Some(key_package) = key_packages.remove(&user_id) else {
+            return false;
+        };
+
+        client
+            .invite_user_to_group(user_id, group_id, key_package)
+            .await
+            .is_ok()
+    }
+
     async fn create_group(&mut self) {
         if let Some(client) = &mut self.client {
             if let Some(selected) = self.users_scroll.selected() {
                 let user = &self.users[selected];
-                let group_name = format!("{}", self.input.clone());
+                let group_name = self.input.clone();
 
-                client.create_group(group_name.clone()).await;
-                let group_id = client.get_group_id(group_name).await;
+                let Ok(group_id) = client.create_group(group_name).await else {
+                    self.incoming_alert = Some("Failed to create group".to_string());
+                    return;
+                };
 
-                client
-                    .invite_user_to_group(
-                        user.user_id.clone(),
-                        group_id.clone(),
-                        user.key_package.clone(),
-                    )
-                    .await;
+                if !Self::invite(client, user.user_id.clone(), group_id.clone()).await {
+                    self.incoming_alert = Some(format!("Failed to invite {}", user.label()));
+                }
 
                 self.current_group_id = Some(group_id);
                 self.messages
@@ -318,66 +380,117 @@ impl App {
         }
     }
 
-    async fn check_messages(&mut self) {
+    // syncs in the background from here on (pushed updates, or polling when there are none)
+    fn start_sync(&mut self) {
         if let Some(client) = &mut self.client {
-            let new_messages = client
-                .check_incoming_messages(self.current_group_id.as_ref())
-                .await;
-            if !new_messages.is_empty() {
-                self.process_new_messages(new_messages).await;
-                // self.save_state("./").await;
-                // Auto-scroll when new messages arrive
-                self.scroll_to_bottom();
+            if client.start_sync(SyncConfig::default()).is_ok() {
+                self.sync_events = Some(client.sync_events());
+            }
+        }
+    }
+
+    // runs a sync if the client says one is due (which also retries the outbox),
+    // then goes through whatever changed
+    async fn sync(&mut self) {
+        if let Some(client) = &mut self.client {
+            client.sync_if_due().await;
+        }
+
+        let mut events = vec![];
+        if let Some(receiver) = &mut self.sync_events {
+            while let Ok(event) = receiver.try_recv() {
+                events.push(event);
             }
         }
+        if !events.is_empty() {
+            self.process_sync_events(events).await;
+        }
     }
 
     async fn send_message(&mut self) {
         if let Some(client) = &mut self.client {
             if let Some(group_id) = &self.current_group_id {
                 if !self.input.is_empty() {
-                    // check if the message is a /inv <user_name> command:
-                    if self.input.starts_with("/inv ") {
-                        let user_name = self.input[5..].to_string();
-                        // TODO: breaks if people have the same name!
-                        // let user_id = client.name_to_id(user_name);
-                        // let key_package = self.users.iter().find(|u| u.name == user_name).unwrap().key_package.clone();
-                        let user = self
-                            .users
-                            .iter()
-                            .find(|user| user.user_id == user_name.clone())
-                            .expect("user not found!");
-
-                        let key_package = user.key_package.clone();
-                        let user_id = user.user_id.clone();
-                        client
-                            .invite_user_to_group(user_id, group_id.clone(), key_package.clone())
-                            .await;
-
-                        client
-                            .send_message(
-                                group_id,
-                                format!(
-                                    "<{} invited {} to join the group!",
-                                    client.user_id, user_name
-                                ),
-                            )
-                            .await;
+                    // check if the message is a /inv <handle> command:
+                    if let Some(handle) = self.input.strip_prefix("/inv ") {
+                        let handle = handle.trim_start_matches('@').to_string();
+                        self.input.clear();
+
+                        // handles are unique, display names aren't:
+                        let Ok(user) = client.find_user(&handle).await else {
+                            self.incoming_alert = Some(format!("No user with handle @{}", handle));
+                            return;
+                        };
+
+                        if !Self::invite(client, user.user_id.clone(), group_id.clone()).await {
+                            self.incoming_alert = Some(format!("Failed to invite @{}", handle));
+                            return;
+                        }
+
+                        let text = format!(
+                            "<{} invited {} to join the group!",
+                            client.display_name(&client.user_id),
+                            user.display_name
+                        );
+                        client.send_message(group_id, text).await.ok();
+                        return;
+                    }
+
+                    // /kick <handle>
+                    if let Some(handle) = self.input.strip_prefix("/kick ") {
+                        let handle = handle.trim_start_matches('@').to_string();
                         self.input.clear();
+
+                        let removed = match client.find_user(&handle).await {
+                            Ok(user) => client
+                                .remove_user_from_group(user.user_id, group_id.clone())
+                                .await
+                                .is_ok(),
+                            Err(_) => false,
+                        };
+                        if !removed {
+                            self.incoming_alert = Some(format!("Failed to remove @{}", handle));
+                        }
                         return;
                     }
 
-                    if self.input.starts_with("/kick ") {
-                        let user_name = self.input[5..].to_string();
-                        let user = self
-                            .users
-                            .iter()
-                            .find(|user| user.user_id == user_name.clone())
-                            .expect("user not found!");
-                        // client.kick_user_from_group(user.user_id.clone(), group_id.clone()).await;
+                    // /rejoin asks the others to re-add us, for when our copy of the group broke
+                    if self.input == "/rejoin" {
+                        self.input.clear();
+
+                        if client.request_rejoin(group_id).await.is_err() {
+                            self.incoming_alert = Some("Failed to ask to rejoin".to_string());
+                        }
+                        return;
+                    }
+
+                    // /retry sends our failed messages in this group again
+                    if self.input == "/retry" {
+                        self.input.clear();
+
+                        let failed: Vec<u64> = client
+                            .get_group_messages(group_id)
+                            .map(|messages| {
+                                messages
+                                    .iter()
+                                    .filter(|message| {
+                                        matches!(message.state, DeliveryState::Failed { .. })
+                                    })
+                                    .filter_map(|message| message.local_id)
+                                    .collect()
+                            })
+                            .unwrap_or_default();
+                        for id in failed {
+                            client.retry_message(id).await.ok();
+                        }
+                        return;
                     }
 
-                    client.send_message(group_id, self.input.clone()).await;
+                    // messages that can't go out yet stay in the outbox (and show as such),
+                    // failures are reported by `process_sync_events`:
+                    if client.send_message(group_id, self.input.clone()).await.is_err() {
+                        self.incoming_alert = Some("Failed to send message".to_string());
+                    }
                     self.input.clear();
                     self.scroll_to_bottom();
                 }
@@ -407,17 +520,52 @@ impl App {
             }
         }
     }
-    async fn process_new_messages(&mut self, messages: Vec<ConvoMessage>) {
-        if let Some(client) = &mut self.client {
-            for message in messages {
-                // do something with incoming messages
-                // if we're not in the group view, add it to the alerts!
-                if self.input_mode != InputMode::Chatting {
-                    let user_name = client.id_to_name.get(&message.sender_id).unwrap().clone();
-                    self.incoming_alert = Some(format!("New message from {}", user_name));
+    async fn process_sync_events(&mut self, events: Vec<SyncEvent>) {
+        let mut messages = 0;
+        let mut invites = 0;
+        for event in &events {
+            match event {
+                SyncEvent::NewMessage { .. } => messages += 1,
+                SyncEvent::InviteReceived { .. } => invites += 1,
+                SyncEvent::Delivery {
+                    state: DeliveryState::Failed { reason },
+                    ..
+                } => {
+                    self.incoming_alert = Some(format!(
+                        "Failed to send message: {} (/retry to try again)",
+                        reason
+                    ));
+                }
+                SyncEvent::MessageDropped { reason, .. } => {
+                    self.incoming_alert = Some(format!("Couldn't read a message: {}", reason));
+                }
+                SyncEvent::ForkDetected { messages, .. } => {
+                    self.incoming_alert = Some(format!(
+                        "Out of sync with the group ({} unreadable), /rejoin to be added back",
+                        messages
+                    ));
+                }
+                SyncEvent::SyncError { error } => {
+                    self.incoming_alert = Some(format!("Sync failed: {}", error));
                 }
+                _ => {}
             }
         }
+
+        // if we're not in the group view, add it to the alerts!
+        if self.input_mode != InputMode::Chatting && (messages > 0 || invites > 0) {
+            self.incoming_alert = Some(if invites > 0 {
+                format!("{} new invite(s)", invites)
+            } else {
+                format!("{} new message(s)", messages)
+            });
+        }
+
+        self.update_groups().await;
+        // Auto-scroll when new messages arrive
+        if messages > 0 {
+            self.scroll_to_bottom();
+        }
     }
 
     async fn accept_invite(&mut self) {
@@ -425,7 +573,9 @@ impl App {
             if let Some(selected) = self.invites_scroll.selected() {
                 if selected < client.manager.pending_invites.len() {
                     let invite = client.manager.pending_invites.remove(selected);
-                    client.process_invite(invite).await;
+                    if client.process_invite(invite).await.is_err() {
+                        self.incoming_alert = Some("Failed to join group".to_string());
+                    }
                     self.input_mode = InputMode::Normal;
                     self.tab_mode = TabMode::Groups;
                     self.invites_scroll.select(None);
@@ -449,7 +599,7 @@ impl App {
     fn scroll_to_bottom(&mut self) {
         if let Some(client) = &self.client {
             if let Some(group_id) = &self.current_group_id {
-                let messages = client.get_renderable_messages(&group_id);
+                let messages = client.get_renderable_messages(group_id).unwrap_or_default();
                 if !messages.is_empty() {
                     self.messages_scroll.select(Some(messages.len() - 1));
                 }
@@ -547,11 +697,11 @@ fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
                         } else {
                             Style::default()
                         };
-                        ListItem::new(user.user_id.clone()).style(style)
+                        ListItem::new(user.label()).style(style)
                     })
                     .collect();
 
-                let our_user_name = app.client.as_ref().unwrap().user_id.clone();
+                let our_user_name = format!("@{}", app.handle);
                 // let empty_list = List::new(vec![ListItem::new(format!(
                 //     "Users <You are: {}>",
                 //     our_user_name
@@ -617,9 +767,7 @@ fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
             f.render_widget(input, chunks[2]);
         }
         InputMode::CreatingGroup => {
-            let user_strings = vec![app.users[app.users_scroll.selected().unwrap()]
-                .user_id
-                .clone()];
+            let user_strings = vec![app.users[app.users_scroll.selected().unwrap()].label()];
             let users: Vec<ListItem> = user_strings
                 .iter()
                 .map(|u| ListItem::new(u.clone()))
@@ -636,7 +784,8 @@ fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
         InputMode::Chatting => {
             if let Some(client) = &app.client {
                 if let Some(group_id) = &app.current_group_id {
-                    let message_items = client.get_group_messages(&group_id);
+                    let no_messages = Vec::new();
+                    let message_items = client.get_group_messages(group_id).unwrap_or(&no_messages);
 
                     // assign a color to each sender:
                     let mut sender_colors = HashMap::new();
@@ -666,8 +815,10 @@ fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
                             let user_style = if m.sender_id == client.user_id {
                                 Style::default().fg(Color::Green)
                             } else {
-                                Style::default()
-                                    .fg(sender_colors.get(&m.sender_id).unwrap().clone())
+                                Style::default().fg(sender_colors
+                                    .get(&m.sender_id)
+                                    .copied()
+                                    .unwrap_or(Color::White))
                             };
 
                             let message_style = if Some(i) == app.messages_scroll.selected() {
@@ -676,10 +827,7 @@ fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
                                 Style::default()
                             };
 
-                            let sender_name = format!(
-                                "{}: ",
-                                client.id_to_name.get(&m.sender_id).unwrap().clone()
-                            );
+                            let sender_name = format!("{}: ", client.display_name(&m.sender_id));
 
                             // return 2 spans, one for the sender and one for the message:
                             let sender_span = Span::styled(sender_name, user_style);
@@ -689,7 +837,7 @@ fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
                         })
                         .collect();
 
-                    let our_user_name = client.user_id.clone();
+                    let our_user_name = format!("@{}", app.handle);
                     let messages_list = List::new(messages).block(
                         Block::default()
                             .title(format!("Messages"))
@@ -703,11 +851,8 @@ fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
                     );
 
                     // list the users:
-                    let users: Vec<ListItem> = app
-                        .users
-                        .iter()
-                        .map(|u| ListItem::new(u.user_id.clone()))
-                        .collect();
+                    let users: Vec<ListItem> =
+                        app.users.iter().map(|u| ListItem::new(u.label())).collect();
 
                     // TODO: filter the users to only include the ones in the group:
                     let users_list = List::new(users)
@@ -740,7 +885,7 @@ fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
 
     let input_title = match app.input_mode {
         InputMode::Normal => "Use Arrow keys / Enter to navigate, <Esc to exit>",
-        InputMode::ChooseUsername => "Enter username",
+        InputMode::ChooseUsername => "Enter a handle (3-32 of a-z, 0-9, _ . -)",
         InputMode::Chatting => "Enter message <Esc to go back>",
         InputMode::AcceptingInvite => "Press Y/n to accept/decline <Esc to go back>",
         InputMode::CreatingGroup => "Enter a name for the group! <Esc to go back>",
@@ -766,16 +911,12 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let mut last_update = Instant::now();
 
     loop {
-        // Update users and groups list every 5 seconds
+        // messages come in through the client's background sync:
+        app.sync().await;
+
+        // the directory isn't part of it, refresh the users list every few seconds
         if Instant::now().duration_since(last_update) >= Duration::from_secs(3) {
             app.update_users().await;
-            app.update_groups().await;
-            app.check_messages().await;
-
-            // if app.input_mode == InputMode::Chatting {
-            //     app.check_messages().await;
-            // }
-
             last_update = Instant::now();
         }
 
@@ -877,13 +1018,29 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     InputMode::ChooseUsername => match key.code {
                         KeyCode::Enter => {
                             if !app.input.is_empty() {
-                                let mut client = ConvoClient::new(app.input.clone());
+                                // handles are for people, user_ids just need to be unique:
+                                let handle = app.input.trim().to_lowercase();
+                                let user_id =
+                                    format!("{}_{}", handle, rand::rng().random_range(0..1000000));
+                                let mut client = ConvoClient::new(user_id);
                                 let res = client
                                     .connect_to_server(app.server_address.to_string())
                                     .await;
 
                                 if res.is_ok() {
+                                    let registered = client
+                                        .register(handle.clone(), handle.clone(), BTreeMap::new())
+                                        .await;
+                                    if let Err(e) = registered {
+                                        // most likely taken, pick another one:
+                                        app.input.clear();
+                                        app.incoming_alert = Some(format!("{:#}", e));
+                                        continue;
+                                    }
+
+                                    app.handle = handle;
                                     app.client = Some(client);
+                                    app.start_sync();
                                     app.input.clear();
                                     app.update_users().await;
                                     app.input_mode = InputMode::Normal;
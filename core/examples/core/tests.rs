@@ -143,6 +143,8 @@ async fn manual_chat() -> Result<(), Box<dyn std::error::Error>> {
     let group_invite = alice
         .create_invite(&gid, bob_key_package)
         .context("failed to create invite")?;
+    // no server to turn the commit down here, alice merges it right away:
+    alice.group_merge_pending_commit(&gid).context("failed to merge commit")?;
     bob.process_invite(group_invite.clone()).context("failed to process invite")?;
 
     println!("<------ Alice creates a new group and invites Bob! ------->");
@@ -182,6 +184,7 @@ async fn manual_chat() -> Result<(), Box<dyn std::error::Error>> {
     let group_invite = bob
         .create_invite(&gid, charlie_key_package)
         .context("failed to create invite")?;
+    bob.group_merge_pending_commit(&gid).context("failed to merge commit")?;
 
     // charlie + everyone* (not actually everyone, but I think log(n) people in the tree?)
     // must process the invite before any new messages can be decrypted
@@ -246,6 +249,7 @@ async fn manual_chat() -> Result<(), Box<dyn std::error::Error>> {
     let (fanned, welcome_option) = charlie
         .kick_member(gid.clone(), alice_key_package)
         .context("failed to kick member")?;
+    charlie.group_merge_pending_commit(&gid).context("failed to merge commit")?;
 
     // bob processes the fanned commit:
     bob.process_message(fanned.clone())
@@ -282,6 +286,7 @@ async fn manual_chat() -> Result<(), Box<dyn std::error::Error>> {
     let proposed_invite = processed_results
         .invite
         .expect("invite not found");
+    bob.group_merge_pending_commit(&gid).context("failed to merge commit")?;
     println!("<------ Bob processed the proposal! ------->");
 
     println!("<------ David joins the group! ------->");
@@ -354,17 +359,26 @@ async fn client() -> Result<(), Box<dyn std::error::Error>> {
     let mut bob_client = ConvoClient::new(bob_name.clone());
     bob_client.connect_to_server(server_address.clone()).await;
 
-    // alice calls list_users, notices bob, and invites bob to join the group
-    let _ = alice_client.list_users().await;
-    let users_list = bob_client.list_users().await.context("failed to list users")?;
+    // bob claims his handle in the directory:
+    bob_client
+        .register(bob_name.clone(), "Bob".to_string(), Default::default())
+        .await
+        .context("failed to register bob")?;
 
-    let bob_user = users_list
-        .iter()
-        .find(|user| user.user_id == bob_name.clone())
-        .expect("bob not found!");
+    // alice looks bob up by his handle, and invites bob to join the group
+    let bob_user = alice_client
+        .find_user(&bob_name)
+        .await
+        .context("failed to find bob")?;
 
     let bob_user_id = bob_user.user_id.clone();
-    let bob_key_package = bob_user.serialized_key_package.clone();
+    // key packages are single use, so alice asks the server for one of bob's just before inviting him:
+    let bob_key_package = alice_client
+        .get_user_key_packages(vec![bob_user_id.clone()])
+        .await
+        .context("failed to get bob's key package")?
+        .remove(&bob_user_id)
+        .context("no key package for bob")?;
 
     println!("<!------ Alice creates a new group (alphabet_group)! ------->");
     alice_client.create_group(gn.clone()).await;
@@ -457,7 +471,12 @@ async fn client() -> Result<(), Box<dyn std::error::Error>> {
         .expect("charlie not found!");
 
     let charlie_user_id = charlie_user.user_id.clone();
-    let charlie_key_package = charlie_user.serialized_key_package.clone();
+    let charlie_key_package = alice_client
+        .get_user_key_packages(vec![charlie_user_id.clone()])
+        .await
+        .context("failed to get charlie's key package")?
+        .remove(&charlie_user_id)
+        .context("no key package for charlie")?;
 
     println!("<!------ Alice invites charlie to the group! ------->");
     // invite charlie to the group:
@@ -0,0 +1,100 @@
+// src/admin.rs
+
+// request and response bodies for the operator routes under `ADMIN_PREFIX`.
+// every admin request carries `Authorization: Bearer <admin token>`, requests are sent
+// in a `Versioned` envelope like the rest of the api.
+
+use serde::{Deserialize, Serialize};
+
+use crate::encoding;
+use crate::types::PresenceStatus;
+
+type GroupId = Vec<u8>;
+
+/// Prefix the admin routes are mounted under (only when the server has an admin token set).
+pub const ADMIN_PREFIX: &str = "/admin";
+
+// GET /admin/users
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AdminUser {
+    pub user_id: String,
+    pub handle: Option<String>,
+    pub created_at: u64,
+    pub status: PresenceStatus,
+    pub last_seen: Option<u64>, // None if the user hasn't made a request since the server started
+    pub key_packages: u64,      // one-time key packages left
+    pub has_last_resort: bool,
+    pub mailbox_depth: u64, // messages waiting to be fetched
+    pub groups: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AdminUsersResponse {
+    pub users: Vec<AdminUser>,
+}
+
+// GET /admin/groups
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AdminGroup {
+    #[serde(with = "encoding::bytes")]
+    pub group_id: GroupId,
+    pub group_name: String,
+    pub creator_id: String,
+    pub members: u64,
+    pub invited: u64,
+    pub pending_removals: u64,
+    pub global_index: u64,
+    pub messages: u64,     // messages kept for members to fetch
+    pub stored_bytes: u64, // counted against the group's storage quota
+    pub last_message_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AdminGroupsResponse {
+    pub groups: Vec<AdminGroup>,
+}
+
+// GET /admin/queues
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueueDepths {
+    pub mailboxes: u64,        // users with messages waiting
+    pub mailbox_messages: u64, // messages waiting, across every mailbox
+    pub largest_mailbox: Option<(String, u64)>,
+    pub group_messages: u64, // messages kept across every group
+    pub group_bytes: u64,
+    pub push_subscribers: u64, // open /events streams
+    pub push_backlog: u64,     // events the slowest subscriber hasn't received yet
+}
+
+// POST /admin/remove_user
+// deletes the account as if the user had asked for it (see `DeleteAccount`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AdminRemoveUser {
+    pub user_id: String,
+}
+
+// POST /admin/delete_group
+// the group and its messages are gone for everyone, members are sent a `PushEvent::GroupDeleted`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AdminDeleteGroup {
+    #[serde(with = "encoding::bytes")]
+    pub group_id: GroupId,
+}
+
+// POST /admin/compact
+// drops expired key packages, idle rate limit state and groups nobody is left in.
+// with `keep_messages` set, each group's history is also cut down to its newest messages
+// (members that are further behind than that will miss the older ones)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Compact {
+    #[serde(default)]
+    pub keep_messages: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CompactionReport {
+    pub key_packages_dropped: u64,
+    pub groups_dropped: u64,
+    pub messages_dropped: u64,
+    pub bytes_freed: u64,
+}
@@ -0,0 +1,492 @@
+// src/api.rs
+
+// request and response bodies for every delivery service route.
+// requests are sent wrapped in a `Versioned` envelope, responses are sent as-is.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::encoding;
+use crate::types::{ConvoMessage, Presence, PresenceStatus, Profile, UserInfo};
+
+/// `AccountExport::format_version` produced by this build.
+pub const ACCOUNT_EXPORT_VERSION: u32 = 1;
+
+type GroupId = Vec<u8>;
+
+/// Prefix the current wire version's routes are mounted under.
+pub const API_PREFIX: &str = "/api/v1";
+/// Prefix of the unversioned routes kept for clients that predate `API_PREFIX`.
+pub const LEGACY_API_PREFIX: &str = "/api";
+
+// GET /api/capabilities (always under the unversioned prefix so any client can find it)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Capabilities {
+    pub wire_versions: Vec<u32>, // every request version the server understands
+    pub features: Vec<String>,   // see `features`
+}
+
+impl Capabilities {
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+
+    /// The highest wire version both sides speak, if any.
+    pub fn negotiate(&self, ours: &[u32]) -> Option<u32> {
+        self.wire_versions
+            .iter()
+            .filter(|v| ours.contains(v))
+            .max()
+            .copied()
+    }
+}
+
+/// Optional server features advertised in `Capabilities::features`.
+pub mod features {
+    /// routes are mounted under `API_PREFIX`
+    pub const VERSIONED_API: &str = "versioned_api";
+    /// request and response bodies may be sent as `application/cbor`
+    pub const CBOR: &str = "cbor";
+    /// request and response bodies may be sent with `Content-Encoding: deflate`
+    pub const DEFLATE: &str = "deflate";
+    /// `/sync` fetches every group and the mailbox in one call
+    pub const SYNC: &str = "sync";
+    /// users upload a pool of one-time key packages (`/upload_key_packages`), each fetch consumes one
+    pub const KEY_PACKAGE_POOL: &str = "key_package_pool";
+    /// `/presence` and `/get_presence` are available
+    pub const PRESENCE: &str = "presence";
+    /// `/events` streams `PushEvent`s as server-sent events
+    pub const PUSH: &str = "push";
+    /// `/register`, `/search_users` and `/get_profiles` are available
+    pub const DIRECTORY: &str = "directory";
+    /// accounts are created with `/create_account` before `/connect`, and can be deleted / exported
+    pub const ACCOUNTS: &str = "accounts";
+    /// `/events` also streams `GroupUpdated` / `MailboxUpdated`, so clients needn't poll for messages
+    pub const GROUP_EVENTS: &str = "group_events";
+    /// `/request_rejoin` and `/readd_user` let a member with a broken copy of a group back in
+    pub const REJOIN: &str = "rejoin";
+}
+
+// POST /create_account
+// conflict if the user_id is taken, /connect only works for existing accounts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CreateAccount {
+    pub user_id: String,
+    // the account's first key package, left out by clients that upload a pool right after
+    #[serde(default, with = "encoding::option_bytes")]
+    pub serialized_key_package: Option<Vec<u8>>,
+    // the public key of the account's credential, `AccountProof`s are checked against it.
+    // taken from the key package when left out
+    #[serde(default, with = "encoding::option_bytes")]
+    pub signature_key: Option<Vec<u8>>,
+}
+
+// shows a request comes from the account's owner: a signature over `payload` with the
+// account's credential key. only accepted for a few minutes after `timestamp`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AccountProof {
+    pub timestamp: u64, // unix seconds
+    #[serde(with = "encoding::bytes")]
+    pub signature: Vec<u8>,
+}
+
+impl AccountProof {
+    /// What gets signed: the route, so a proof for one can't be used for another, then who
+    /// and when.
+    pub fn payload(action: &str, user_id: &str, timestamp: u64) -> Vec<u8> {
+        format!("skychat {} {} {}", action, user_id, timestamp).into_bytes()
+    }
+}
+
+// POST /delete_account
+// drops the account's key packages, mailbox, profile and presence straight away.
+// the account loses access to its groups immediately, the MLS removal is left to the
+// remaining members (see `GroupMessages::pending_removals`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeleteAccount {
+    pub user_id: String,
+    pub proof: AccountProof,
+}
+
+impl DeleteAccount {
+    pub const ACTION: &'static str = "delete_account";
+}
+
+// POST /export_account
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportAccount {
+    pub user_id: String,
+    pub proof: AccountProof,
+}
+
+impl ExportAccount {
+    pub const ACTION: &'static str = "export_account";
+}
+
+// GET /events, as query parameters
+// streams the `PushEvent`s user_id gets to see. with an `AccountProof` for ACTION (its fields
+// spelled out, as a query can't nest) having the stream open also counts as being online
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OpenEvents {
+    pub user_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "encoding::option_bytes"
+    )]
+    pub signature: Option<Vec<u8>>,
+}
+
+impl OpenEvents {
+    pub const ACTION: &'static str = "events";
+
+    pub fn new(user_id: String, proof: Option<AccountProof>) -> Self {
+        let (timestamp, signature) = proof
+            .map(|proof| (proof.timestamp, proof.signature))
+            .unzip();
+        Self {
+            user_id,
+            timestamp,
+            signature,
+        }
+    }
+
+    pub fn proof(&self) -> Option<AccountProof> {
+        Some(AccountProof {
+            timestamp: self.timestamp?,
+            signature: self.signature.clone()?,
+        })
+    }
+}
+
+/// Everything the server holds about one account.
+///
+/// Group message contents aren't included: they're end-to-end encrypted and the server
+/// doesn't record who sent them. Fields may be added in later `format_version`s,
+/// existing ones keep their meaning.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AccountExport {
+    pub format_version: u32, // `ACCOUNT_EXPORT_VERSION`
+    pub exported_at: u64,    // unix seconds
+    pub user_id: String,
+    pub created_at: u64, // unix seconds
+    pub profile: Option<Profile>,
+    pub presence: Option<Presence>,
+    // published key packages not yet handed out, including the last resort one
+    pub key_packages: Vec<ExportedKeyPackage>,
+    // groups the account is in or invited to
+    pub groups: Vec<ExportedGroup>,
+    // undelivered invites etc., exporting doesn't consume them
+    pub mailbox: Vec<ConvoMessage>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportedKeyPackage {
+    #[serde(with = "encoding::bytes")]
+    pub serialized: Vec<u8>,
+    pub expires: u64, // unix seconds
+    pub last_resort: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportedGroup {
+    #[serde(with = "encoding::bytes")]
+    pub group_id: GroupId,
+    pub group_name: String,
+    pub created_by_user: bool, // the account created this group
+    pub invited: bool,         // invited, hasn't accepted yet
+    pub global_index: u64,     // how many messages the group has
+}
+
+// POST /connect
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Connect {
+    pub user_id: String,
+    // replaces the account's one-time key packages, left out to keep the pool as it is
+    #[serde(default, with = "encoding::option_bytes")]
+    pub serialized_key_package: Option<Vec<u8>>,
+}
+
+// POST /upload_key_packages
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UploadKeyPackages {
+    pub user_id: String,
+    // one-time key packages, each one is handed out to at most one inviter
+    #[serde(with = "encoding::bytes_list")]
+    pub key_packages: Vec<Vec<u8>>,
+    // handed out (repeatedly) once the one-time packages run out, replaces the previous one
+    #[serde(default, with = "encoding::option_bytes")]
+    pub last_resort: Option<Vec<u8>>,
+    // drop the previously uploaded one-time packages first (used when rotating)
+    #[serde(default)]
+    pub replace: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyPackageStock {
+    pub remaining: u64, // one-time key packages left on the server
+    pub low: bool,      // time to upload some more
+}
+
+// GET /list_users
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UsersResponse {
+    pub users: Vec<UserInfo>,
+}
+
+// POST /register
+// reserves `handle` for the user (conflict if someone else has it) and sets their profile.
+// registering again updates the profile, a new handle releases the old one
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Register {
+    pub user_id: String,
+    pub handle: String,
+    pub display_name: String,
+    #[serde(default)]
+    pub fields: BTreeMap<String, String>,
+}
+
+// POST /search_users
+// matches handles and display names, case insensitive, sorted by handle.
+// an empty query pages through everyone
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchUsers {
+    pub query: String,
+    #[serde(default)]
+    pub offset: u64,
+    pub limit: u64, // capped by the server
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchUsersResponse {
+    pub users: Vec<Profile>,
+    // pass as `offset` to get the next page, None on the last page
+    #[serde(default)]
+    pub next_offset: Option<u64>,
+}
+
+// POST /get_profiles
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GetProfiles {
+    pub user_ids: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProfilesResponse {
+    pub users: Vec<Profile>, // unregistered users are left out
+}
+
+// POST /presence
+// e.g. away when the app goes to the background, offline when logging out.
+// any other request marks the user online again.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SetPresence {
+    pub user_id: String,
+    pub status: PresenceStatus,
+}
+
+// POST /get_presence
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GetPresence {
+    pub user_ids: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PresenceResponse {
+    pub presence: Vec<Presence>, // users the server has never seen are left out
+}
+
+// GET /events?user_id=<user_id>
+// a text/event-stream of json `PushEvent`s
+
+// POST /send_message
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SendMessage {
+    #[serde(with = "encoding::bytes")]
+    pub group_id: GroupId,
+    #[serde(with = "encoding::bytes")]
+    pub message: Vec<u8>,
+    pub sender_id: String,
+    pub global_index: u64,
+}
+
+// POST /create_group
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CreateGroup {
+    #[serde(with = "encoding::bytes")]
+    pub group_id: GroupId,
+    pub group_name: String,
+    pub sender_id: String, // the user creating the group
+}
+
+// POST /get_user_keys
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GetUserKeys {
+    pub user_ids: Vec<String>,
+    pub sender_id: String, // the user asking, fetches are limited per sender too
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserKeysResponse {
+    // user_id -> serialized key package
+    #[serde(with = "encoding::bytes_map")]
+    pub key_packages: HashMap<String, Vec<u8>>,
+}
+
+// POST /get_new_messages
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GetMessages {
+    #[serde(default, with = "encoding::option_bytes")]
+    pub group_id: Option<GroupId>, // the group to get messages from
+    pub sender_id: String,         // the user requesting the messages
+    pub index: u64,                // only messages after this index are returned
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MessagesResponse {
+    pub messages: Vec<ConvoMessage>,
+}
+
+// POST /invite_user
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InviteUser {
+    #[serde(with = "encoding::bytes")]
+    pub group_id: GroupId, // the group to invite the user to
+    pub sender_id: String,   // the user sending the invite
+    pub receiver_id: String, // the user to invite
+    #[serde(with = "encoding::bytes")]
+    pub welcome_message: Vec<u8>, // the welcome message to send to the user
+    #[serde(with = "encoding::bytes")]
+    pub ratchet_tree: Vec<u8>, // the ratchet tree to send to the user
+    #[serde(default, with = "encoding::option_bytes")]
+    pub fanned: Option<Vec<u8>>, // the fanned commit to send to all other users in the group
+}
+
+// POST /remove_user
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RemoveUser {
+    #[serde(with = "encoding::bytes")]
+    pub group_id: GroupId,
+    pub sender_id: String, // the member doing the removing (may be the user themselves, i.e. leaving)
+    pub user_id: String,   // the member to remove
+    #[serde(with = "encoding::bytes")]
+    pub fanned: Vec<u8>, // the remove commit to send to the remaining members
+}
+
+// POST /request_rejoin
+// for a member whose copy of the group is beyond repair (e.g. restored from an old backup):
+// replaces any earlier request of theirs, the other members see it in `GroupMessages`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RequestRejoin {
+    #[serde(with = "encoding::bytes")]
+    pub group_id: GroupId,
+    pub sender_id: String,
+    #[serde(with = "encoding::bytes")]
+    pub key_package: Vec<u8>, // a fresh one, for the welcome back in
+}
+
+// POST /readd_user
+// answers a rejoin request: one commit removing the member's stale leaf and adding them back.
+// the welcome goes to their mailbox like an invite. stale_epoch if another commit got in
+// first, not_found if the request has already been answered
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReaddUser {
+    #[serde(with = "encoding::bytes")]
+    pub group_id: GroupId,
+    pub sender_id: String,
+    pub user_id: String, // the member asking to rejoin
+    #[serde(with = "encoding::bytes")]
+    pub welcome_message: Vec<u8>,
+    #[serde(with = "encoding::bytes")]
+    pub fanned: Vec<u8>, // the commit, for everyone else
+}
+
+// POST /accept_invite
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AcceptInvite {
+    #[serde(with = "encoding::bytes")]
+    pub group_id: GroupId,
+    pub sender_id: String,
+}
+
+// POST /group_index
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GetGroupIndex {
+    #[serde(with = "encoding::bytes")]
+    pub group_id: GroupId,
+    pub sender_id: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroupIndexResponse {
+    pub global_index: u64,
+}
+
+// POST /sync
+// one call to catch up on every group we're in plus our mailbox (invites etc.)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sync {
+    pub sender_id: String,
+    // group_id -> the last global_index we've seen in that group
+    #[serde(with = "encoding::bytes_key_map")]
+    pub groups: HashMap<GroupId, u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroupMessages {
+    pub messages: Vec<ConvoMessage>, // messages after the requested index, in order
+    pub global_index: u64,           // the group's current index on the server
+    // deleted accounts still in the group's MLS tree, waiting for a member to commit their removal
+    #[serde(default)]
+    pub pending_removals: Vec<String>,
+    // members asking to be re-added, see `RequestRejoin`
+    #[serde(default)]
+    pub rejoin_requests: Vec<RejoinRequest>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RejoinRequest {
+    pub user_id: String,
+    #[serde(with = "encoding::bytes")]
+    pub key_package: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SyncResponse {
+    // only groups the server knows about are included
+    #[serde(with = "encoding::bytes_key_map")]
+    pub groups: HashMap<GroupId, GroupMessages>,
+    pub mailbox: Vec<ConvoMessage>,
+    // set (to the number left) when the sender is running out of one-time key packages
+    #[serde(default)]
+    pub key_packages_low: Option<u64>,
+}
+
+// the body of every non-2xx response
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiError {
+    pub code: String, // see `error_codes`
+    pub message: String,
+    // seconds to wait before retrying, set for `error_codes::RATE_LIMITED`
+    // (also sent as a `Retry-After` header)
+    #[serde(default)]
+    pub retry_after: Option<u64>,
+}
+
+/// Machine readable `ApiError::code` values.
+pub mod error_codes {
+    pub const NOT_FOUND: &str = "not_found";
+    /// the sender isn't allowed to touch this group (not a member, not the creator, ...)
+    pub const FORBIDDEN: &str = "forbidden";
+    pub const CONFLICT: &str = "conflict";
+    /// a commit made in an epoch the group has already moved past, sync and make it again
+    pub const STALE_EPOCH: &str = "stale_epoch";
+    pub const BAD_REQUEST: &str = "bad_request";
+    /// over the message size limit or a storage quota
+    pub const TOO_LARGE: &str = "too_large";
+    /// slow down, see `ApiError::retry_after`
+    pub const RATE_LIMITED: &str = "rate_limited";
+    pub const INTERNAL: &str = "internal";
+}
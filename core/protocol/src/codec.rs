@@ -0,0 +1,76 @@
+// src/codec.rs
+
+// body encodings for the wire types.
+// json is the default (and what older clients speak), cbor keeps binary fields as raw bytes.
+// either can additionally be compressed (http "deflate", i.e. zlib framing).
+
+use anyhow::{Context, Result};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io::{Read, Write};
+
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+pub const CBOR_CONTENT_TYPE: &str = "application/cbor";
+/// `Content-Encoding` / `Accept-Encoding` value for compressed bodies
+pub const DEFLATE_ENCODING: &str = "deflate";
+
+/// Bodies smaller than this aren't worth compressing.
+pub const COMPRESSION_THRESHOLD: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireFormat {
+    Json,
+    Cbor,
+}
+
+impl WireFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            WireFormat::Json => JSON_CONTENT_TYPE,
+            WireFormat::Cbor => CBOR_CONTENT_TYPE,
+        }
+    }
+
+    /// Picks the format from a `Content-Type` / `Accept` header value, defaulting to json.
+    pub fn from_media_type(media_type: Option<&str>) -> Self {
+        match media_type {
+            Some(media_type) if media_type.contains(CBOR_CONTENT_TYPE) => WireFormat::Cbor,
+            _ => WireFormat::Json,
+        }
+    }
+}
+
+pub fn encode<T: Serialize>(value: &T, format: WireFormat) -> Result<Vec<u8>> {
+    match format {
+        WireFormat::Json => serde_json::to_vec(value).context("Failed to encode json body"),
+        WireFormat::Cbor => {
+            let mut buffer = Vec::new();
+            ciborium::into_writer(value, &mut buffer).context("Failed to encode cbor body")?;
+            Ok(buffer)
+        }
+    }
+}
+
+pub fn decode<T: DeserializeOwned>(bytes: &[u8], format: WireFormat) -> Result<T> {
+    match format {
+        WireFormat::Json => serde_json::from_slice(bytes).context("Failed to decode json body"),
+        WireFormat::Cbor => ciborium::from_reader(bytes).context("Failed to decode cbor body"),
+    }
+}
+
+pub fn compress(bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(bytes).context("Failed to compress body")?;
+    encoder.finish().context("Failed to compress body")
+}
+
+pub fn decompress(bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    ZlibDecoder::new(bytes)
+        .read_to_end(&mut decompressed)
+        .context("Failed to decompress body")?;
+    Ok(decompressed)
+}
@@ -0,0 +1,217 @@
+// src/encoding.rs
+
+// serde helpers for binary fields.
+// human readable formats (json) get url-safe base64 strings, binary formats get raw bytes,
+// so the same types can be used for every transport without bloating the binary ones.
+
+use base64::{engine::general_purpose, Engine as _};
+use serde::de::{self, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{SerializeMap, Serializer};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a base64 string or a byte buffer")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        general_purpose::URL_SAFE
+            .decode(v)
+            .map_err(|e| E::custom(format!("invalid base64: {}", e)))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        Ok(v.to_vec())
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(v)
+    }
+
+    // older clients sent binary fields as json number arrays, keep accepting them:
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element::<u8>()? {
+            bytes.push(byte);
+        }
+        Ok(bytes)
+    }
+}
+
+fn serialize_bytes<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    if serializer.is_human_readable() {
+        serializer.serialize_str(&general_purpose::URL_SAFE.encode(bytes))
+    } else {
+        serializer.serialize_bytes(bytes)
+    }
+}
+
+fn deserialize_bytes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    if deserializer.is_human_readable() {
+        deserializer.deserialize_any(BytesVisitor)
+    } else {
+        deserializer.deserialize_byte_buf(BytesVisitor)
+    }
+}
+
+// wrapper so the helpers below can reuse the visitor for nested values:
+struct EncodedBytes(Vec<u8>);
+
+impl serde::Serialize for EncodedBytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_bytes(&self.0, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for EncodedBytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_bytes(deserializer).map(EncodedBytes)
+    }
+}
+
+/// `#[serde(with = "encoding::bytes")]` for `Vec<u8>` fields
+pub mod bytes {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_bytes(bytes, serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        deserialize_bytes(deserializer)
+    }
+}
+
+/// `#[serde(with = "encoding::option_bytes")]` for `Option<Vec<u8>>` fields
+pub mod option_bytes {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        bytes: &Option<Vec<u8>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match bytes {
+            Some(bytes) => serializer.serialize_some(&EncodedBytes(bytes.clone())),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<u8>>, D::Error> {
+        let bytes: Option<EncodedBytes> = Option::deserialize(deserializer)?;
+        Ok(bytes.map(|b| b.0))
+    }
+}
+
+/// `#[serde(with = "encoding::bytes_list")]` for `Vec<Vec<u8>>` fields
+pub mod bytes_list {
+    use super::*;
+    use serde::ser::SerializeSeq;
+
+    pub fn serialize<S: Serializer>(list: &[Vec<u8>], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut out = serializer.serialize_seq(Some(list.len()))?;
+        for bytes in list {
+            out.serialize_element(&EncodedBytes(bytes.clone()))?;
+        }
+        out.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Vec<u8>>, D::Error> {
+        let list: Vec<EncodedBytes> = Vec::deserialize(deserializer)?;
+        Ok(list.into_iter().map(|b| b.0).collect())
+    }
+}
+
+/// `#[serde(with = "encoding::bytes_map")]` for maps whose values are `Vec<u8>`
+pub mod bytes_map {
+    use super::*;
+
+    pub fn serialize<K, S>(map: &HashMap<K, Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        K: serde::Serialize,
+        S: Serializer,
+    {
+        let mut out = serializer.serialize_map(Some(map.len()))?;
+        for (key, value) in map {
+            out.serialize_entry(key, &EncodedBytes(value.clone()))?;
+        }
+        out.end()
+    }
+
+    pub fn deserialize<'de, K, D>(deserializer: D) -> Result<HashMap<K, Vec<u8>>, D::Error>
+    where
+        K: Deserialize<'de> + Eq + Hash,
+        D: Deserializer<'de>,
+    {
+        struct MapVisitor<K>(std::marker::PhantomData<K>);
+
+        impl<'de, K: Deserialize<'de> + Eq + Hash> Visitor<'de> for MapVisitor<K> {
+            type Value = HashMap<K, Vec<u8>>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a map of byte buffers")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
+                let mut map = HashMap::with_capacity(access.size_hint().unwrap_or(0));
+                while let Some((key, value)) = access.next_entry::<K, EncodedBytes>()? {
+                    map.insert(key, value.0);
+                }
+                Ok(map)
+            }
+        }
+
+        deserializer.deserialize_map(MapVisitor(std::marker::PhantomData))
+    }
+}
+
+/// `#[serde(with = "encoding::bytes_key_map")]` for maps keyed by `Vec<u8>` (e.g. group ids)
+pub mod bytes_key_map {
+    use super::*;
+
+    pub fn serialize<V, S>(map: &HashMap<Vec<u8>, V>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        V: serde::Serialize,
+        S: Serializer,
+    {
+        let mut out = serializer.serialize_map(Some(map.len()))?;
+        for (key, value) in map {
+            out.serialize_entry(&EncodedBytes(key.clone()), value)?;
+        }
+        out.end()
+    }
+
+    pub fn deserialize<'de, V, D>(deserializer: D) -> Result<HashMap<Vec<u8>, V>, D::Error>
+    where
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        struct MapVisitor<V>(std::marker::PhantomData<V>);
+
+        impl<'de, V: Deserialize<'de>> Visitor<'de> for MapVisitor<V> {
+            type Value = HashMap<Vec<u8>, V>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a map keyed by byte buffers")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
+                let mut map = HashMap::with_capacity(access.size_hint().unwrap_or(0));
+                while let Some((key, value)) = access.next_entry::<EncodedBytes, V>()? {
+                    map.insert(key.0, value);
+                }
+                Ok(map)
+            }
+        }
+
+        deserializer.deserialize_map(MapVisitor(std::marker::PhantomData))
+    }
+}
@@ -0,0 +1,437 @@
+pub mod admin;
+pub mod api;
+pub mod codec;
+pub mod encoding;
+pub mod types;
+
+pub use admin::*;
+pub use api::*;
+pub use types::*;
+
+use serde::{Deserialize, Serialize};
+
+/// The wire version spoken by this build.
+/// Bump whenever a request or response type changes in a way older peers can't read.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Every wire version this build can speak, oldest first.
+pub const SUPPORTED_VERSIONS: &[u32] = &[PROTOCOL_VERSION];
+
+/// Envelope every request body is sent in.
+///
+/// The body's fields are flattened next to `version`, so a request looks like
+/// `{ "version": 1, "group_id": "...", ... }`. Bodies without a version (older clients)
+/// deserialize with `version: 0`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Versioned<T> {
+    #[serde(default)]
+    pub version: u32,
+    #[serde(flatten)]
+    pub body: T,
+}
+
+impl<T> Versioned<T> {
+    pub fn new(body: T) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            body,
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.body
+    }
+}
+
+impl<T> std::ops::Deref for Versioned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.body
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::de::DeserializeOwned;
+    use std::collections::{BTreeMap, HashMap};
+    use std::fmt::Debug;
+
+    fn round_trip<T: Serialize + DeserializeOwned + PartialEq + Debug>(value: T) {
+        let json = serde_json::to_string(&value).unwrap();
+        let decoded: T = serde_json::from_str(&json).unwrap();
+        assert_eq!(value, decoded);
+    }
+
+    fn invite() -> ConvoInvite {
+        ConvoInvite {
+            group_name: "group".to_string(),
+            welcome_message: vec![1, 2, 3],
+            ratchet_tree: Some(vec![4, 5]),
+            global_index: 7,
+            fanned: None,
+        }
+    }
+
+    #[test]
+    fn requests_round_trip() {
+        round_trip(Versioned::new(Connect {
+            user_id: "alice".to_string(),
+            serialized_key_package: Some(vec![0, 255, 16]),
+        }));
+        round_trip(Versioned::new(CreateAccount {
+            user_id: "alice".to_string(),
+            serialized_key_package: None,
+            signature_key: Some(vec![7; 32]),
+        }));
+        round_trip(Versioned::new(DeleteAccount {
+            user_id: "alice".to_string(),
+            proof: AccountProof {
+                timestamp: 10,
+                signature: vec![1, 2, 3],
+            },
+        }));
+        round_trip(Versioned::new(ExportAccount {
+            user_id: "alice".to_string(),
+            proof: AccountProof {
+                timestamp: 10,
+                signature: vec![1, 2, 3],
+            },
+        }));
+        round_trip(Versioned::new(UploadKeyPackages {
+            user_id: "alice".to_string(),
+            key_packages: vec![vec![1, 2], vec![3, 4]],
+            last_resort: Some(vec![5, 6]),
+            replace: false,
+        }));
+        round_trip(Versioned::new(Register {
+            user_id: "alice".to_string(),
+            handle: "alice".to_string(),
+            display_name: "Alice".to_string(),
+            fields: BTreeMap::from([("bio".to_string(), "hi".to_string())]),
+        }));
+        round_trip(Versioned::new(SearchUsers {
+            query: "ali".to_string(),
+            offset: 20,
+            limit: 20,
+        }));
+        round_trip(Versioned::new(GetProfiles {
+            user_ids: vec!["bob".to_string()],
+        }));
+        round_trip(Versioned::new(SetPresence {
+            user_id: "alice".to_string(),
+            status: PresenceStatus::Away,
+        }));
+        round_trip(Versioned::new(GetPresence {
+            user_ids: vec!["bob".to_string()],
+        }));
+        round_trip(Versioned::new(SendMessage {
+            group_id: vec![9; 16],
+            message: vec![1; 64],
+            sender_id: "alice".to_string(),
+            global_index: 3,
+        }));
+        round_trip(Versioned::new(CreateGroup {
+            group_id: vec![9; 16],
+            group_name: "group".to_string(),
+            sender_id: "alice".to_string(),
+        }));
+        round_trip(Versioned::new(GetUserKeys {
+            user_ids: vec!["bob".to_string()],
+            sender_id: "alice".to_string(),
+        }));
+        round_trip(Versioned::new(GetMessages {
+            group_id: None,
+            sender_id: "bob".to_string(),
+            index: 0,
+        }));
+        round_trip(Versioned::new(InviteUser {
+            group_id: vec![9; 16],
+            sender_id: "alice".to_string(),
+            receiver_id: "bob".to_string(),
+            welcome_message: vec![1, 2],
+            ratchet_tree: vec![3, 4],
+            fanned: Some(vec![5]),
+        }));
+        round_trip(Versioned::new(RemoveUser {
+            group_id: vec![9; 16],
+            sender_id: "alice".to_string(),
+            user_id: "bob".to_string(),
+            fanned: vec![5],
+        }));
+        round_trip(Versioned::new(RequestRejoin {
+            group_id: vec![9; 16],
+            sender_id: "bob".to_string(),
+            key_package: vec![1, 2, 3],
+        }));
+        round_trip(Versioned::new(ReaddUser {
+            group_id: vec![9; 16],
+            sender_id: "alice".to_string(),
+            user_id: "bob".to_string(),
+            welcome_message: vec![4],
+            fanned: vec![5],
+        }));
+        round_trip(Versioned::new(AcceptInvite {
+            group_id: vec![9; 16],
+            sender_id: "bob".to_string(),
+        }));
+        round_trip(Versioned::new(GetGroupIndex {
+            group_id: vec![9; 16],
+            sender_id: "bob".to_string(),
+        }));
+        round_trip(Versioned::new(Sync {
+            sender_id: "bob".to_string(),
+            groups: HashMap::from([(vec![9; 16], 3), (vec![8; 16], 0)]),
+        }));
+    }
+
+    #[test]
+    fn responses_round_trip() {
+        round_trip(UsersResponse {
+            users: vec![UserInfo {
+                user_id: "bob".to_string(),
+                serialized_key_package: vec![1, 2, 3],
+                last_active: 10,
+            }],
+        });
+        round_trip(UserKeysResponse {
+            key_packages: HashMap::from([("bob".to_string(), vec![1, 2, 3])]),
+        });
+        round_trip(MessagesResponse {
+            messages: vec![
+                ConvoMessage {
+                    global_index: 1,
+                    unix_timestamp: 2,
+                    encrypted: Some(vec![1, 2, 3]),
+                    invite: None,
+                },
+                ConvoMessage {
+                    global_index: 2,
+                    unix_timestamp: 3,
+                    encrypted: None,
+                    invite: Some(invite()),
+                },
+            ],
+        });
+        round_trip(GroupIndexResponse { global_index: 5 });
+        round_trip(SyncResponse {
+            groups: HashMap::from([(
+                vec![9; 16],
+                GroupMessages {
+                    messages: vec![ConvoMessage {
+                        global_index: 4,
+                        unix_timestamp: 2,
+                        encrypted: Some(vec![1, 2, 3]),
+                        invite: None,
+                    }],
+                    global_index: 4,
+                    pending_removals: vec!["carol".to_string()],
+                    rejoin_requests: vec![RejoinRequest {
+                        user_id: "dave".to_string(),
+                        key_package: vec![4, 5],
+                    }],
+                },
+            )]),
+            mailbox: vec![ConvoMessage {
+                global_index: 0,
+                unix_timestamp: 3,
+                encrypted: None,
+                invite: Some(invite()),
+            }],
+            key_packages_low: Some(2),
+        });
+        round_trip(KeyPackageStock {
+            remaining: 2,
+            low: true,
+        });
+        round_trip(SearchUsersResponse {
+            users: vec![Profile {
+                user_id: "bob".to_string(),
+                handle: "bob".to_string(),
+                display_name: "Bob".to_string(),
+                fields: BTreeMap::new(),
+            }],
+            next_offset: Some(20),
+        });
+        round_trip(PresenceResponse {
+            presence: vec![Presence {
+                user_id: "bob".to_string(),
+                status: PresenceStatus::Online,
+                last_seen: 10,
+            }],
+        });
+        round_trip(PushEvent::Presence(Presence {
+            user_id: "bob".to_string(),
+            status: PresenceStatus::Offline,
+            last_seen: 10,
+        }));
+        round_trip(PushEvent::AccountDeleted {
+            user_id: "carol".to_string(),
+        });
+        round_trip(PushEvent::GroupDeleted {
+            group_id: vec![1, 2, 3],
+        });
+        round_trip(PushEvent::GroupUpdated {
+            group_id: vec![1, 2, 3],
+            global_index: 4,
+        });
+        round_trip(PushEvent::MailboxUpdated {
+            user_id: "dave".to_string(),
+        });
+        round_trip(AdminGroupsResponse {
+            groups: vec![AdminGroup {
+                group_id: vec![1, 2, 3],
+                group_name: "group".to_string(),
+                creator_id: "alice".to_string(),
+                members: 2,
+                invited: 1,
+                pending_removals: 0,
+                global_index: 4,
+                messages: 4,
+                stored_bytes: 400,
+                last_message_at: Some(20),
+            }],
+        });
+        round_trip(QueueDepths {
+            mailboxes: 1,
+            mailbox_messages: 3,
+            largest_mailbox: Some(("bob".to_string(), 3)),
+            group_messages: 4,
+            group_bytes: 400,
+            push_subscribers: 2,
+            push_backlog: 0,
+        });
+        round_trip(Compact {
+            keep_messages: Some(100),
+        });
+        round_trip(AccountExport {
+            format_version: ACCOUNT_EXPORT_VERSION,
+            exported_at: 20,
+            user_id: "bob".to_string(),
+            created_at: 10,
+            profile: None,
+            presence: None,
+            key_packages: vec![ExportedKeyPackage {
+                serialized: vec![1, 2, 3],
+                expires: 30,
+                last_resort: true,
+            }],
+            groups: vec![ExportedGroup {
+                group_id: vec![9; 16],
+                group_name: "group".to_string(),
+                created_by_user: false,
+                invited: true,
+                global_index: 4,
+            }],
+            mailbox: vec![],
+        });
+        round_trip(ApiError {
+            code: error_codes::FORBIDDEN.to_string(),
+            message: "Not a member of this group".to_string(),
+            retry_after: None,
+        });
+        round_trip(ApiError {
+            code: error_codes::RATE_LIMITED.to_string(),
+            message: "Too many requests".to_string(),
+            retry_after: Some(3),
+        });
+        round_trip(Capabilities {
+            wire_versions: vec![1],
+            features: vec![features::VERSIONED_API.to_string()],
+        });
+    }
+
+    #[test]
+    fn negotiates_highest_common_version() {
+        let capabilities = Capabilities {
+            wire_versions: vec![1, 2, 3],
+            features: vec![],
+        };
+
+        assert_eq!(capabilities.negotiate(&[1, 2]), Some(2));
+        assert_eq!(capabilities.negotiate(&[4]), None);
+    }
+
+    #[test]
+    fn cbor_round_trip_keeps_raw_bytes() {
+        let request = Versioned::new(SendMessage {
+            group_id: vec![7; 16],
+            message: vec![42; 1024],
+            sender_id: "alice".to_string(),
+            global_index: 9,
+        });
+
+        let cbor = codec::encode(&request, codec::WireFormat::Cbor).unwrap();
+        let json = codec::encode(&request, codec::WireFormat::Json).unwrap();
+        // a byte string costs a couple of bytes of framing, base64 costs a third on top:
+        assert!(cbor.len() < 1024 + 100);
+        assert!(json.len() > 1024 * 4 / 3);
+
+        let decoded: Versioned<SendMessage> = codec::decode(&cbor, codec::WireFormat::Cbor).unwrap();
+        assert_eq!(request, decoded);
+
+        let response = MessagesResponse {
+            messages: vec![ConvoMessage {
+                global_index: 1,
+                unix_timestamp: 2,
+                encrypted: Some(vec![1; 300]),
+                invite: Some(invite()),
+            }],
+        };
+        let cbor = codec::encode(&response, codec::WireFormat::Cbor).unwrap();
+        let compressed = codec::compress(&cbor).unwrap();
+        let decoded: MessagesResponse =
+            codec::decode(&codec::decompress(&compressed).unwrap(), codec::WireFormat::Cbor)
+                .unwrap();
+        assert_eq!(response, decoded);
+
+        let request = Versioned::new(Sync {
+            sender_id: "bob".to_string(),
+            groups: HashMap::from([(vec![9; 16], 3)]),
+        });
+        let cbor = codec::encode(&request, codec::WireFormat::Cbor).unwrap();
+        let decoded: Versioned<Sync> = codec::decode(&cbor, codec::WireFormat::Cbor).unwrap();
+        assert_eq!(request, decoded);
+    }
+
+    #[test]
+    fn binary_fields_are_base64_in_json() {
+        let json = serde_json::to_value(Versioned::new(SendMessage {
+            group_id: vec![0xfb, 0xff],
+            message: vec![1, 2, 3],
+            sender_id: "alice".to_string(),
+            global_index: 1,
+        }))
+        .unwrap();
+
+        assert_eq!(json["version"], PROTOCOL_VERSION);
+        assert_eq!(json["group_id"], "-_8=");
+        assert_eq!(json["message"], "AQID");
+    }
+
+    #[test]
+    fn push_events_are_tagged() {
+        let json = serde_json::to_value(PushEvent::Presence(Presence {
+            user_id: "bob".to_string(),
+            status: PresenceStatus::Away,
+            last_seen: 10,
+        }))
+        .unwrap();
+
+        assert_eq!(json["type"], "presence");
+        assert_eq!(json["status"], "away");
+    }
+
+    #[test]
+    fn accepts_legacy_bodies() {
+        // no version field and binary fields as number arrays, as sent by older clients:
+        let request: Versioned<CreateGroup> = serde_json::from_str(
+            r#"{ "group_id": [1, 2, 3], "group_name": "group", "sender_id": "alice" }"#,
+        )
+        .unwrap();
+
+        assert_eq!(request.version, 0);
+        assert_eq!(request.group_id, vec![1, 2, 3]);
+    }
+}
@@ -0,0 +1,86 @@
+// src/types.rs
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::encoding;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConvoInvite {
+    pub group_name: String,
+    #[serde(with = "encoding::bytes")]
+    pub welcome_message: Vec<u8>,
+    #[serde(default, with = "encoding::option_bytes")]
+    pub ratchet_tree: Option<Vec<u8>>,
+    pub global_index: u64,
+    #[serde(default, with = "encoding::option_bytes")]
+    pub fanned: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConvoMessage {
+    pub global_index: u64,
+    pub unix_timestamp: u64,
+    #[serde(default, with = "encoding::option_bytes")]
+    pub encrypted: Option<Vec<u8>>,
+    #[serde(default)]
+    pub invite: Option<ConvoInvite>,
+}
+
+// a user as returned by the server's user listing:
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserInfo {
+    pub user_id: String,
+    #[serde(with = "encoding::bytes")]
+    pub serialized_key_package: Vec<u8>,
+    pub last_active: u64,
+}
+
+// a user's public directory entry, never includes key packages:
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    pub user_id: String,
+    pub handle: String, // unique, lowercase
+    pub display_name: String,
+    // free-form extras (bio, avatar url, ...)
+    #[serde(default)]
+    pub fields: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceStatus {
+    Online,
+    Away,
+    Offline,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Presence {
+    pub user_id: String,
+    pub status: PresenceStatus,
+    pub last_seen: u64, // unix seconds of the user's last request
+}
+
+// pushed to clients over the `/events` stream, one json object per server-sent event:
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PushEvent {
+    // a user's presence changed
+    Presence(Presence),
+    // the account is gone, any group still containing it has it in `GroupMessages::pending_removals`
+    AccountDeleted { user_id: String },
+    // an operator deleted the group, its messages are gone and no more can be sent to it
+    GroupDeleted {
+        #[serde(with = "encoding::bytes")]
+        group_id: Vec<u8>,
+    },
+    // a message was stored in the group, only streamed to its members
+    GroupUpdated {
+        #[serde(with = "encoding::bytes")]
+        group_id: Vec<u8>,
+        global_index: u64,
+    },
+    // something (e.g. an invite) is waiting in the user's mailbox, only streamed to them
+    MailboxUpdated { user_id: String },
+}
@@ -0,0 +1,126 @@
+// src/admin.rs
+
+// the operator routes, mounted under /admin only when an admin token is configured.
+// every request needs `Authorization: Bearer <admin token>`, anything else is a 401.
+
+use rocket::http::Status;
+use rocket::outcome::Outcome;
+use rocket::request::{self, FromRequest};
+use rocket::{get, post, routes};
+use rocket::{Request, Route, State};
+
+use skychat_protocol::{
+    AdminDeleteGroup, AdminGroupsResponse, AdminRemoveUser, AdminUsersResponse, Compact,
+    CompactionReport, QueueDepths,
+};
+
+use crate::web::{ApiResult, Body, ServerState, Wire};
+
+pub fn admin_routes() -> Vec<Route> {
+    routes![
+        list_users,
+        list_groups,
+        queue_depths,
+        remove_user,
+        delete_group,
+        compact
+    ]
+}
+
+// request guard: the request carries the configured admin token
+pub struct Admin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let expected = req
+            .rocket()
+            .state::<ServerState>()
+            .and_then(|state| state.admin_token.as_deref());
+        let given = req
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "));
+
+        match (expected, given) {
+            (Some(expected), Some(given)) if tokens_match(expected, given) => {
+                Outcome::Success(Admin)
+            }
+            _ => Outcome::Error((Status::Unauthorized, ())),
+        }
+    }
+}
+
+// compares every byte, so how long it takes says nothing about how much of the token was right
+fn tokens_match(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+// GET /admin/users
+// every account with its presence, key package stock, mailbox depth and group count
+#[get("/users")]
+pub async fn list_users(_admin: Admin, state: &State<ServerState>) -> Wire<AdminUsersResponse> {
+    let users = state.convo_server.admin_list_users();
+    Wire(AdminUsersResponse { users })
+}
+
+// GET /admin/groups
+// every group with its size, message count, storage use and last activity
+#[get("/groups")]
+pub async fn list_groups(_admin: Admin, state: &State<ServerState>) -> Wire<AdminGroupsResponse> {
+    let groups = state.convo_server.admin_list_groups();
+    Wire(AdminGroupsResponse { groups })
+}
+
+// GET /admin/queues
+#[get("/queues")]
+pub async fn queue_depths(_admin: Admin, state: &State<ServerState>) -> Wire<QueueDepths> {
+    Wire(state.convo_server.admin_queue_depths())
+}
+
+// POST /admin/remove_user (json containing user_id)
+#[post("/remove_user", data = "<data>")]
+pub async fn remove_user(
+    _admin: Admin,
+    data: Body<AdminRemoveUser>,
+    state: &State<ServerState>,
+) -> ApiResult<()> {
+    state
+        .convo_server
+        .admin_remove_user(data.into_inner().user_id)?;
+    Ok(())
+}
+
+// POST /admin/delete_group (json containing group_id)
+#[post("/delete_group", data = "<data>")]
+pub async fn delete_group(
+    _admin: Admin,
+    data: Body<AdminDeleteGroup>,
+    state: &State<ServerState>,
+) -> ApiResult<()> {
+    state
+        .convo_server
+        .admin_delete_group(data.into_inner().group_id)?;
+    Ok(())
+}
+
+// POST /admin/compact (json containing keep_messages, optional)
+#[post("/compact", data = "<data>")]
+pub async fn compact(
+    _admin: Admin,
+    data: Body<Compact>,
+    state: &State<ServerState>,
+) -> Wire<CompactionReport> {
+    Wire(
+        state
+            .convo_server
+            .admin_compact(data.into_inner().keep_messages),
+    )
+}
@@ -0,0 +1,131 @@
+// src/bin/skychat_admin.rs
+
+// talks to a running server's /admin routes and prints the (json) responses.
+// the server is $SKYCHAT_ADMIN_URL (http://127.0.0.1:8888 by default), the token is
+// $SKYCHAT_ADMIN_TOKEN, the same variable the server reads its own from.
+
+use anyhow::{bail, Context, Result};
+use reqwest::header::AUTHORIZATION;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use skychat_protocol::{
+    AdminDeleteGroup, AdminRemoveUser, ApiError, Compact, Versioned, ADMIN_PREFIX,
+};
+
+const USAGE: &str = "usage: skychat_admin <command>
+
+commands:
+    users                      list every account
+    groups                     list every group
+    queues                     show mailbox, group and push queue depths
+    remove-user <user_id>      delete an account
+    delete-group <group_id>    delete a group (its base64 id, as listed by `groups`)
+    compact [keep_messages]    drop what's no longer needed, optionally trimming group histories";
+
+struct AdminClient {
+    http: reqwest::Client,
+    base_url: String,
+    token: String,
+}
+
+impl AdminClient {
+    async fn get(&self, route: &str) -> Result<Value> {
+        let request = self.http.get(self.url(route));
+        self.send(route, request).await
+    }
+
+    async fn post<T: Serialize>(&self, route: &str, body: T) -> Result<Value> {
+        let request = self.http.post(self.url(route)).json(&Versioned::new(body));
+        self.send(route, request).await
+    }
+
+    fn url(&self, route: &str) -> String {
+        format!("{}{}{}", self.base_url, ADMIN_PREFIX, route)
+    }
+
+    async fn send(&self, route: &str, request: reqwest::RequestBuilder) -> Result<Value> {
+        let response = request
+            .header(AUTHORIZATION, format!("Bearer {}", self.token))
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach {}", self.base_url))?;
+
+        let status = response.status();
+        if status == reqwest::StatusCode::UNAUTHORIZED {
+            bail!("{} was refused, check SKYCHAT_ADMIN_TOKEN", route);
+        }
+        if !status.is_success() {
+            match response.json::<ApiError>().await {
+                Ok(error) => bail!("{} failed: {}", route, error.message),
+                // rocket's own 404, the admin routes aren't mounted:
+                Err(_) if status == reqwest::StatusCode::NOT_FOUND => {
+                    bail!(
+                        "{} not found, does the server have an admin token set?",
+                        route
+                    )
+                }
+                Err(_) => bail!("{} failed: {}", route, status),
+            }
+        }
+
+        // unit responses come back empty:
+        let bytes = response.bytes().await?;
+        if bytes.is_empty() {
+            return Ok(Value::Null);
+        }
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let Some(command) = args.first() else {
+        println!("{}", USAGE);
+        return Ok(());
+    };
+    let argument = args.get(1).map(String::as_str);
+
+    let client = AdminClient {
+        http: reqwest::Client::new(),
+        base_url: std::env::var("SKYCHAT_ADMIN_URL")
+            .unwrap_or_else(|_| "http://127.0.0.1:8888".to_string())
+            .trim_end_matches('/')
+            .to_string(),
+        token: std::env::var("SKYCHAT_ADMIN_TOKEN").context("SKYCHAT_ADMIN_TOKEN isn't set")?,
+    };
+
+    let response = match (command.as_str(), argument) {
+        ("users", None) => client.get("/users").await?,
+        ("groups", None) => client.get("/groups").await?,
+        ("queues", None) => client.get("/queues").await?,
+        ("remove-user", Some(user_id)) => {
+            let body = AdminRemoveUser {
+                user_id: user_id.to_string(),
+            };
+            client.post("/remove_user", body).await?
+        }
+        ("delete-group", Some(group_id)) => {
+            // decoded the same way the server decodes it:
+            let body: AdminDeleteGroup = serde_json::from_value(json!({ "group_id": group_id }))
+                .context("Invalid group id")?;
+            client.post("/delete_group", body).await?
+        }
+        ("compact", keep_messages) => {
+            let keep_messages = keep_messages
+                .map(str::parse)
+                .transpose()
+                .context("keep_messages should be a number")?;
+            client.post("/compact", Compact { keep_messages }).await?
+        }
+        _ => bail!("{}", USAGE),
+    };
+
+    if response.is_null() {
+        println!("ok");
+    } else {
+        println!("{}", serde_json::to_string_pretty(&response)?);
+    }
+    Ok(())
+}
@@ -0,0 +1,107 @@
+// src/config.rs
+
+// how a server is set up: a toml file (skychat.toml unless told otherwise), with SKYCHAT_*
+// environment variables on top. nested keys are joined with a double underscore,
+// e.g. SKYCHAT_LIMITS__MAX_GROUP_SIZE=64 or SKYCHAT_STORAGE__BACKEND=file.
+// anything not set anywhere keeps the default below.
+
+use anyhow::{Context, Result};
+use rocket::config::{Shutdown, TlsConfig};
+use rocket::figment::providers::{Env, Format, Serialized, Toml};
+use rocket::figment::Figment;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+
+use crate::limits::Limits;
+use crate::presence::PresenceConfig;
+use crate::storage::StorageConfig;
+
+pub const DEFAULT_CONFIG_PATH: &str = "skychat.toml";
+pub const ENV_PREFIX: &str = "SKYCHAT_";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsPaths {
+    pub certs: PathBuf, // PEM certificate chain
+    pub key: PathBuf,   // PEM private key
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json, // one json object per line, for log collectors
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub address: IpAddr,
+    pub port: u16,
+    pub tls: Option<TlsPaths>, // plain http without it
+    // bearer token for the /admin routes, they're left out entirely when it isn't set
+    pub admin_token: Option<String>,
+    pub storage: StorageConfig,
+    pub limits: Limits,
+    // away_after / offline_after, top level so SKYCHAT_AWAY_AFTER keeps working:
+    #[serde(flatten)]
+    pub presence: PresenceConfig,
+    // how often (in seconds) each cleanup task runs, 0 turns it off:
+    pub presence_sweep_interval: u64,
+    pub key_package_prune_interval: u64,
+    pub snapshot_interval: u64,
+    // seconds in-flight requests get to finish after SIGTERM before connections are closed
+    pub shutdown_grace: u32,
+    // tracing filter (e.g. "info" or "skychat_server=debug"), RUST_LOG takes precedence
+    pub log: String,
+    pub log_format: LogFormat,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 8888,
+            tls: None,
+            admin_token: None,
+            storage: StorageConfig::default(),
+            limits: Limits::default(),
+            presence: PresenceConfig::default(),
+            presence_sweep_interval: 10,
+            key_package_prune_interval: 60 * 60,
+            snapshot_interval: 5 * 60,
+            shutdown_grace: 10,
+            // rocket's own per-request logging is covered by ours:
+            log: "info,rocket=warn".to_string(),
+            log_format: LogFormat::Text,
+        }
+    }
+}
+
+impl ServerConfig {
+    // a missing config file is fine, the defaults (and environment) are used
+    pub fn load(path: &Path) -> Result<Self> {
+        Figment::from(Serialized::defaults(ServerConfig::default()))
+            .merge(Toml::file(path))
+            .merge(Env::prefixed(ENV_PREFIX).split("__"))
+            .extract()
+            .with_context(|| format!("Invalid server config ({})", path.display()))
+    }
+
+    pub fn rocket_config(&self) -> rocket::Config {
+        rocket::Config {
+            address: self.address,
+            port: self.port,
+            tls: self
+                .tls
+                .as_ref()
+                .map(|tls| TlsConfig::from_paths(&tls.certs, &tls.key)),
+            shutdown: Shutdown {
+                grace: self.shutdown_grace,
+                ..Shutdown::default()
+            },
+            ..rocket::Config::default()
+        }
+    }
+}
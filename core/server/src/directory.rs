@@ -0,0 +1,227 @@
+// src/directory.rs
+
+// the public user directory: unique handles, display names and profile fields.
+// key packages live with the user (see `ConvoUser`) and are never handed out from here.
+
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+
+use skychat_protocol::Profile;
+
+use crate::error::ServerError;
+
+/// Most results a single search page may return.
+pub const MAX_SEARCH_LIMIT: usize = 50;
+
+const MIN_HANDLE_LEN: usize = 3;
+const MAX_HANDLE_LEN: usize = 32;
+const MAX_DISPLAY_NAME_LEN: usize = 64;
+
+#[derive(Debug, Default)]
+pub struct Directory {
+    profiles: HashMap<String, Profile>, // user_id -> profile
+    handles: BTreeMap<String, String>,  // handle -> user_id, sorted for search
+}
+
+// handles are lowercased, then must be 3-32 of a-z, 0-9, '_', '.' or '-':
+pub fn normalize_handle(handle: &str) -> Result<String> {
+    let handle = handle.trim().to_lowercase();
+
+    if handle.len() < MIN_HANDLE_LEN || handle.len() > MAX_HANDLE_LEN {
+        return Err(ServerError::BadRequest(format!(
+            "Handles must be {} to {} characters",
+            MIN_HANDLE_LEN, MAX_HANDLE_LEN
+        ))
+        .into());
+    }
+    if !handle
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '.' | '-'))
+    {
+        return Err(ServerError::BadRequest(
+            "Handles may only contain letters, digits, '_', '.' and '-'".to_string(),
+        )
+        .into());
+    }
+    Ok(handle)
+}
+
+impl Directory {
+    pub fn get(&self, user_id: &str) -> Option<&Profile> {
+        self.profiles.get(user_id)
+    }
+
+    pub fn profiles(&self) -> impl Iterator<Item = &Profile> {
+        self.profiles.values()
+    }
+
+    pub fn user_id_for_handle(&self, handle: &str) -> Option<&String> {
+        self.handles.get(&handle.to_lowercase())
+    }
+
+    pub fn register(
+        &mut self,
+        user_id: String,
+        handle: &str,
+        display_name: String,
+        fields: BTreeMap<String, String>,
+    ) -> Result<Profile> {
+        let handle = normalize_handle(handle)?;
+        let display_name = display_name.trim().to_string();
+
+        if display_name.is_empty() || display_name.chars().count() > MAX_DISPLAY_NAME_LEN {
+            return Err(ServerError::BadRequest(format!(
+                "Display names must be 1 to {} characters",
+                MAX_DISPLAY_NAME_LEN
+            ))
+            .into());
+        }
+
+        if self
+            .handles
+            .get(&handle)
+            .is_some_and(|owner| *owner != user_id)
+        {
+            return Err(ServerError::Conflict("Handle is already taken".to_string()).into());
+        }
+
+        // changing handle frees up the old one:
+        if let Some(previous) = self.profiles.get(&user_id) {
+            self.handles.remove(&previous.handle);
+        }
+
+        let profile = Profile {
+            user_id: user_id.clone(),
+            handle: handle.clone(),
+            display_name,
+            fields,
+        };
+        self.handles.insert(handle, user_id.clone());
+        self.profiles.insert(user_id, profile.clone());
+        Ok(profile)
+    }
+
+    pub fn remove(&mut self, user_id: &str) {
+        if let Some(profile) = self.profiles.remove(user_id) {
+            self.handles.remove(&profile.handle);
+        }
+    }
+
+    // one page of matches and the offset of the next page, if any.
+    // an exact handle match comes first, the rest are ordered by handle
+    pub fn search(
+        &self,
+        query: &str,
+        offset: usize,
+        limit: usize,
+    ) -> (Vec<Profile>, Option<usize>) {
+        let query = query.trim().to_lowercase();
+        let limit = limit.clamp(1, MAX_SEARCH_LIMIT);
+
+        let exact = self
+            .handles
+            .get(&query)
+            .and_then(|user_id| self.profiles.get(user_id));
+        let rest = self
+            .handles
+            .iter()
+            .filter(|(handle, _)| **handle != query)
+            .filter_map(|(_, user_id)| self.profiles.get(user_id))
+            .filter(|profile| {
+                query.is_empty()
+                    || profile.handle.contains(&query)
+                    || profile.display_name.to_lowercase().contains(&query)
+            });
+
+        let mut matches = exact.into_iter().chain(rest).skip(offset);
+        let page: Vec<Profile> = matches.by_ref().take(limit).cloned().collect();
+        let next_offset = matches.next().map(|_| offset + page.len());
+
+        (page, next_offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn register(directory: &mut Directory, user_id: &str, handle: &str) -> Result<Profile> {
+        directory.register(
+            user_id.to_string(),
+            handle,
+            user_id.to_string(),
+            BTreeMap::new(),
+        )
+    }
+
+    fn handles(page: &[Profile]) -> Vec<&str> {
+        page.iter().map(|profile| profile.handle.as_str()).collect()
+    }
+
+    #[test]
+    fn handles_are_normalized_and_unique() {
+        assert_eq!(normalize_handle("  Alice.B ").unwrap(), "alice.b");
+        for bad in ["al", &"a".repeat(MAX_HANDLE_LEN + 1), "al ice", "alice!"] {
+            assert!(normalize_handle(bad).is_err(), "{:?}", bad);
+        }
+
+        let mut directory = Directory::default();
+        register(&mut directory, "alice", "Alice").unwrap();
+        let error = register(&mut directory, "mallory", "ALICE").unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ServerError>(),
+            Some(ServerError::Conflict(_))
+        ));
+        // registering again with your own handle is fine:
+        register(&mut directory, "alice", "alice").unwrap();
+        assert_eq!(directory.user_id_for_handle("Alice").unwrap(), "alice");
+
+        // a new handle frees the old one for anybody:
+        register(&mut directory, "alice", "alice2").unwrap();
+        assert!(directory.user_id_for_handle("alice").is_none());
+        register(&mut directory, "bob", "alice").unwrap();
+        assert_eq!(directory.user_id_for_handle("alice").unwrap(), "bob");
+
+        directory.remove("bob");
+        assert!(directory.user_id_for_handle("alice").is_none());
+        assert!(directory.get("bob").is_none());
+    }
+
+    #[test]
+    fn search_pages_through_matches_exact_handle_first() {
+        let mut directory = Directory::default();
+        for (user_id, handle) in [("1", "sam"), ("2", "samantha"), ("3", "asam"), ("4", "bob")] {
+            register(&mut directory, user_id, handle).unwrap();
+        }
+
+        let (page, next) = directory.search("SAM", 0, 2);
+        assert_eq!(handles(&page), ["sam", "asam"]);
+        assert_eq!(next, Some(2));
+        let (page, next) = directory.search("sam", 2, 2);
+        assert_eq!(handles(&page), ["samantha"]);
+        assert_eq!(next, None);
+
+        // display names match too:
+        directory
+            .register("5".to_string(), "zed", "Sammy".to_string(), BTreeMap::new())
+            .unwrap();
+        assert_eq!(
+            handles(&directory.search("sam", 0, 10).0),
+            ["sam", "asam", "samantha", "zed"]
+        );
+
+        for user_id in 0..MAX_SEARCH_LIMIT + 5 {
+            register(
+                &mut directory,
+                &format!("user {}", user_id),
+                &format!("user{:03}", user_id),
+            )
+            .unwrap();
+        }
+        let (page, next) = directory.search("user", 0, 1000);
+        assert_eq!(page.len(), MAX_SEARCH_LIMIT);
+        assert_eq!(next, Some(MAX_SEARCH_LIMIT));
+        // a zero limit still returns something:
+        assert_eq!(directory.search("", 0, 0).0.len(), 1);
+    }
+}
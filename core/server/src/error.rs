@@ -0,0 +1,92 @@
+// src/error.rs
+
+// errors the delivery service hands back to clients.
+// they travel through anyhow like everything else, the web layer downcasts them
+// to pick a status code (anything else is treated as an internal error).
+
+use std::fmt;
+
+use skychat_protocol::{error_codes, ApiError};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerError {
+    /// the group / user doesn't exist
+    NotFound(String),
+    /// the sender isn't allowed to do this (e.g. not a member of the group)
+    Forbidden(String),
+    /// the request clashes with the current state (e.g. group id already taken, stale index)
+    Conflict(String),
+    /// a commit for an epoch that already has one (sent as a 409 too)
+    StaleEpoch(String),
+    /// the request itself doesn't make sense
+    BadRequest(String),
+    /// the message is too big, or storing it would go over a quota
+    TooLarge(String),
+    /// a rate limit was hit, the request may be retried after `retry_after` seconds
+    RateLimited { message: String, retry_after: u64 },
+}
+
+impl ServerError {
+    /// The `ApiError::code` sent to clients.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ServerError::NotFound(_) => error_codes::NOT_FOUND,
+            ServerError::Forbidden(_) => error_codes::FORBIDDEN,
+            ServerError::Conflict(_) => error_codes::CONFLICT,
+            ServerError::StaleEpoch(_) => error_codes::STALE_EPOCH,
+            ServerError::BadRequest(_) => error_codes::BAD_REQUEST,
+            ServerError::TooLarge(_) => error_codes::TOO_LARGE,
+            ServerError::RateLimited { .. } => error_codes::RATE_LIMITED,
+        }
+    }
+
+    /// The HTTP status the error is sent with.
+    pub fn status(&self) -> u16 {
+        match self {
+            ServerError::NotFound(_) => 404,
+            ServerError::Forbidden(_) => 403,
+            ServerError::Conflict(_) | ServerError::StaleEpoch(_) => 409,
+            ServerError::BadRequest(_) => 400,
+            ServerError::TooLarge(_) => 413,
+            ServerError::RateLimited { .. } => 429,
+        }
+    }
+
+    /// Seconds the client should wait before retrying, if that's what it needs to do.
+    pub fn retry_after(&self) -> Option<u64> {
+        match self {
+            ServerError::RateLimited { retry_after, .. } => Some(*retry_after),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerError::NotFound(message)
+            | ServerError::Forbidden(message)
+            | ServerError::Conflict(message)
+            | ServerError::StaleEpoch(message)
+            | ServerError::BadRequest(message)
+            | ServerError::TooLarge(message)
+            | ServerError::RateLimited { message, .. } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ServerError {}
+
+/// What a failed request looks like to the client: its status code and `ApiError` body.
+/// `ServerError`s keep their own status and code, anything else is a 500.
+pub fn api_error(e: &anyhow::Error) -> (u16, ApiError) {
+    let server_error = e.downcast_ref::<ServerError>();
+    let error = ApiError {
+        code: server_error
+            .map_or(error_codes::INTERNAL, ServerError::code)
+            .to_string(),
+        message: e.to_string(),
+        retry_after: server_error.and_then(ServerError::retry_after),
+    };
+    (server_error.map_or(500, ServerError::status), error)
+}
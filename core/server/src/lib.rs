@@ -1 +1,13 @@
+#[cfg(feature = "http")]
+pub mod admin;
+#[cfg(feature = "http")]
+pub mod config;
+pub mod directory;
+pub mod error;
+pub mod limits;
+pub mod metrics;
+pub mod presence;
 pub mod server;
+pub mod storage;
+#[cfg(feature = "http")]
+pub mod web;
@@ -0,0 +1,211 @@
+// src/limits.rs
+
+// rate limits (token buckets per user / per group) and size caps.
+// everything is checked before the server touches any state, so a rejected request changes nothing.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Instant;
+
+use crate::error::ServerError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rate {
+    pub burst: u32,      // requests allowed back to back
+    pub per_minute: u32, // sustained rate once the burst is used up, 0 for no limit
+}
+
+impl Rate {
+    pub const UNLIMITED: Rate = Rate {
+        burst: 0,
+        per_minute: 0,
+    };
+
+    fn is_unlimited(&self) -> bool {
+        self.per_minute == 0
+    }
+
+    fn per_second(&self) -> f64 {
+        self.per_minute as f64 / 60.0
+    }
+}
+
+// anything left out of a config file keeps its default:
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Limits {
+    pub user_sends: Rate,          // messages per sender
+    pub group_sends: Rate,         // messages per group, across all of its members
+    pub user_invites: Rate,        // invites per sender
+    pub key_package_fetches: Rate, // per user whose key packages are being handed out
+    pub key_package_requests: Rate, // key packages fetched per requesting user
+    pub max_message_size: usize,   // bytes, applies to messages, commits and welcomes
+    pub max_group_size: usize,     // members plus pending invites
+    pub max_group_storage: usize,  // bytes of messages kept per group
+    pub max_mailbox_messages: usize,
+    pub max_key_packages: usize, // one-time key packages stored per user
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            user_sends: Rate {
+                burst: 30,
+                per_minute: 120,
+            },
+            group_sends: Rate {
+                burst: 100,
+                per_minute: 600,
+            },
+            user_invites: Rate {
+                burst: 10,
+                per_minute: 30,
+            },
+            key_package_fetches: Rate {
+                burst: 10,
+                per_minute: 30,
+            },
+            key_package_requests: Rate {
+                burst: 20,
+                per_minute: 60,
+            },
+            max_message_size: 256 * 1024,
+            max_group_size: 256,
+            max_group_storage: 64 * 1024 * 1024,
+            max_mailbox_messages: 500,
+            max_key_packages: 100,
+        }
+    }
+}
+
+impl Limits {
+    // no rate limits or quotas at all (tests, trusted deployments)
+    pub fn unlimited() -> Self {
+        Self {
+            user_sends: Rate::UNLIMITED,
+            group_sends: Rate::UNLIMITED,
+            user_invites: Rate::UNLIMITED,
+            key_package_fetches: Rate::UNLIMITED,
+            key_package_requests: Rate::UNLIMITED,
+            max_message_size: usize::MAX,
+            max_group_size: usize::MAX,
+            max_group_storage: usize::MAX,
+            max_mailbox_messages: usize::MAX,
+            max_key_packages: usize::MAX,
+        }
+    }
+
+    pub fn check_message_size(&self, message: &[u8]) -> Result<()> {
+        if message.len() > self.max_message_size {
+            return Err(ServerError::TooLarge(format!(
+                "Message is larger than {} bytes",
+                self.max_message_size
+            ))
+            .into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Send,
+    GroupSend,
+    Invite,
+    KeyPackageFetch,
+    KeyPackageRequest,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    // keyed by user_id or group_id bytes, depending on the action:
+    buckets: HashMap<(Action, Vec<u8>), Bucket>,
+}
+
+impl RateLimiter {
+    // takes one token from each of the given buckets (a bucket listed n times gives n), or
+    // none of them if any runs short. the error says how long until every bucket has enough again,
+    // asking a bucket for more than its burst is refused outright since it never will
+    pub fn take(&mut self, requests: &[(Action, &[u8], Rate)]) -> Result<()> {
+        let now = Instant::now();
+        let mut wanted: HashMap<(Action, &[u8]), (f64, Rate)> = HashMap::new();
+        for (action, key, rate) in requests {
+            if rate.is_unlimited() {
+                continue;
+            }
+            wanted.entry((*action, *key)).or_insert((0.0, *rate)).0 += 1.0;
+        }
+
+        let mut retry_after = 0.0_f64;
+        for ((action, key), (count, rate)) in &wanted {
+            if *count > rate.burst.max(1) as f64 {
+                return Err(ServerError::BadRequest(format!(
+                    "Too many at once, at most {} allowed",
+                    rate.burst.max(1)
+                ))
+                .into());
+            }
+            let tokens = self.refilled(*action, key, *rate, now);
+            if tokens < *count {
+                retry_after = retry_after.max((count - tokens) / rate.per_second());
+            }
+        }
+
+        if retry_after > 0.0 {
+            return Err(ServerError::RateLimited {
+                message: "Too many requests".to_string(),
+                retry_after: retry_after.ceil() as u64,
+            }
+            .into());
+        }
+
+        for ((action, key), (count, rate)) in wanted {
+            let tokens = self.refilled(action, key, rate, now);
+            self.buckets.insert(
+                (action, key.to_vec()),
+                Bucket {
+                    tokens: tokens - count,
+                    updated: now,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    fn refilled(&self, action: Action, key: &[u8], rate: Rate, now: Instant) -> f64 {
+        let burst = rate.burst.max(1) as f64;
+        match self.buckets.get(&(action, key.to_vec())) {
+            Some(bucket) => {
+                let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+                (bucket.tokens + elapsed * rate.per_second()).min(burst)
+            }
+            None => burst,
+        }
+    }
+
+    // forgets buckets that have been idle long enough to be full again
+    pub fn prune(&mut self, limits: &Limits) {
+        let now = Instant::now();
+        self.buckets.retain(|(action, _), bucket| {
+            let rate = match action {
+                Action::Send => limits.user_sends,
+                Action::GroupSend => limits.group_sends,
+                Action::Invite => limits.user_invites,
+                Action::KeyPackageFetch => limits.key_package_fetches,
+                Action::KeyPackageRequest => limits.key_package_requests,
+            };
+            if rate.is_unlimited() {
+                return false;
+            }
+            let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens + elapsed * rate.per_second() < rate.burst.max(1) as f64
+        });
+    }
+}
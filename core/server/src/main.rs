@@ -0,0 +1,125 @@
+// src/main.rs
+
+// skychat_server [config file]
+// the config file defaults to skychat.toml (or $SKYCHAT_CONFIG), see config.rs for what goes in it.
+// on SIGTERM / ctrl-c in-flight requests get `shutdown_grace` seconds to finish, then the
+// state is saved one last time before exiting.
+
+use anyhow::{Context, Result};
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{error, info};
+use tracing_subscriber::EnvFilter;
+
+use skychat_server::config::{LogFormat, ServerConfig, DEFAULT_CONFIG_PATH};
+use skychat_server::server::ConvoServer;
+use skychat_server::storage::StorageConfig;
+use skychat_server::web::{self, ServerState};
+
+#[rocket::main]
+async fn main() -> Result<()> {
+    let config_path = std::env::args()
+        .nth(1)
+        .or_else(|| std::env::var("SKYCHAT_CONFIG").ok())
+        .unwrap_or_else(|| DEFAULT_CONFIG_PATH.to_string());
+    let config = ServerConfig::load(&PathBuf::from(config_path))?;
+    init_tracing(&config);
+
+    let convo_server =
+        ConvoServer::with_presence_config(config.presence).with_limits(config.limits.clone());
+    if let Some(snapshot) = config.storage.load()? {
+        convo_server.restore(snapshot)?;
+        info!("restored state from storage");
+    }
+
+    let state = ServerState::new(convo_server).with_admin_token(config.admin_token.clone());
+    let server = Arc::clone(&state.convo_server);
+
+    // marks quiet users away / offline (they're never removed):
+    every(
+        config.presence_sweep_interval,
+        Arc::clone(&server),
+        |server| async move {
+            server.cleanup_inactive_users().ok();
+        },
+    );
+
+    // expired key packages are dropped as they're handed out, this also clears them for idle users
+    // (and forgets rate limit state for anyone who's been quiet):
+    every(
+        config.key_package_prune_interval,
+        Arc::clone(&server),
+        |server| async move {
+            server.prune_expired_key_packages();
+            server.prune_rate_limits();
+        },
+    );
+
+    let storage = config.storage.clone();
+    every(
+        config.snapshot_interval,
+        Arc::clone(&server),
+        move |server| {
+            let storage = storage.clone();
+            async move {
+                if let Err(e) = save(&server, &storage).await {
+                    error!("failed to save state: {:#}", e);
+                }
+            }
+        },
+    );
+
+    info!(address = %config.address, port = config.port, "starting server");
+    // returns once a shutdown signal came in and the grace period is over:
+    web::rocket(config.rocket_config(), state)
+        .launch()
+        .await
+        .context("Server error")?;
+
+    save(&server, &config.storage).await?;
+    info!("server stopped");
+    Ok(())
+}
+
+// rocket's own logging goes through here too (it can't install its logger once this one is set)
+fn init_tracing(config: &ServerConfig) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&config.log));
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+    match config.log_format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+}
+
+// runs `task` every `seconds` (the first run is one interval in), 0 turns it off
+fn every<F, Fut>(seconds: u64, server: Arc<ConvoServer>, task: F)
+where
+    F: Fn(Arc<ConvoServer>) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    if seconds == 0 {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut interval = interval(Duration::from_secs(seconds));
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            task(Arc::clone(&server)).await;
+        }
+    });
+}
+
+// the snapshot is taken under the server's locks, the (slow) write happens off the runtime
+async fn save(server: &ConvoServer, storage: &StorageConfig) -> Result<()> {
+    if *storage == StorageConfig::Memory {
+        return Ok(());
+    }
+    let snapshot = server.snapshot();
+    let storage = storage.clone();
+    tokio::task::spawn_blocking(move || storage.save(&snapshot)).await?
+}
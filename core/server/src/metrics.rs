@@ -0,0 +1,186 @@
+// src/metrics.rs
+
+// counters behind GET /metrics, rendered in prometheus' text format. request counts and
+// latencies come from the web layer, message counts and conflicts from `ConvoServer` as they
+// happen. gauges (group counts, queue depths) aren't kept here, they're read off the server
+// whenever it's scraped.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+/// Upper bounds (seconds) of the request latency histogram buckets.
+pub const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()], // not cumulative, summed up when rendered
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|&bound| seconds <= bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+/// A value read off the server at scrape time.
+pub struct Gauge {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub value: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    requests: Mutex<BTreeMap<(String, u16), u64>>, // (route, status) -> count
+    latencies: Mutex<BTreeMap<String, Histogram>>, // per route
+    messages: AtomicU64,
+    message_bytes: AtomicU64,
+    invites: AtomicU64,
+    ordering_conflicts: AtomicU64,
+}
+
+impl Metrics {
+    // `route` is the handler's name, so the label can't grow with user input
+    pub fn record_request(&self, route: &str, status: u16, elapsed: Duration) {
+        let mut requests = self.requests.lock().unwrap_or_else(PoisonError::into_inner);
+        *requests.entry((route.to_string(), status)).or_default() += 1;
+        drop(requests);
+
+        let mut latencies = self
+            .latencies
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        latencies
+            .entry(route.to_string())
+            .or_default()
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn record_message(&self, bytes: usize) {
+        self.messages.fetch_add(1, Ordering::Relaxed);
+        self.message_bytes
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_invite(&self) {
+        self.invites.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // a message was sent with a stale global index, i.e. someone else posted first
+    pub fn record_ordering_conflict(&self) {
+        self.ordering_conflicts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn render(&self, gauges: &[Gauge]) -> String {
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "skychat_requests_total",
+            "counter",
+            "HTTP requests handled, by route and status",
+        );
+        for ((route, status), count) in self
+            .requests
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+        {
+            let _ = writeln!(
+                out,
+                "skychat_requests_total{{route=\"{}\",status=\"{}\"}} {}",
+                route, status, count
+            );
+        }
+
+        header(
+            &mut out,
+            "skychat_request_duration_seconds",
+            "histogram",
+            "HTTP request latency, by route",
+        );
+        for (route, histogram) in self
+            .latencies
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+        {
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "skychat_request_duration_seconds_bucket{{route=\"{}\",le=\"{}\"}} {}",
+                    route, bound, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "skychat_request_duration_seconds_bucket{{route=\"{}\",le=\"+Inf\"}} {}",
+                route, histogram.count
+            );
+            let _ = writeln!(
+                out,
+                "skychat_request_duration_seconds_sum{{route=\"{}\"}} {}",
+                route, histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "skychat_request_duration_seconds_count{{route=\"{}\"}} {}",
+                route, histogram.count
+            );
+        }
+
+        counter(
+            &mut out,
+            "skychat_messages_total",
+            "Group messages stored",
+            &self.messages,
+        );
+        counter(
+            &mut out,
+            "skychat_message_bytes_total",
+            "Bytes of group messages stored",
+            &self.message_bytes,
+        );
+        counter(
+            &mut out,
+            "skychat_invites_total",
+            "Invites delivered",
+            &self.invites,
+        );
+        counter(
+            &mut out,
+            "skychat_ordering_conflicts_total",
+            "Messages refused for a stale global index",
+            &self.ordering_conflicts,
+        );
+
+        for gauge in gauges {
+            header(&mut out, gauge.name, "gauge", gauge.help);
+            let _ = writeln!(out, "{} {}", gauge.name, gauge.value);
+        }
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: &AtomicU64) {
+    header(out, name, "counter", help);
+    let _ = writeln!(out, "{} {}", name, value.load(Ordering::Relaxed));
+}
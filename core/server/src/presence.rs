@@ -0,0 +1,154 @@
+// src/presence.rs
+
+// who's around, kept apart from the user directory so that going quiet never costs
+// anyone their account or key packages. statuses only ever change through `touch`/`set`
+// (a request from the user) or `sweep` (time passing).
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use skychat_protocol::{Presence, PresenceStatus};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PresenceConfig {
+    /// seconds without a request before an online user shows as away
+    pub away_after: u64,
+    /// seconds without a request before a user shows as offline
+    pub offline_after: u64,
+}
+
+impl Default for PresenceConfig {
+    fn default() -> Self {
+        Self {
+            away_after: 60,
+            offline_after: 5 * 60,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PresenceTracker {
+    pub config: PresenceConfig,
+    entries: HashMap<String, Presence>,
+}
+
+impl PresenceTracker {
+    pub fn new(config: PresenceConfig) -> Self {
+        Self {
+            config,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, user_id: &str) -> Option<&Presence> {
+        self.entries.get(user_id)
+    }
+
+    pub fn remove(&mut self, user_id: &str) {
+        self.entries.remove(user_id);
+    }
+
+    // the user just made a request, returns their presence if the status changed
+    pub fn touch(&mut self, user_id: &str, now: u64) -> Option<Presence> {
+        self.set(user_id, PresenceStatus::Online, now)
+    }
+
+    pub fn set(&mut self, user_id: &str, status: PresenceStatus, now: u64) -> Option<Presence> {
+        let presence = self
+            .entries
+            .entry(user_id.to_string())
+            .or_insert_with(|| Presence {
+                user_id: user_id.to_string(),
+                status: PresenceStatus::Offline,
+                last_seen: now,
+            });
+
+        let changed = presence.status != status;
+        presence.status = status;
+        presence.last_seen = now;
+
+        changed.then(|| presence.clone())
+    }
+
+    // moves quiet users to away / offline, returns everyone whose status changed
+    pub fn sweep(&mut self, now: u64) -> Vec<Presence> {
+        let config = self.config;
+        let mut changed = Vec::new();
+
+        for presence in self.entries.values_mut() {
+            let idle = now.saturating_sub(presence.last_seen);
+            let status = if idle >= config.offline_after {
+                PresenceStatus::Offline
+            } else if idle >= config.away_after && presence.status == PresenceStatus::Online {
+                PresenceStatus::Away
+            } else {
+                presence.status
+            };
+
+            if status != presence.status {
+                presence.status = status;
+                changed.push(presence.clone());
+            }
+        }
+
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statuses(changed: Vec<Presence>) -> Vec<(String, PresenceStatus)> {
+        let mut statuses: Vec<_> = changed
+            .into_iter()
+            .map(|presence| (presence.user_id, presence.status))
+            .collect();
+        statuses.sort_by(|a, b| a.0.cmp(&b.0));
+        statuses
+    }
+
+    #[test]
+    fn quiet_users_go_away_then_offline() {
+        let mut tracker = PresenceTracker::new(PresenceConfig {
+            away_after: 60,
+            offline_after: 300,
+        });
+        let status = |user_id: &str, status| (user_id.to_string(), status);
+
+        let online = tracker.touch("alice", 1000).unwrap();
+        assert_eq!(online.status, PresenceStatus::Online);
+        // already online, nothing to tell anyone:
+        assert!(tracker.touch("alice", 1010).is_none());
+
+        assert!(tracker.sweep(1069).is_empty());
+        assert_eq!(
+            statuses(tracker.sweep(1070)),
+            [status("alice", PresenceStatus::Away)]
+        );
+        assert!(tracker.sweep(1100).is_empty());
+        assert_eq!(
+            statuses(tracker.sweep(1310)),
+            [status("alice", PresenceStatus::Offline)]
+        );
+        assert_eq!(tracker.get("alice").unwrap().last_seen, 1010);
+
+        // away by choice stays away until it's offline:
+        tracker.set("bob", PresenceStatus::Away, 2000).unwrap();
+        tracker.touch("alice", 2000).unwrap();
+        assert_eq!(
+            statuses(tracker.sweep(2100)),
+            [status("alice", PresenceStatus::Away)]
+        );
+        assert_eq!(
+            statuses(tracker.sweep(2300)),
+            [
+                status("alice", PresenceStatus::Offline),
+                status("bob", PresenceStatus::Offline),
+            ]
+        );
+
+        tracker.remove("bob");
+        assert!(tracker.get("bob").is_none());
+    }
+}
@@ -1,19 +1,146 @@
 // src/convo/server.rs
-use anyhow::{Context, Result};
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
-use skychat_core::utils;
+use skychat_core::utils::{self, ShortId};
+use tracing::{debug, instrument};
 
-use skychat_core::manager::{ConvoInvite, ConvoMessage};
+use tokio::sync::broadcast;
+
+use crate::directory::Directory;
+use crate::error::ServerError;
+use crate::limits::{Action, Limits, RateLimiter};
+use crate::metrics::{Gauge, Metrics};
+use crate::presence::{PresenceConfig, PresenceTracker};
+use crate::storage::Snapshot;
+
+use skychat_protocol::{
+    features, AccountExport, Capabilities, AccountProof, OpenEvents, DeleteAccount, ExportAccount, AdminGroup, AdminUser, CompactionReport, ConvoInvite, ConvoMessage, ExportedGroup, ExportedKeyPackage, GroupMessages,
+    KeyPackageStock, Presence, PresenceStatus, Profile, PushEvent, QueueDepths, RejoinRequest, SearchUsersResponse, SyncResponse,
+    UserInfo, ACCOUNT_EXPORT_VERSION, SUPPORTED_VERSIONS,
+};
 
 type GroupId = Vec<u8>;
 
+/// What `/api/capabilities` answers: the wire versions and optional features this server speaks.
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        wire_versions: SUPPORTED_VERSIONS.to_vec(),
+        features: vec![
+            features::VERSIONED_API.to_string(),
+            features::CBOR.to_string(),
+            features::DEFLATE.to_string(),
+            features::SYNC.to_string(),
+            features::KEY_PACKAGE_POOL.to_string(),
+            features::PRESENCE.to_string(),
+            features::PUSH.to_string(),
+            features::DIRECTORY.to_string(),
+            features::ACCOUNTS.to_string(),
+            features::GROUP_EVENTS.to_string(),
+            features::REJOIN.to_string(),
+        ],
+    }
+}
+
+/// How many push events a slow `/events` subscriber may fall behind before it misses some.
+pub const PUSH_CHANNEL_CAPACITY: usize = 256;
+
+/// Owners are told to upload more one-time key packages once they have fewer than this left.
+pub const KEY_PACKAGE_LOW_WATERMARK: usize = 5;
+
+/// How far (seconds) an `AccountProof`'s timestamp may be from the server's clock.
+pub const MAX_ACCOUNT_PROOF_SKEW: u64 = 5 * 60;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConvoUser {
     pub user_id: String,
-    pub serialized_key_package: Vec<u8>,
-    pub last_active: u64,
+    pub key_packages: VecDeque<StoredKeyPackage>, // one-time key packages, oldest first
+    pub last_resort_key_package: Option<StoredKeyPackage>,
+    pub created_at: u64,
+    // the key the account's `AccountProof`s are signed with, unknown for accounts from before
+    // it was kept (see `proof_key`):
+    #[serde(default)]
+    pub signature_key: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredKeyPackage {
+    pub serialized: Vec<u8>,
+    pub expires: u64, // the package's not_after (unix seconds)
+}
+
+impl StoredKeyPackage {
+    // checks the package and reads its expiry, expired packages are refused
+    pub fn new(serialized: Vec<u8>) -> Result<Self> {
+        let expires = utils::key_package_expiry(&serialized)
+            .map_err(|e| ServerError::BadRequest(format!("Invalid key package: {:#}", e)))?;
+
+        if expires <= utils::current_timestamp() {
+            return Err(ServerError::BadRequest("Key package has expired".to_string()).into());
+        }
+        Ok(Self {
+            serialized,
+            expires,
+        })
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires <= now
+    }
+}
+
+impl ConvoUser {
+    // the next key package to give to an inviter, one-time packages are removed as they're handed out
+    pub fn take_key_package(&mut self) -> Option<Vec<u8>> {
+        self.drop_expired_key_packages(utils::current_timestamp());
+        self.key_packages
+            .pop_front()
+            .or_else(|| self.last_resort_key_package.clone())
+            .map(|key_package| key_package.serialized)
+    }
+
+    // returns how many were dropped
+    pub fn drop_expired_key_packages(&mut self, now: u64) -> usize {
+        let before = self.key_packages.len();
+        self.key_packages.retain(|key_package| !key_package.is_expired(now));
+        let mut dropped = before - self.key_packages.len();
+        if self
+            .last_resort_key_package
+            .as_ref()
+            .is_some_and(|key_package| key_package.is_expired(now))
+        {
+            self.last_resort_key_package = None;
+            dropped += 1;
+        }
+        dropped
+    }
+
+    // the key proofs are checked against. older accounts only have it in their key packages
+    pub fn proof_key(&self) -> Option<Vec<u8>> {
+        self.signature_key.clone().or_else(|| {
+            self.last_resort_key_package
+                .iter()
+                .chain(&self.key_packages)
+                .find_map(|key_package| utils::key_package_owner(&key_package.serialized).ok())
+                .map(|(_identity, signature_key)| signature_key)
+        })
+    }
+
+    pub fn key_package_stock(&self) -> KeyPackageStock {
+        let now = utils::current_timestamp();
+        let remaining = self
+            .key_packages
+            .iter()
+            .filter(|key_package| !key_package.is_expired(now))
+            .count();
+
+        KeyPackageStock {
+            remaining: remaining as u64,
+            low: remaining < KEY_PACKAGE_LOW_WATERMARK,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -21,140 +148,895 @@ pub struct ConvoGroup {
     pub group_id: Vec<u8>,
     pub group_name: String,
     pub global_index: u64,
-    pub user_ids: Vec<String>,
+    pub creator_id: String,
+    pub user_ids: Vec<String>,         // current members
+    pub invited_user_ids: Vec<String>, // invited, but haven't accepted yet
+    // deleted accounts that are still in the MLS group until a member commits their removal:
+    #[serde(default)]
+    pub pending_removals: Vec<String>,
+    // members asking to be re-added (see `RequestRejoin`), at most one request each:
+    #[serde(default)]
+    pub rejoin_requests: Vec<RejoinRequest>,
     pub messages: Vec<ConvoMessage>,
+    #[serde(default)]
+    pub stored_bytes: usize, // total size of `messages`, counted against the storage quota
+    // the epoch the next commit has to be made in, unknown for groups from before it was tracked
+    // (the next commit sets it):
+    #[serde(default)]
+    pub epoch: Option<u64>,
 }
 
+impl ConvoGroup {
+    pub fn is_member(&self, user_id: &str) -> bool {
+        self.user_ids.iter().any(|id| id == user_id)
+    }
+
+    pub fn is_invited(&self, user_id: &str) -> bool {
+        self.invited_user_ids.iter().any(|id| id == user_id)
+    }
+
+    pub fn is_pending_removal(&self, user_id: &str) -> bool {
+        self.pending_removals.iter().any(|id| id == user_id)
+    }
+
+    // at most one commit per epoch: two members committing in the same epoch would split the
+    // group in two, whoever comes second has to sync and commit again. returns the epoch the
+    // commit starts, for `epoch` once it's stored
+    fn check_commit(&self, group_id: &[u8], commit: &[u8]) -> Result<u64> {
+        let (commit_group_id, epoch) = utils::peek_message(commit)
+            .map_err(|e| ServerError::BadRequest(format!("Invalid commit: {:#}", e)))?;
+        if commit_group_id != group_id {
+            return Err(ServerError::BadRequest("Commit is for another group".to_string()).into());
+        }
+        match self.epoch {
+            Some(current) if current != epoch => Err(ServerError::StaleEpoch(format!(
+                "Commit is for epoch {}, the group is at {} (need to sync first)",
+                epoch, current
+            ))
+            .into()),
+            _ => Ok(epoch + 1),
+        }
+    }
+
+    fn push_message(&mut self, message: ConvoMessage) {
+        self.stored_bytes += message.encrypted.as_ref().map_or(0, Vec::len);
+        self.messages.push(message);
+    }
+
+    // whether `size` more bytes still fit in the group's storage quota
+    fn check_storage(&self, size: usize, limits: &Limits) -> Result<()> {
+        if self.stored_bytes.saturating_add(size) > limits.max_group_storage {
+            return Err(
+                ServerError::TooLarge("Group storage quota exceeded".to_string()).into(),
+            );
+        }
+        Ok(())
+    }
+}
+
+// a panicking request shouldn't take the rest of the server down with it. state is only
+// changed once every check has passed, so whatever a poisoned lock guards is still consistent
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn check_member(group: &ConvoGroup, sender_id: &str) -> Result<()> {
+    if !group.is_member(sender_id) {
+        return Err(ServerError::Forbidden("Not a member of this group".to_string()).into());
+    }
+    Ok(())
+}
+
+type SharedGroup = Arc<Mutex<ConvoGroup>>;
+
+/// The delivery service. Every method takes `&self`, so one server can be shared between
+/// request handlers as it is.
+///
+/// Each group sits behind its own lock: requests to the same group are applied one at a time
+/// (so its message indices stay in order), requests to different groups run in parallel.
+/// Where more than one lock is needed they're taken in field order, starting from `groups`.
 pub struct ConvoServer {
-    pub users: HashMap<String, ConvoUser>,
-    pub groups: HashMap<Vec<u8>, ConvoGroup>,
-    pub user_specific_messages: HashMap<String, Vec<ConvoMessage>>,
+    // the map is only locked to find, add or drop a group, not while it's being used
+    groups: RwLock<HashMap<GroupId, SharedGroup>>,
+    users: Mutex<HashMap<String, ConvoUser>>,
+    user_specific_messages: Mutex<HashMap<String, Vec<ConvoMessage>>>,
+    directory: Mutex<Directory>,
+    presence: Mutex<PresenceTracker>,
+    rate_limiter: Mutex<RateLimiter>,
+    pub limits: Limits,
+    pub metrics: Metrics,
+    events: broadcast::Sender<PushEvent>,
 }
 
 impl ConvoServer {
     pub fn new() -> Self {
+        Self::with_presence_config(PresenceConfig::default())
+    }
+
+    pub fn with_presence_config(config: PresenceConfig) -> Self {
+        let (events, _) = broadcast::channel(PUSH_CHANNEL_CAPACITY);
         Self {
-            users: HashMap::new(),
-            groups: HashMap::new(),
-            user_specific_messages: HashMap::new(),
+            groups: RwLock::new(HashMap::new()),
+            users: Mutex::new(HashMap::new()),
+            user_specific_messages: Mutex::new(HashMap::new()),
+            directory: Mutex::new(Directory::default()),
+            presence: Mutex::new(PresenceTracker::new(config)),
+            rate_limiter: Mutex::new(RateLimiter::default()),
+            limits: Limits::default(),
+            metrics: Metrics::default(),
+            events,
         }
     }
 
-    pub fn cleanup_inactive_users(&mut self) -> Result<()> {
-        let current_time = utils::current_timestamp();
-        let timeout_threshold = 30; // 30 seconds timeout
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
 
-        // Collect user IDs to remove
-        let inactive_users: Vec<String> = self
+    // forgets idle rate limit state, run every now and then
+    pub fn prune_rate_limits(&self) {
+        lock(&self.rate_limiter).prune(&self.limits);
+    }
+
+    // a receiver for every push event from now on (what /events streams)
+    pub fn subscribe(&self) -> broadcast::Receiver<PushEvent> {
+        self.events.subscribe()
+    }
+
+    // a copy of everything worth keeping across a restart
+    pub fn snapshot(&self) -> Snapshot {
+        let groups = read(&self.groups)
+            .values()
+            .map(|group| lock(group).clone())
+            .collect();
+
+        Snapshot {
+            groups,
+            users: lock(&self.users).values().cloned().collect(),
+            mailboxes: lock(&self.user_specific_messages).clone(),
+            profiles: lock(&self.directory).profiles().cloned().collect(),
+        }
+    }
+
+    // loads a snapshot into a freshly started server, replacing whatever it held
+    pub fn restore(&self, snapshot: Snapshot) -> Result<()> {
+        let mut directory = Directory::default();
+        for profile in snapshot.profiles {
+            directory.register(
+                profile.user_id,
+                &profile.handle,
+                profile.display_name,
+                profile.fields,
+            )?;
+        }
+
+        *write(&self.groups) = snapshot
+            .groups
+            .into_iter()
+            .map(|group| (group.group_id.clone(), Arc::new(Mutex::new(group))))
+            .collect();
+        *lock(&self.users) = snapshot
             .users
-            .iter()
-            .filter(|(_, user)| current_time - user.last_active > timeout_threshold)
-            .map(|(user_id, _)| user_id.clone())
+            .into_iter()
+            .map(|user| (user.user_id.clone(), user))
             .collect();
+        *lock(&self.user_specific_messages) = snapshot.mailboxes;
+        *lock(&self.directory) = directory;
+        Ok(())
+    }
 
-        // Remove from users HashMap
-        for user_id in inactive_users {
-            self.users.remove(&user_id);
+    fn publish_presence(&self, presence: Presence) {
+        // no subscribers is fine, nobody's listening:
+        let _ = self.events.send(PushEvent::Presence(presence));
+    }
+
+    fn publish_group_update(&self, group_id: &GroupId, global_index: u64) {
+        let _ = self.events.send(PushEvent::GroupUpdated {
+            group_id: group_id.clone(),
+            global_index,
+        });
+    }
+
+    // whether a subscriber streaming events for user_id gets to see this one:
+    // group and mailbox updates are only for the group's members / the mailbox's owner
+    pub fn event_visible_to(&self, event: &PushEvent, user_id: &str) -> bool {
+        match event {
+            PushEvent::GroupUpdated { group_id, .. } => self
+                .group(group_id)
+                .map(|group| lock(&group).is_member(user_id))
+                .unwrap_or(false),
+            PushEvent::MailboxUpdated { user_id: owner } => owner == user_id,
+            // presence only goes to people who share a group with the user:
+            PushEvent::Presence(presence) => {
+                presence.user_id == user_id || self.share_a_group(&presence.user_id, user_id)
+            }
+            _ => true,
         }
+    }
+
+    fn share_a_group(&self, user_id: &str, other_id: &str) -> bool {
+        read(&self.groups).values().any(|group| {
+            let group = lock(group);
+            group.is_member(user_id) && group.is_member(other_id)
+        })
+    }
+
+    fn user_exists(&self, user_id: &str) -> bool {
+        lock(&self.users).contains_key(user_id)
+    }
 
+    fn group(&self, group_id: &GroupId) -> Result<SharedGroup> {
+        read(&self.groups)
+            .get(group_id)
+            .cloned()
+            .ok_or_else(|| ServerError::NotFound("Group not found".to_string()).into())
+    }
+
+    // the user just made a request, so they're online (only tracked for known users)
+    fn touch(&self, user_id: &str) {
+        if !self.user_exists(user_id) {
+            return;
+        }
+        let presence = lock(&self.presence).touch(user_id, utils::current_timestamp());
+        if let Some(presence) = presence {
+            self.publish_presence(presence);
+        }
+    }
+
+    // marks users that have gone quiet as away, then offline, per the presence config.
+    // users (and their key packages) are kept, they're just no longer shown as online
+    pub fn cleanup_inactive_users(&self) -> Result<()> {
+        let changed = lock(&self.presence).sweep(utils::current_timestamp());
+        for presence in changed {
+            self.publish_presence(presence);
+        }
         Ok(())
     }
 
+    // reserves a handle for a connected user and sets their public profile
+    #[instrument(skip_all, fields(user = %user_id))]
+    pub fn client_register(
+        &self,
+        user_id: String,
+        handle: String,
+        display_name: String,
+        fields: BTreeMap<String, String>,
+    ) -> Result<Profile> {
+        if !self.user_exists(&user_id) {
+            return Err(ServerError::NotFound("User not found".to_string()).into());
+        }
+
+        let profile =
+            lock(&self.directory).register(user_id.clone(), &handle, display_name, fields)?;
+
+        self.touch(&user_id);
+        Ok(profile)
+    }
+
+    pub fn client_search_users(
+        &self,
+        query: String,
+        offset: u64,
+        limit: u64,
+    ) -> SearchUsersResponse {
+        let (users, next_offset) =
+            lock(&self.directory).search(&query, offset as usize, limit as usize);
+
+        SearchUsersResponse {
+            users,
+            next_offset: next_offset.map(|offset| offset as u64),
+        }
+    }
+
+    pub fn client_get_profiles(&self, user_ids: Vec<String>) -> Vec<Profile> {
+        let directory = lock(&self.directory);
+        user_ids
+            .iter()
+            .filter_map(|user_id| directory.get(user_id).cloned())
+            .collect()
+    }
+
+    pub fn client_set_presence(&self, user_id: String, status: PresenceStatus) -> Result<()> {
+        if !self.user_exists(&user_id) {
+            return Err(ServerError::NotFound("User not found".to_string()).into());
+        }
+        let presence = lock(&self.presence).set(&user_id, status, utils::current_timestamp());
+        if let Some(presence) = presence {
+            self.publish_presence(presence);
+        }
+        Ok(())
+    }
+
+    // a subscription to the push events, see `event_visible_to` for what it carries. only the
+    // account's owner (with a proof) is marked online for opening one
+    pub fn client_open_events(&self, request: &OpenEvents) -> Result<broadcast::Receiver<PushEvent>> {
+        let receiver = self.subscribe();
+        if let Some(proof) = request.proof() {
+            self.check_account_proof(&request.user_id, OpenEvents::ACTION, &proof)?;
+            self.client_set_presence(request.user_id.clone(), PresenceStatus::Online)?;
+        }
+        Ok(receiver)
+    }
+
+    pub fn client_get_presence(&self, user_ids: Vec<String>) -> Vec<Presence> {
+        let presence = lock(&self.presence);
+        user_ids
+            .iter()
+            .filter_map(|user_id| presence.get(user_id).cloned())
+            .collect()
+    }
+
+    #[instrument(skip_all, fields(group = %ShortId(&group_id), sender = %sender_id))]
     pub fn client_create_group(
-        &mut self,
+        &self,
         group_id: Vec<u8>,
         group_name: String,
+        sender_id: String,
     ) -> Result<()> {
+        let mut groups = write(&self.groups);
+
         // Check if group exists
-        if self.groups.contains_key(&group_id) {
-            anyhow::bail!("Group already exists");
+        if groups.contains_key(&group_id) {
+            return Err(ServerError::Conflict("Group already exists".to_string()).into());
         }
 
-        // Create the ConvoGroup
+        // Create the ConvoGroup, the creator is its first member
         let group = ConvoGroup {
             group_id: group_id.clone(),
             group_name: group_name.clone(),
             global_index: 0,
-            user_ids: vec![],
+            creator_id: sender_id.clone(),
+            user_ids: vec![sender_id],
+            invited_user_ids: vec![],
+            pending_removals: vec![],
+            rejoin_requests: vec![],
             messages: Vec::new(),
+            stored_bytes: 0,
+            epoch: Some(0),
         };
 
-        self.groups.insert(group_id, group);
+        groups.insert(group_id, Arc::new(Mutex::new(group)));
         Ok(())
     }
 
-    pub fn client_accept_invite(&mut self, group_id: Vec<u8>, sender_id: String) -> Result<()> {
-        let group = self.groups.get_mut(&group_id).context("Group not found")?;
+    #[instrument(skip_all, fields(group = %ShortId(&group_id), sender = %sender_id))]
+    pub fn client_accept_invite(&self, group_id: Vec<u8>, sender_id: String) -> Result<()> {
+        let group = self.group(&group_id)?;
+        let mut group = lock(&group);
 
+        if !group.is_invited(&sender_id) {
+            return Err(
+                ServerError::Forbidden("No pending invite to this group".to_string()).into(),
+            );
+        }
+
+        group.invited_user_ids.retain(|id| id != &sender_id);
         group.user_ids.push(sender_id.clone());
 
         // Delete the invite from user_specific_messages
-        self.user_specific_messages.remove(&sender_id);
+        lock(&self.user_specific_messages).remove(&sender_id);
+        Ok(())
+    }
+
+    // takes user_id out of the group (sender_id == user_id for leaving),
+    // the remove commit is fanned out to whoever is left
+    #[instrument(skip_all, fields(group = %ShortId(&group_id), sender = %sender_id, removed = %user_id))]
+    pub fn client_remove_user(
+        &self,
+        group_id: Vec<u8>,
+        sender_id: String,
+        user_id: String,
+        fanned: Vec<u8>,
+    ) -> Result<()> {
+        // removals aren't rate limited or held to the storage quota, they only ever shrink a group:
+        self.limits.check_message_size(&fanned)?;
+        let group = self.group(&group_id)?;
+        let mut group = lock(&group);
+        check_member(&group, &sender_id)?;
+
+        if !group.is_member(&user_id)
+            && !group.is_invited(&user_id)
+            && !group.is_pending_removal(&user_id)
+        {
+            return Err(ServerError::NotFound("User is not in this group".to_string()).into());
+        }
+
+        let epoch = group.check_commit(&group_id, &fanned)?;
+
+        group.user_ids.retain(|id| id != &user_id);
+        group.invited_user_ids.retain(|id| id != &user_id);
+        group.pending_removals.retain(|id| id != &user_id);
+        group.rejoin_requests.retain(|request| request.user_id != user_id);
+        group.epoch = Some(epoch);
+
+        let global_index = group.global_index + 1;
+        group.push_message(ConvoMessage {
+            global_index,
+            encrypted: Some(fanned),
+            unix_timestamp: utils::current_timestamp(),
+            invite: None,
+        });
+        group.global_index = global_index;
+        self.publish_group_update(&group_id, global_index);
+        Ok(())
+    }
+
+    // a member who can't follow the group any more asks the others to re-add them,
+    // a newer request (i.e. a newer key package) replaces theirs
+    #[instrument(skip_all, fields(group = %ShortId(&group_id), sender = %sender_id))]
+    pub fn client_request_rejoin(
+        &self,
+        group_id: Vec<u8>,
+        sender_id: String,
+        key_package: Vec<u8>,
+    ) -> Result<()> {
+        let key_package = StoredKeyPackage::new(key_package)?.serialized;
+        let group = self.group(&group_id)?;
+        let mut group = lock(&group);
+        check_member(&group, &sender_id)?;
+
+        group.rejoin_requests.retain(|request| request.user_id != sender_id);
+        group.rejoin_requests.push(RejoinRequest {
+            user_id: sender_id,
+            key_package,
+        });
+        self.publish_group_update(&group_id, group.global_index);
+        Ok(())
+    }
+
+    // answers a rejoin request: the commit replacing the member's leaf goes to the group,
+    // the welcome to their mailbox, and they're an invitee until they accept it
+    #[instrument(skip_all, fields(group = %ShortId(&group_id), sender = %sender_id, readded = %user_id))]
+    pub fn client_readd_user(
+        &self,
+        group_id: Vec<u8>,
+        sender_id: String,
+        user_id: String,
+        welcome_message: Vec<u8>,
+        fanned: Vec<u8>,
+    ) -> Result<()> {
+        self.limits.check_message_size(&welcome_message)?;
+        self.limits.check_message_size(&fanned)?;
+        let group = self.group(&group_id)?;
+        let mut group = lock(&group);
+        check_member(&group, &sender_id)?;
+
+        // whoever answers second finds the epoch moved on:
+        let epoch = group.check_commit(&group_id, &fanned)?;
+        if !group.rejoin_requests.iter().any(|request| request.user_id == user_id) {
+            return Err(ServerError::NotFound("No rejoin request from this user".to_string()).into());
+        }
+        group.check_storage(fanned.len(), &self.limits)?;
+
+        group.rejoin_requests.retain(|request| request.user_id != user_id);
+        group.user_ids.retain(|id| id != &user_id);
+        if !group.is_invited(&user_id) {
+            group.invited_user_ids.push(user_id.clone());
+        }
+        group.epoch = Some(epoch);
+
+        let global_index = group.global_index + 1;
+        group.push_message(ConvoMessage {
+            global_index,
+            encrypted: Some(fanned),
+            unix_timestamp: utils::current_timestamp(),
+            invite: None,
+        });
+        group.global_index = global_index;
+
+        // the welcome picks up right after the commit:
+        lock(&self.user_specific_messages)
+            .entry(user_id.clone())
+            .or_default()
+            .push(ConvoMessage {
+                global_index,
+                encrypted: None,
+                unix_timestamp: utils::current_timestamp(),
+                invite: Some(ConvoInvite {
+                    global_index,
+                    group_name: group.group_name.clone(),
+                    welcome_message,
+                    ratchet_tree: None,
+                    fanned: None,
+                }),
+            });
+
+        self.publish_group_update(&group_id, global_index);
+        let _ = self.events.send(PushEvent::MailboxUpdated { user_id });
+        Ok(())
+    }
+
+    #[instrument(skip_all, fields(user = %user_id))]
+    pub fn client_create_account(
+        &self,
+        user_id: String,
+        serialized_key_package: Option<Vec<u8>>,
+        signature_key: Option<Vec<u8>>,
+    ) -> Result<()> {
+        // the account is tied to the key its owner signs with from now on:
+        let owner = serialized_key_package
+            .as_deref()
+            .map(utils::key_package_owner)
+            .transpose()
+            .map_err(|e| ServerError::BadRequest(format!("Invalid key package: {:#}", e)))?;
+        let signature_key = match (owner, signature_key) {
+            (Some((identity, _)), _) if identity != user_id.as_bytes() => {
+                return Err(ServerError::BadRequest(
+                    "Key package is for another user".to_string(),
+                )
+                .into());
+            }
+            (Some((_, from_key_package)), Some(signature_key))
+                if from_key_package != signature_key =>
+            {
+                return Err(ServerError::BadRequest(
+                    "Key package is signed with another key".to_string(),
+                )
+                .into());
+            }
+            (_, Some(signature_key)) => signature_key,
+            (Some((_, signature_key)), None) => signature_key,
+            (None, None) => {
+                return Err(
+                    ServerError::BadRequest("A signature key is needed".to_string()).into(),
+                );
+            }
+        };
+        let key_packages = serialized_key_package
+            .map(StoredKeyPackage::new)
+            .transpose()?
+            .into_iter()
+            .collect();
+
+        {
+            let mut users = lock(&self.users);
+            if users.contains_key(&user_id) {
+                return Err(ServerError::Conflict("Account already exists".to_string()).into());
+            }
+
+            users.insert(
+                user_id.clone(),
+                ConvoUser {
+                    user_id: user_id.clone(),
+                    key_packages,
+                    last_resort_key_package: None,
+                    created_at: utils::current_timestamp(),
+                    signature_key: Some(signature_key),
+                },
+            );
+        }
+        self.touch(&user_id);
         Ok(())
     }
 
+    // (re)connecting with a key package starts a fresh pool with it. clients that keep a pool
+    // (see upload_key_packages) connect without one and leave theirs as it is
+    #[instrument(skip_all, fields(user = %user_id))]
     pub fn client_connect(
-        &mut self,
+        &self,
         user_id: String,
-        serialized_key_package: Vec<u8>,
+        serialized_key_package: Option<Vec<u8>>,
     ) -> Result<()> {
-        let timestamp = utils::current_timestamp();
-
-        self.users.insert(
-            user_id.clone(),
-            ConvoUser {
-                user_id: user_id.clone(),
-                serialized_key_package,
-                last_active: timestamp,
-            },
-        );
+        let key_package = serialized_key_package
+            .map(StoredKeyPackage::new)
+            .transpose()?;
+
+        let mut users = lock(&self.users);
+        let user = users
+            .get_mut(&user_id)
+            .ok_or_else(|| ServerError::NotFound("Account not found, create it first".to_string()))?;
+        if let Some(key_package) = key_package {
+            user.key_packages = VecDeque::from([key_package]);
+        }
+        drop(users);
+
+        self.touch(&user_id);
+        Ok(())
+    }
+
+    // everything about the account goes now, except its place in group MLS trees:
+    // only a member can commit a removal, so the account is listed in each group's
+    // pending_removals until one of them does (it can't read or post in the meantime)
+    #[instrument(skip_all, fields(user = %user_id))]
+    pub fn client_delete_account(&self, user_id: String, proof: AccountProof) -> Result<()> {
+        self.check_account_proof(&user_id, DeleteAccount::ACTION, &proof)?;
+        self.delete_account(user_id)
+    }
+
+    // only the account's owner gets to act on it: the proof has to be recent and signed with
+    // the account's key
+    fn check_account_proof(&self, user_id: &str, action: &str, proof: &AccountProof) -> Result<()> {
+        if utils::current_timestamp().abs_diff(proof.timestamp) > MAX_ACCOUNT_PROOF_SKEW {
+            return Err(ServerError::Forbidden("Account proof has expired".to_string()).into());
+        }
+
+        let signature_key = lock(&self.users)
+            .get(user_id)
+            .ok_or_else(|| ServerError::NotFound("Account not found".to_string()))?
+            .proof_key()
+            .ok_or_else(|| ServerError::Forbidden("Account has no signature key".to_string()))?;
+        utils::verify_account_proof(&signature_key, action, user_id, proof)
+            .map_err(|_| ServerError::Forbidden("Invalid account proof".to_string()))?;
+        Ok(())
+    }
+
+    fn delete_account(&self, user_id: String) -> Result<()> {
+        // held throughout, so no group is created or dropped halfway through:
+        let mut groups = write(&self.groups);
+
+        if lock(&self.users).remove(&user_id).is_none() {
+            return Err(ServerError::NotFound("Account not found".to_string()).into());
+        }
+        lock(&self.user_specific_messages).remove(&user_id);
+        lock(&self.directory).remove(&user_id);
+        lock(&self.presence).remove(&user_id);
+
+        groups.retain(|_, group| {
+            let mut group = lock(group);
+            group.invited_user_ids.retain(|id| id != &user_id);
+            group.rejoin_requests.retain(|request| request.user_id != user_id);
+            if group.is_member(&user_id) {
+                group.user_ids.retain(|id| id != &user_id);
+                group.pending_removals.push(user_id.clone());
+            }
+            // nobody left to remove them (or to accept an invite and do it), the group is gone:
+            !group.user_ids.is_empty() || !group.invited_user_ids.is_empty()
+        });
+        drop(groups);
+
+        let _ = self.events.send(PushEvent::AccountDeleted { user_id });
         Ok(())
     }
 
-    pub fn client_list_users(&self) -> Vec<ConvoUser> {
-        self.users.values().cloned().collect()
+    pub fn client_export_account(
+        &self,
+        user_id: String,
+        proof: AccountProof,
+    ) -> Result<AccountExport> {
+        self.check_account_proof(&user_id, ExportAccount::ACTION, &proof)?;
+        let groups: Vec<_> = read(&self.groups)
+            .values()
+            .filter_map(|group| {
+                let group = lock(group);
+                (group.is_member(&user_id) || group.is_invited(&user_id)).then(|| ExportedGroup {
+                    group_id: group.group_id.clone(),
+                    group_name: group.group_name.clone(),
+                    created_by_user: group.creator_id == user_id,
+                    invited: group.is_invited(&user_id),
+                    global_index: group.global_index,
+                })
+            })
+            .collect();
+
+        let now = utils::current_timestamp();
+        let export_key_package = |key_package: &StoredKeyPackage, last_resort: bool| {
+            ExportedKeyPackage {
+                serialized: key_package.serialized.clone(),
+                expires: key_package.expires,
+                last_resort,
+            }
+        };
+        let (created_at, key_packages) = {
+            let users = lock(&self.users);
+            let user = users
+                .get(&user_id)
+                .ok_or_else(|| ServerError::NotFound("Account not found".to_string()))?;
+
+            let key_packages = user
+                .key_packages
+                .iter()
+                .filter(|key_package| !key_package.is_expired(now))
+                .map(|key_package| export_key_package(key_package, false))
+                .chain(
+                    user.last_resort_key_package
+                        .iter()
+                        .map(|key_package| export_key_package(key_package, true)),
+                )
+                .collect();
+            (user.created_at, key_packages)
+        };
+
+        Ok(AccountExport {
+            format_version: ACCOUNT_EXPORT_VERSION,
+            exported_at: now,
+            user_id: user_id.clone(),
+            created_at,
+            profile: lock(&self.directory).get(&user_id).cloned(),
+            presence: lock(&self.presence).get(&user_id).cloned(),
+            key_packages,
+            groups,
+            mailbox: lock(&self.user_specific_messages)
+                .get(&user_id)
+                .cloned()
+                .unwrap_or_default(),
+        })
     }
 
-    pub fn client_get_group_index(&self, group_id: Vec<u8>, _sender_id: String) -> Result<u64> {
-        let group = self.groups.get(&group_id).context("Group not found")?;
+    // adds to the user's pool, or with `replace` swaps it out entirely (rotation)
+    #[instrument(skip_all, fields(user = %user_id, count = key_packages.len()))]
+    pub fn client_upload_key_packages(
+        &self,
+        user_id: String,
+        key_packages: Vec<Vec<u8>>,
+        last_resort: Option<Vec<u8>>,
+        replace: bool,
+    ) -> Result<KeyPackageStock> {
+        // check everything before touching the pool:
+        let key_packages = key_packages
+            .into_iter()
+            .map(StoredKeyPackage::new)
+            .collect::<Result<Vec<_>>>()?;
+        let last_resort = last_resort.map(StoredKeyPackage::new).transpose()?;
+
+        let stock = {
+            let mut users = lock(&self.users);
+            let user = users
+                .get_mut(&user_id)
+                .ok_or_else(|| ServerError::NotFound("User not found".to_string()))?;
+
+            let stored = if replace { 0 } else { user.key_packages.len() };
+            if stored + key_packages.len() > self.limits.max_key_packages {
+                return Err(ServerError::TooLarge(format!(
+                    "At most {} key packages can be stored",
+                    self.limits.max_key_packages
+                ))
+                .into());
+            }
+
+            if replace {
+                user.key_packages.clear();
+            }
+            user.key_packages.extend(key_packages);
+            if let Some(last_resort) = last_resort {
+                user.last_resort_key_package = Some(last_resort);
+            }
+            user.key_package_stock()
+        };
+
+        self.touch(&user_id);
+        Ok(stock)
+    }
+
+    // drops every expired key package from the directory
+    pub fn prune_expired_key_packages(&self) {
+        let now = utils::current_timestamp();
+        for user in lock(&self.users).values_mut() {
+            user.drop_expired_key_packages(now);
+        }
+    }
+
+    pub fn client_list_users(&self) -> Vec<UserInfo> {
+        let users = lock(&self.users);
+        let presence = lock(&self.presence);
+        users
+            .values()
+            .map(|user| UserInfo {
+                user_id: user.user_id.clone(),
+                // the last resort package can be shared, fetch one-time ones with get_user_keys:
+                serialized_key_package: user
+                    .last_resort_key_package
+                    .as_ref()
+                    .map(|key_package| key_package.serialized.clone())
+                    .unwrap_or_default(),
+                last_active: presence
+                    .get(&user.user_id)
+                    .map(|presence| presence.last_seen)
+                    .unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    pub fn client_get_group_index(&self, group_id: Vec<u8>, sender_id: String) -> Result<u64> {
+        let group = self.group(&group_id)?;
+        let group = lock(&group);
+        check_member(&group, &sender_id)?;
         Ok(group.global_index)
     }
 
     pub fn client_get_new_messages(
-        &mut self,
+        &self,
         group_id: Option<GroupId>,
         sender_id: String,
         index: u64,
     ) -> Result<Vec<ConvoMessage>> {
-        // Update last_active timestamp
-        if let Some(user) = self.users.get_mut(&sender_id) {
-            user.last_active = utils::current_timestamp();
-        }
+        self.touch(&sender_id);
 
         let mut new_messages = Vec::new();
 
-        // Get group messages if group_id provided
+        // Get group messages if group_id provided, filtered by index
         if let Some(group_id) = group_id {
-            if let Some(group) = self.groups.get(&group_id) {
-                new_messages.extend(group.messages.clone());
-            }
+            let group = self.group(&group_id)?;
+            let group = lock(&group);
+            check_member(&group, &sender_id)?;
+            new_messages.extend(
+                group
+                    .messages
+                    .iter()
+                    .filter(|msg| msg.global_index > index)
+                    .cloned(),
+            );
         }
 
-        // Filter messages by index
-        new_messages.retain(|msg| msg.global_index > index);
-
         // Add user-specific messages
         // user-specific messages shouldn't be filtered by index, so we add them after the filtering:
-        if let Some(specific_messages) = self.user_specific_messages.get(&sender_id) {
-            new_messages.extend(specific_messages.iter().cloned());
-            self.user_specific_messages.remove(&sender_id);
+        if let Some(specific_messages) = lock(&self.user_specific_messages).remove(&sender_id) {
+            new_messages.extend(specific_messages);
         }
 
         Ok(new_messages)
     }
 
+    // everything new for a user in one go: messages after each of the given indices,
+    // per group, plus (and draining) the user's mailbox
+    #[instrument(skip_all, fields(sender = %sender_id, groups = indices.len()))]
+    pub fn client_sync(
+        &self,
+        sender_id: String,
+        indices: HashMap<GroupId, u64>,
+    ) -> Result<SyncResponse> {
+        self.touch(&sender_id);
+
+        // groups we don't know about (or aren't a member of) are just left out of the response:
+        let requested: Vec<_> = {
+            let groups = read(&self.groups);
+            indices
+                .into_iter()
+                .filter_map(|(group_id, index)| Some((groups.get(&group_id)?.clone(), index)))
+                .collect()
+        };
+
+        let mut groups = HashMap::new();
+        for (group, index) in requested {
+            let group = lock(&group);
+            if !group.is_member(&sender_id) {
+                continue;
+            }
+            let messages = group
+                .messages
+                .iter()
+                .filter(|msg| msg.global_index > index)
+                .cloned()
+                .collect();
+
+            groups.insert(
+                group.group_id.clone(),
+                GroupMessages {
+                    messages,
+                    global_index: group.global_index,
+                    pending_removals: group.pending_removals.clone(),
+                    rejoin_requests: group.rejoin_requests.clone(),
+                },
+            );
+        }
+
+        let mailbox = lock(&self.user_specific_messages)
+            .remove(&sender_id)
+            .unwrap_or_default();
+
+        let key_packages_low = lock(&self.users)
+            .get(&sender_id)
+            .map(|user| user.key_package_stock())
+            .filter(|stock| stock.low)
+            .map(|stock| stock.remaining);
+
+        Ok(SyncResponse {
+            groups,
+            mailbox,
+            key_packages_low,
+        })
+    }
+
+    #[instrument(skip_all, fields(group = %ShortId(&group_id), sender = %sender_id, receiver = %receiver_id))]
     pub fn client_invite_user(
-        &mut self,
+        &self,
         group_id: Vec<u8>,
         sender_id: String,
         receiver_id: String,
@@ -162,28 +1044,62 @@ impl ConvoServer {
         ratchet_tree: Vec<u8>,
         fanned: Option<Vec<u8>>,
     ) -> Result<()> {
-        let group = self.groups.get_mut(&group_id).context("Group not found")?;
+        self.limits.check_message_size(&welcome_message)?;
+        self.limits.check_message_size(&ratchet_tree)?;
+        if let Some(fanned) = &fanned {
+            self.limits.check_message_size(fanned)?;
+        }
+        let group = self.group(&group_id)?;
+        let mut group = lock(&group);
+        check_member(&group, &sender_id)?;
+        lock(&self.rate_limiter).take(&[(
+            Action::Invite,
+            sender_id.as_bytes(),
+            self.limits.user_invites,
+        )])?;
+
+        // held until the welcome is in, so the quota can't be raced past:
+        let mut mailboxes = lock(&self.user_specific_messages);
+        let mailbox_size = mailboxes.get(&receiver_id).map_or(0, Vec::len);
+        if mailbox_size >= self.limits.max_mailbox_messages {
+            return Err(ServerError::TooLarge("Receiver's mailbox is full".to_string()).into());
+        }
+
+        if group.is_member(&receiver_id) {
+            return Err(ServerError::Conflict("User is already a member".to_string()).into());
+        }
+        if !group.is_invited(&receiver_id)
+            && group.user_ids.len() + group.invited_user_ids.len() >= self.limits.max_group_size
+        {
+            return Err(ServerError::Conflict("Group is full".to_string()).into());
+        }
+        group.check_storage(fanned.as_ref().map_or(0, Vec::len), &self.limits)?;
+        let epoch = fanned
+            .as_ref()
+            .map(|fanned| group.check_commit(&group_id, fanned))
+            .transpose()?;
+
+        if !group.is_invited(&receiver_id) {
+            group.invited_user_ids.push(receiver_id.clone());
+        }
 
         // Add fanned message if provided
         if let Some(fanned) = fanned {
-            group.messages.push(ConvoMessage {
-                global_index: group.global_index + 1,
+            group.epoch = epoch;
+            let global_index = group.global_index + 1;
+            group.push_message(ConvoMessage {
+                global_index,
                 encrypted: Some(fanned),
                 unix_timestamp: utils::current_timestamp(),
                 invite: None,
             });
-            group.global_index += 1;
+            group.global_index = global_index;
         }
 
-        // Create user specific messages entry if it doesn't exist
-        self.user_specific_messages
+        // Add welcome message (creating the mailbox if it doesn't exist)
+        mailboxes
             .entry(receiver_id.clone())
-            .or_insert_with(Vec::new);
-
-        // Add welcome message
-        self.user_specific_messages
-            .get_mut(&receiver_id)
-            .unwrap()
+            .or_default()
             .push(ConvoMessage {
                 global_index: group.global_index,
                 encrypted: None,
@@ -198,48 +1114,589 @@ impl ConvoServer {
             });
 
         group.global_index += 1;
+        self.metrics.record_invite();
+        self.publish_group_update(&group_id, group.global_index);
+        let _ = self.events.send(PushEvent::MailboxUpdated {
+            user_id: receiver_id,
+        });
         Ok(())
     }
 
+    #[instrument(skip_all, fields(group = %ShortId(&group_id), sender = %sender_id, global_index))]
     pub fn client_send_message(
-        &mut self,
+        &self,
         group_id: Vec<u8>,
+        sender_id: String,
         message: Vec<u8>,
         global_index: u64,
     ) -> Result<()> {
+        self.limits.check_message_size(&message)?;
 
-        // if the group doesn't exist, create it:
-        if !self.groups.contains_key(&group_id) {
-            self.client_create_group(group_id.clone(), "unknown".to_string())?;
-        }
-
-        let group = self.groups.get_mut(&group_id).context("Group not found")?;
+        // the group stays locked from the index check until the message is stored,
+        // so two senders can never both get the same index:
+        let group = self.group(&group_id)?;
+        let mut group = lock(&group);
+        check_member(&group, &sender_id)?;
+        group.check_storage(message.len(), &self.limits)?;
 
         // the proposed message's global_index must be the current group's global_index + 1:
         let correct_new_gi = group.global_index + 1;
         if global_index == correct_new_gi {
-            group.messages.push(ConvoMessage {
+            // last, a message refused for anything else doesn't use up a send:
+            lock(&self.rate_limiter).take(&[
+                (Action::Send, sender_id.as_bytes(), self.limits.user_sends),
+                (Action::GroupSend, &group_id, self.limits.group_sends),
+            ])?;
+            self.metrics.record_message(message.len());
+            group.push_message(ConvoMessage {
                 global_index: correct_new_gi,
-                encrypted: Some(message.clone()),
+                encrypted: Some(message),
                 unix_timestamp: utils::current_timestamp(),
                 invite: None,
             });
             group.global_index = correct_new_gi;
+            self.publish_group_update(&group_id, correct_new_gi);
             Ok(())
         } else if global_index > correct_new_gi {
-            anyhow::bail!("Message is somehow too new!")
+            Err(ServerError::BadRequest("Message is somehow too new!".to_string()).into())
         } else {
-            anyhow::bail!("Message is too old! (need to sync first)")
+            self.metrics.record_ordering_conflict();
+            debug!(expected = correct_new_gi, "stale global index");
+            Err(ServerError::Conflict("Message is too old! (need to sync first)".to_string()).into())
         }
     }
 
-    pub fn client_get_user_keys(&self, user_ids: Vec<String>) -> Result<HashMap<String, Vec<u8>>> {
+    #[instrument(skip_all, fields(users = user_ids.len()))]
+    // hands out one key package for each of user_ids, or none at all if any of them has none
+    // left, so a failed fetch doesn't use up the others' packages
+    pub fn client_get_user_keys(
+        &self,
+        user_ids: Vec<String>,
+        sender_id: String,
+    ) -> Result<HashMap<String, Vec<u8>>> {
+        // limited per owner, so nobody can drain someone else's pool, and per sender, so one
+        // account can't drain everyone's:
+        let fetches: Vec<_> = user_ids
+            .iter()
+            .flat_map(|user_id| {
+                [
+                    (
+                        Action::KeyPackageFetch,
+                        user_id.as_bytes(),
+                        self.limits.key_package_fetches,
+                    ),
+                    (
+                        Action::KeyPackageRequest,
+                        sender_id.as_bytes(),
+                        self.limits.key_package_requests,
+                    ),
+                ]
+            })
+            .collect();
+        lock(&self.rate_limiter).take(&fetches)?;
+
+        let mut users = lock(&self.users);
+        let now = utils::current_timestamp();
+        for user_id in &user_ids {
+            let user = users
+                .get_mut(user_id)
+                .ok_or_else(|| ServerError::NotFound(format!("User {} not found", user_id)))?;
+            user.drop_expired_key_packages(now);
+            if user.key_packages.is_empty() && user.last_resort_key_package.is_none() {
+                return Err(ServerError::NotFound(format!(
+                    "User {} has no key packages",
+                    user_id
+                ))
+                .into());
+            }
+        }
+
         let mut key_packages_map = HashMap::new();
         for user_id in user_ids {
-            if let Some(user) = self.users.get(&user_id) {
-                key_packages_map.insert(user_id, user.serialized_key_package.clone());
+            if let Some(key_package) = users
+                .get_mut(&user_id)
+                .and_then(|user| user.take_key_package())
+            {
+                key_packages_map.insert(user_id, key_package);
             }
         }
         Ok(key_packages_map)
     }
+
+    // what GET /metrics serves: the recorded counters plus the server's current sizes
+    pub fn render_metrics(&self) -> String {
+        let queues = self.admin_queue_depths();
+        let gauges = [
+            Gauge {
+                name: "skychat_groups",
+                help: "Groups on the server",
+                value: read(&self.groups).len() as u64,
+            },
+            Gauge {
+                name: "skychat_users",
+                help: "Accounts on the server",
+                value: lock(&self.users).len() as u64,
+            },
+            Gauge {
+                name: "skychat_mailbox_messages",
+                help: "Messages waiting in mailboxes",
+                value: queues.mailbox_messages,
+            },
+            Gauge {
+                name: "skychat_group_messages",
+                help: "Messages kept across every group",
+                value: queues.group_messages,
+            },
+            Gauge {
+                name: "skychat_group_bytes",
+                help: "Bytes of messages kept across every group",
+                value: queues.group_bytes,
+            },
+            Gauge {
+                name: "skychat_push_subscribers",
+                help: "Open /events streams",
+                value: queues.push_subscribers,
+            },
+            Gauge {
+                name: "skychat_push_backlog",
+                help: "Push events the slowest subscriber hasn't received yet",
+                value: queues.push_backlog,
+            },
+        ];
+        self.metrics.render(&gauges)
+    }
+
+    // everything below is for operators (the /admin routes), nothing checks who's asking
+
+    pub fn admin_list_users(&self) -> Vec<AdminUser> {
+        let mut group_counts: HashMap<String, u64> = HashMap::new();
+        for group in read(&self.groups).values() {
+            for user_id in &lock(group).user_ids {
+                *group_counts.entry(user_id.clone()).or_default() += 1;
+            }
+        }
+
+        let users = lock(&self.users);
+        let mailboxes = lock(&self.user_specific_messages);
+        let directory = lock(&self.directory);
+        let presence = lock(&self.presence);
+        users
+            .values()
+            .map(|user| {
+                let presence = presence.get(&user.user_id);
+                AdminUser {
+                    user_id: user.user_id.clone(),
+                    handle: directory
+                        .get(&user.user_id)
+                        .map(|profile| profile.handle.clone()),
+                    created_at: user.created_at,
+                    status: presence.map_or(PresenceStatus::Offline, |presence| presence.status),
+                    last_seen: presence.map(|presence| presence.last_seen),
+                    key_packages: user.key_packages.len() as u64,
+                    has_last_resort: user.last_resort_key_package.is_some(),
+                    mailbox_depth: mailboxes.get(&user.user_id).map_or(0, Vec::len) as u64,
+                    groups: group_counts.get(&user.user_id).copied().unwrap_or_default(),
+                }
+            })
+            .collect()
+    }
+
+    pub fn admin_list_groups(&self) -> Vec<AdminGroup> {
+        read(&self.groups)
+            .values()
+            .map(|group| {
+                let group = lock(group);
+                AdminGroup {
+                    group_id: group.group_id.clone(),
+                    group_name: group.group_name.clone(),
+                    creator_id: group.creator_id.clone(),
+                    members: group.user_ids.len() as u64,
+                    invited: group.invited_user_ids.len() as u64,
+                    pending_removals: group.pending_removals.len() as u64,
+                    global_index: group.global_index,
+                    messages: group.messages.len() as u64,
+                    stored_bytes: group.stored_bytes as u64,
+                    last_message_at: group.messages.last().map(|message| message.unix_timestamp),
+                }
+            })
+            .collect()
+    }
+
+    pub fn admin_queue_depths(&self) -> QueueDepths {
+        let (group_messages, group_bytes) =
+            read(&self.groups)
+                .values()
+                .fold((0, 0), |(messages, bytes), group| {
+                    let group = lock(group);
+                    (
+                        messages + group.messages.len() as u64,
+                        bytes + group.stored_bytes as u64,
+                    )
+                });
+
+        let mailboxes = lock(&self.user_specific_messages);
+        let waiting = mailboxes.iter().filter(|(_, messages)| !messages.is_empty());
+        QueueDepths {
+            mailboxes: waiting.clone().count() as u64,
+            mailbox_messages: waiting.clone().map(|(_, messages)| messages.len() as u64).sum(),
+            largest_mailbox: waiting
+                .max_by_key(|(_, messages)| messages.len())
+                .map(|(user_id, messages)| (user_id.clone(), messages.len() as u64)),
+            group_messages,
+            group_bytes,
+            push_subscribers: self.events.receiver_count() as u64,
+            push_backlog: self.events.len() as u64,
+        }
+    }
+
+    // same as the user deleting their own account
+    #[instrument(skip_all, fields(user = %user_id))]
+    pub fn admin_remove_user(&self, user_id: String) -> Result<()> {
+        self.delete_account(user_id)
+    }
+
+    #[instrument(skip_all, fields(group = %ShortId(&group_id)))]
+    pub fn admin_delete_group(&self, group_id: Vec<u8>) -> Result<()> {
+        if write(&self.groups).remove(&group_id).is_none() {
+            return Err(ServerError::NotFound("Group not found".to_string()).into());
+        }
+        let _ = self.events.send(PushEvent::GroupDeleted { group_id });
+        Ok(())
+    }
+
+    // drops whatever is no longer needed, and with `keep_messages` set, every group's
+    // history beyond its newest `keep_messages` messages
+    #[instrument(skip_all, fields(keep_messages))]
+    pub fn admin_compact(&self, keep_messages: Option<u64>) -> CompactionReport {
+        let mut report = CompactionReport::default();
+        {
+            let mut groups = write(&self.groups);
+            groups.retain(|_, group| {
+                let group = lock(group);
+                let empty = group.user_ids.is_empty() && group.invited_user_ids.is_empty();
+                if empty {
+                    report.groups_dropped += 1;
+                    report.messages_dropped += group.messages.len() as u64;
+                    report.bytes_freed += group.stored_bytes as u64;
+                }
+                !empty
+            });
+
+            if let Some(keep) = keep_messages {
+                for group in groups.values() {
+                    let mut group = lock(group);
+                    let excess = group.messages.len().saturating_sub(keep as usize);
+                    let freed: usize = group
+                        .messages
+                        .drain(..excess)
+                        .map(|message| message.encrypted.as_ref().map_or(0, Vec::len))
+                        .sum();
+                    group.stored_bytes -= freed;
+                    report.messages_dropped += excess as u64;
+                    report.bytes_freed += freed as u64;
+                }
+            }
+        }
+
+        let now = utils::current_timestamp();
+        for user in lock(&self.users).values_mut() {
+            report.key_packages_dropped += user.drop_expired_key_packages(now) as u64;
+        }
+        lock(&self.user_specific_messages).retain(|_, messages| !messages.is_empty());
+        self.prune_rate_limits();
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::StorageConfig;
+    use std::thread;
+
+    const GROUPS: usize = 8;
+    const MEMBERS_PER_GROUP: usize = 6;
+    const MESSAGES_PER_MEMBER: u64 = 100;
+
+    fn group_id(group: usize) -> GroupId {
+        vec![group as u8; 16]
+    }
+
+    // every member of every group posts at once, each retrying with a fresh index whenever
+    // someone else got there first (what a client does after a conflict and a sync)
+    #[test]
+    fn concurrent_senders_keep_group_indices_in_order() {
+        let server = Arc::new(ConvoServer::new().with_limits(Limits::unlimited()));
+
+        for group in 0..GROUPS {
+            let creator = format!("user_{}_0", group);
+            server
+                .client_create_group(group_id(group), format!("group {}", group), creator.clone())
+                .unwrap();
+
+            for member in 1..MEMBERS_PER_GROUP {
+                let user_id = format!("user_{}_{}", group, member);
+                server
+                    .client_invite_user(
+                        group_id(group),
+                        creator.clone(),
+                        user_id.clone(),
+                        vec![1],
+                        vec![2],
+                        None,
+                    )
+                    .unwrap();
+                server
+                    .client_accept_invite(group_id(group), user_id)
+                    .unwrap();
+            }
+        }
+
+        let clients: Vec<_> = (0..GROUPS)
+            .flat_map(|group| (0..MEMBERS_PER_GROUP).map(move |member| (group, member)))
+            .map(|(group, member)| {
+                let server = Arc::clone(&server);
+                thread::spawn(move || {
+                    let sender_id = format!("user_{}_{}", group, member);
+                    for n in 0..MESSAGES_PER_MEMBER {
+                        loop {
+                            let index = server
+                                .client_get_group_index(group_id(group), sender_id.clone())
+                                .unwrap();
+                            let message = format!("{} {}", sender_id, n).into_bytes();
+                            match server.client_send_message(
+                                group_id(group),
+                                sender_id.clone(),
+                                message,
+                                index + 1,
+                            ) {
+                                Ok(()) => break,
+                                Err(e) => assert!(matches!(
+                                    e.downcast_ref::<ServerError>(),
+                                    Some(ServerError::Conflict(_))
+                                )),
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for client in clients {
+            client.join().unwrap();
+        }
+
+        let invites = (MEMBERS_PER_GROUP - 1) as u64;
+        let sent = MEMBERS_PER_GROUP as u64 * MESSAGES_PER_MEMBER;
+        for group in 0..GROUPS {
+            let creator = format!("user_{}_0", group);
+            let messages = server
+                .client_get_new_messages(Some(group_id(group)), creator.clone(), 0)
+                .unwrap();
+
+            // every message made it in exactly once, numbered without gaps after the invites:
+            assert_eq!(messages.len() as u64, sent);
+            for (n, message) in messages.iter().enumerate() {
+                assert_eq!(message.global_index, invites + 1 + n as u64);
+            }
+            assert_eq!(
+                server.client_get_group_index(group_id(group), creator).unwrap(),
+                invites + sent
+            );
+        }
+    }
+
+    // going quiet only changes presence, the account and its key packages stay
+    #[test]
+    fn inactive_users_keep_their_accounts_and_key_packages() {
+        let server = ConvoServer::with_presence_config(PresenceConfig {
+            away_after: 0,
+            offline_after: 0,
+        });
+        let alice = skychat_core::manager::ConvoManager::init("alice".to_string());
+        server
+            .client_create_account(
+                "alice".to_string(),
+                None,
+                Some(alice.signature_public_key()),
+            )
+            .unwrap();
+        server
+            .client_upload_key_packages(
+                "alice".to_string(),
+                alice.generate_key_packages(2).unwrap(),
+                None,
+                false,
+            )
+            .unwrap();
+        server
+            .client_set_presence("alice".to_string(), PresenceStatus::Online)
+            .unwrap();
+
+        server.cleanup_inactive_users().unwrap();
+
+        let presence = server.client_get_presence(vec!["alice".to_string()]);
+        assert_eq!(presence[0].status, PresenceStatus::Offline);
+        assert!(server.user_exists("alice"));
+        server
+            .client_get_user_keys(vec!["alice".to_string()], "bob".to_string())
+            .unwrap();
+        let stock = server
+            .client_upload_key_packages("alice".to_string(), Vec::new(), None, false)
+            .unwrap();
+        assert_eq!(stock.remaining, 1);
+    }
+
+    #[test]
+    fn panicking_handler_does_not_poison_the_server() {
+        let server = Arc::new(ConvoServer::new());
+        server
+            .client_create_group(group_id(0), "group".to_string(), "alice".to_string())
+            .unwrap();
+
+        let shared = Arc::clone(&server);
+        let result = thread::spawn(move || {
+            let group = shared.group(&group_id(0)).unwrap();
+            let _group = lock(&group);
+            let _users = lock(&shared.users);
+            panic!("handler bug");
+        })
+        .join();
+        assert!(result.is_err());
+
+        server
+            .client_send_message(group_id(0), "alice".to_string(), vec![1], 1)
+            .unwrap();
+        assert!(server.client_list_users().is_empty());
+    }
+
+    #[test]
+    fn file_snapshot_survives_a_restart() {
+        let path = std::env::temp_dir().join(format!("skychat-snapshot-{}.cbor", std::process::id()));
+        let storage = StorageConfig::File { path: path.clone() };
+
+        let server = ConvoServer::new();
+        server
+            .client_create_group(group_id(0), "group".to_string(), "alice".to_string())
+            .unwrap();
+        server
+            .client_send_message(group_id(0), "alice".to_string(), vec![1], 1)
+            .unwrap();
+        storage.save(&server.snapshot()).unwrap();
+
+        let restarted = ConvoServer::new();
+        restarted.restore(storage.load().unwrap().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            restarted
+                .client_get_group_index(group_id(0), "alice".to_string())
+                .unwrap(),
+            1
+        );
+        assert!(restarted
+            .client_get_group_index(group_id(0), "mallory".to_string())
+            .is_err());
+    }
+
+    #[test]
+    fn compaction_trims_histories_and_drops_deleted_groups() {
+        let server = ConvoServer::new().with_limits(Limits::unlimited());
+        for group in 0..2 {
+            server
+                .client_create_group(group_id(group), "group".to_string(), "alice".to_string())
+                .unwrap();
+            for index in 1..=10 {
+                server
+                    .client_send_message(group_id(group), "alice".to_string(), vec![0; 10], index)
+                    .unwrap();
+            }
+        }
+        server.admin_delete_group(group_id(1)).unwrap();
+        assert!(server.admin_delete_group(group_id(1)).is_err());
+
+        let report = server.admin_compact(Some(4));
+        assert_eq!(report.messages_dropped, 6);
+        assert_eq!(report.bytes_freed, 60);
+
+        let groups = server.admin_list_groups();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].messages, 4);
+        assert_eq!(groups[0].stored_bytes, 40);
+        assert_eq!(groups[0].global_index, 10);
+    }
+
+    #[test]
+    fn metrics_count_messages_and_ordering_conflicts() {
+        let server = ConvoServer::new();
+        server
+            .client_create_group(group_id(0), "group".to_string(), "alice".to_string())
+            .unwrap();
+        server
+            .client_send_message(group_id(0), "alice".to_string(), vec![0; 10], 1)
+            .unwrap();
+        assert!(server
+            .client_send_message(group_id(0), "alice".to_string(), vec![0; 10], 1)
+            .is_err());
+
+        let metrics = server.render_metrics();
+        assert!(metrics.contains("skychat_messages_total 1\n"));
+        assert!(metrics.contains("skychat_message_bytes_total 10\n"));
+        assert!(metrics.contains("skychat_ordering_conflicts_total 1\n"));
+        assert!(metrics.contains("skychat_groups 1\n"));
+    }
+
+    #[test]
+    fn group_and_mailbox_updates_only_reach_their_users() {
+        let server = ConvoServer::new();
+        let mut events = server.subscribe();
+        server
+            .client_create_group(group_id(0), "group".to_string(), "alice".to_string())
+            .unwrap();
+        server
+            .client_invite_user(
+                group_id(0),
+                "alice".to_string(),
+                "bob".to_string(),
+                vec![1],
+                vec![2],
+                None,
+            )
+            .unwrap();
+        server
+            .client_send_message(group_id(0), "alice".to_string(), vec![0; 10], 2)
+            .unwrap();
+
+        let mut received = vec![];
+        while let Ok(event) = events.try_recv() {
+            received.push(event);
+        }
+        assert_eq!(
+            received,
+            [
+                PushEvent::GroupUpdated {
+                    group_id: group_id(0),
+                    global_index: 1
+                },
+                PushEvent::MailboxUpdated {
+                    user_id: "bob".to_string()
+                },
+                PushEvent::GroupUpdated {
+                    group_id: group_id(0),
+                    global_index: 2
+                },
+            ]
+        );
+
+        // bob is only invited so far, his mailbox is all he hears about:
+        let visible = |user_id: &str| {
+            received
+                .iter()
+                .filter(|event| server.event_visible_to(event, user_id))
+                .count()
+        };
+        assert_eq!(visible("alice"), 2);
+        assert_eq!(visible("bob"), 1);
+        assert_eq!(visible("mallory"), 0);
+    }
 }
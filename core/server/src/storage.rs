@@ -0,0 +1,68 @@
+// src/storage.rs
+
+// where the server keeps its state between restarts. `memory` keeps nothing, `file` loads a
+// snapshot on startup and writes a new one every now and then (and once more on shutdown).
+// presence and rate limit state aren't kept, they rebuild themselves as users come back.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use skychat_protocol::codec::{self, WireFormat};
+use skychat_protocol::{ConvoMessage, Profile};
+
+use crate::server::{ConvoGroup, ConvoUser};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum StorageConfig {
+    #[default]
+    Memory,
+    File {
+        path: PathBuf,
+    },
+}
+
+/// Everything `ConvoServer` needs to pick up where it left off.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub users: Vec<ConvoUser>,
+    pub groups: Vec<ConvoGroup>,
+    pub mailboxes: HashMap<String, Vec<ConvoMessage>>,
+    pub profiles: Vec<Profile>,
+}
+
+impl StorageConfig {
+    // the last saved snapshot, None if there's nothing to load (yet)
+    pub fn load(&self) -> Result<Option<Snapshot>> {
+        let StorageConfig::File { path } = self else {
+            return Ok(None);
+        };
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let snapshot = codec::decode(&bytes, WireFormat::Cbor)
+            .with_context(|| format!("Failed to decode snapshot {}", path.display()))?;
+        Ok(Some(snapshot))
+    }
+
+    // written to a temporary file first, so a crash mid-write leaves the last snapshot intact
+    pub fn save(&self, snapshot: &Snapshot) -> Result<()> {
+        let StorageConfig::File { path } = self else {
+            return Ok(());
+        };
+
+        let bytes =
+            codec::encode(snapshot, WireFormat::Cbor).context("Failed to encode snapshot")?;
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, bytes)
+            .with_context(|| format!("Failed to write {}", temp_path.display()))?;
+        fs::rename(&temp_path, path)
+            .with_context(|| format!("Failed to replace {}", path.display()))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,644 @@
+// src/web.rs
+
+use std::io::Cursor;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use rocket::data::{self, Data, FromData, Limits};
+use rocket::fairing::{AdHoc, Fairing, Info, Kind};
+use rocket::http::{ContentType, Status};
+use rocket::outcome::Outcome;
+use rocket::response::stream::{Event, EventStream};
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::Json;
+use rocket::serde::{DeserializeOwned, Serialize};
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::error::RecvError;
+use rocket::{get, post, routes};
+use rocket::{Build, Config, Request, Rocket, Route, Shutdown, State};
+use tracing::{debug, error, info};
+
+use skychat_core::utils::BufferConverter;
+
+use skychat_protocol::codec::{self, WireFormat, COMPRESSION_THRESHOLD, DEFLATE_ENCODING};
+use skychat_protocol::{
+    AcceptInvite, AccountExport, ApiError, Capabilities, Connect, CreateAccount, CreateGroup,
+    DeleteAccount, ExportAccount, GetGroupIndex, GetMessages,
+    GetPresence, GetProfiles, GetUserKeys, GroupIndexResponse, InviteUser, KeyPackageStock, MessagesResponse, OpenEvents,
+    PresenceResponse, Profile, ProfilesResponse, PushEvent, ReaddUser, Register, RemoveUser, RequestRejoin, SearchUsers,
+    SearchUsersResponse, SendMessage, SetPresence, Sync, SyncResponse, UploadKeyPackages, UserKeysResponse,
+    UsersResponse, Versioned, ADMIN_PREFIX, API_PREFIX, LEGACY_API_PREFIX, SUPPORTED_VERSIONS,
+};
+
+use crate::admin;
+use crate::error;
+use crate::server::{capabilities, ConvoServer};
+
+pub struct ServerState {
+    pub convo_server: Arc<ConvoServer>,
+    // whether /ready says yes: set once the server is listening, cleared as it shuts down
+    pub ready: Arc<AtomicBool>,
+    // the bearer token the /admin routes want, they aren't mounted without one
+    pub admin_token: Option<String>,
+}
+
+impl ServerState {
+    pub fn new(convo_server: ConvoServer) -> Self {
+        ServerState {
+            convo_server: Arc::new(convo_server),
+            ready: Arc::new(AtomicBool::new(false)),
+            admin_token: None,
+        }
+    }
+
+    pub fn with_admin_token(mut self, admin_token: Option<String>) -> Self {
+        self.admin_token = admin_token;
+        self
+    }
+}
+
+impl Clone for ServerState {
+    fn clone(&self) -> Self {
+        ServerState {
+            convo_server: Arc::clone(&self.convo_server),
+            ready: Arc::clone(&self.ready),
+            admin_token: self.admin_token.clone(),
+        }
+    }
+}
+
+pub fn api_routes() -> Vec<Route> {
+    routes![
+        create_account,
+        delete_account,
+        export_account,
+        connect,
+        upload_key_packages,
+        list_users,
+        invite_user,
+        remove_user,
+        request_rejoin,
+        readd_user,
+        create_group,
+        get_new_messages,
+        get_new_messages_bin,
+        sync,
+        accept_invite,
+        send_message,
+        group_index,
+        get_user_keys,
+        register,
+        search_users,
+        get_profiles,
+        set_presence,
+        get_presence,
+        events,
+    ]
+}
+
+// everything mounted and managed, ready to launch
+pub fn rocket(config: Config, state: ServerState) -> Rocket<Build> {
+    let liftoff_ready = Arc::clone(&state.ready);
+    let shutdown_ready = Arc::clone(&state.ready);
+
+    let admin_routes = if state.admin_token.is_some() {
+        admin::admin_routes()
+    } else {
+        Vec::new()
+    };
+
+    // the same routes are kept under the unversioned /api for older clients:
+    rocket::custom(config)
+        .mount(API_PREFIX, api_routes())
+        .mount(LEGACY_API_PREFIX, api_routes())
+        .mount(LEGACY_API_PREFIX, routes![get_capabilities])
+        .mount(ADMIN_PREFIX, admin_routes)
+        .mount("/", routes![health, ready, metrics])
+        .manage(state)
+        .attach(RequestMetrics)
+        .attach(AdHoc::on_liftoff("Ready", |_| {
+            Box::pin(async move { liftoff_ready.store(true, Ordering::SeqCst) })
+        }))
+        .attach(AdHoc::on_shutdown("Not ready", |_| {
+            Box::pin(async move {
+                shutdown_ready.store(false, Ordering::SeqCst);
+                info!("shutting down, draining in-flight requests");
+            })
+        }))
+}
+
+// times every request, counts it in the server's metrics and logs it.
+// requests are labelled with the handler's name (never the uri, which can carry user ids)
+pub struct RequestMetrics;
+
+struct RequestStart(Instant);
+
+#[rocket::async_trait]
+impl Fairing for RequestMetrics {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request metrics",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+        req.local_cache(|| RequestStart(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let elapsed = req.local_cache(|| RequestStart(Instant::now())).0.elapsed();
+        let route = req
+            .route()
+            .and_then(|route| route.name.as_deref())
+            .unwrap_or("unmatched");
+        let status = res.status().code;
+
+        if let Some(state) = req.rocket().state::<ServerState>() {
+            state.convo_server.metrics.record_request(route, status, elapsed);
+        }
+        info!(
+            method = %req.method(),
+            route,
+            status,
+            latency_ms = elapsed.as_secs_f64() * 1000.0,
+            "request"
+        );
+    }
+}
+
+// // base64 utils:
+type EncodedBase64 = String;
+
+// a request body sent in a `Versioned` envelope, as json or cbor (optionally deflated).
+// bodies from unversioned (legacy) clients are accepted, unknown versions are rejected.
+pub struct Body<T>(pub T);
+
+impl<T> Body<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, T: DeserializeOwned> FromData<'r> for Body<T> {
+    type Error = String;
+
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let format = WireFormat::from_media_type(req.headers().get_one("Content-Type"));
+        let limit = req.limits().get("json").unwrap_or(Limits::JSON);
+
+        let bytes = match data.open(limit).into_bytes().await {
+            Ok(bytes) if bytes.is_complete() => bytes.into_inner(),
+            Ok(_) => {
+                return Outcome::Error((Status::PayloadTooLarge, "body too large".to_string()))
+            }
+            Err(e) => return Outcome::Error((Status::BadRequest, e.to_string())),
+        };
+
+        let bytes = if req.headers().get_one("Content-Encoding") == Some(DEFLATE_ENCODING) {
+            match codec::decompress(&bytes) {
+                Ok(bytes) => bytes,
+                Err(e) => return Outcome::Error((Status::BadRequest, e.to_string())),
+            }
+        } else {
+            bytes
+        };
+
+        let versioned: Versioned<T> = match codec::decode(&bytes, format) {
+            Ok(versioned) => versioned,
+            Err(e) => return Outcome::Error((Status::UnprocessableEntity, format!("{:#}", e))),
+        };
+
+        if versioned.version != 0 && !SUPPORTED_VERSIONS.contains(&versioned.version) {
+            return Outcome::Error((
+                Status::BadRequest,
+                format!("unsupported protocol version {}", versioned.version),
+            ));
+        }
+
+        Outcome::Success(Body(versioned.into_inner()))
+    }
+}
+
+// a response body encoded in whatever format the request's Accept header asked for
+// (json by default), deflated when the client accepts it and the body is large enough.
+pub struct Wire<T>(pub T);
+
+impl<'r, T: Serialize> Responder<'r, 'static> for Wire<T> {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let format = WireFormat::from_media_type(req.headers().get_one("Accept"));
+        let mut body = codec::encode(&self.0, format).map_err(|_| Status::InternalServerError)?;
+
+        let accepts_deflate = req
+            .headers()
+            .get_one("Accept-Encoding")
+            .is_some_and(|encodings| encodings.contains(DEFLATE_ENCODING));
+
+        let mut response = Response::build();
+        if accepts_deflate && body.len() >= COMPRESSION_THRESHOLD {
+            body = codec::compress(&body).map_err(|_| Status::InternalServerError)?;
+            response.raw_header("Content-Encoding", DEFLATE_ENCODING);
+        }
+
+        response
+            .raw_header("Content-Type", format.content_type())
+            .sized_body(body.len(), Cursor::new(body))
+            .ok()
+    }
+}
+
+// a failed request: the status code plus an `ApiError` body (in the requested format).
+// `ServerError`s map to their matching status, anything else is a 500.
+pub struct Failure(pub Status, pub ApiError);
+
+impl From<anyhow::Error> for Failure {
+    fn from(e: anyhow::Error) -> Self {
+        let (status, error) = error::api_error(&e);
+        let status = Status::from_code(status).unwrap_or(Status::InternalServerError);
+        // a client's mistake is routine, anything else is ours:
+        if status == Status::InternalServerError {
+            error!("request failed: {:#}", e);
+        } else {
+            debug!(code = %error.code, "request refused: {:#}", e);
+        }
+        Failure(status, error)
+    }
+}
+
+impl<'r> Responder<'r, 'static> for Failure {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let retry_after = self.1.retry_after;
+        let mut response = Wire(self.1).respond_to(req)?;
+        response.set_status(self.0);
+        if let Some(retry_after) = retry_after {
+            response.set_raw_header("Retry-After", retry_after.to_string());
+        }
+        Ok(response)
+    }
+}
+
+pub type ApiResult<T> = Result<T, Failure>;
+
+// GET /health (mounted at the root)
+// the process is up and answering, nothing more
+#[get("/health")]
+pub async fn health() -> &'static str {
+    "ok"
+}
+
+// GET /ready (mounted at the root)
+// 503 until the server is listening and again once it starts shutting down
+#[get("/ready")]
+pub async fn ready(state: &State<ServerState>) -> Status {
+    if state.ready.load(Ordering::SeqCst) {
+        Status::Ok
+    } else {
+        Status::ServiceUnavailable
+    }
+}
+
+// GET /metrics (mounted at the root)
+// prometheus text format, see metrics.rs
+#[get("/metrics")]
+pub async fn metrics(state: &State<ServerState>) -> (ContentType, String) {
+    let content_type = ContentType::new("text", "plain").with_params(("version", "0.0.4"));
+    (content_type, state.convo_server.render_metrics())
+}
+
+// GET /capabilities (mounted under the unversioned /api only)
+#[get("/capabilities")]
+pub async fn get_capabilities() -> Json<Capabilities> {
+    Json(capabilities())
+}
+
+// POST /create_account (json containing user_id and a first key package)
+// conflict if the user_id is already taken
+#[post("/create_account", data = "<data>")]
+pub async fn create_account(
+    data: Body<CreateAccount>,
+    state: &State<ServerState>,
+) -> ApiResult<()> {
+    let server = &state.convo_server;
+    let data = data.into_inner();
+    server.client_create_account(data.user_id, data.serialized_key_package, data.signature_key)?;
+    Ok(())
+}
+
+// POST /delete_account (json containing user_id and a proof it's the caller's)
+#[post("/delete_account", data = "<data>")]
+pub async fn delete_account(
+    data: Body<DeleteAccount>,
+    state: &State<ServerState>,
+) -> ApiResult<()> {
+    let server = &state.convo_server;
+    let data = data.into_inner();
+    server.client_delete_account(data.user_id, data.proof)?;
+    Ok(())
+}
+
+// POST /export_account (json containing user_id and a proof it's the caller's)
+// returns everything the server holds about the account, see `AccountExport`
+#[post("/export_account", data = "<data>")]
+pub async fn export_account(
+    data: Body<ExportAccount>,
+    state: &State<ServerState>,
+) -> ApiResult<Json<AccountExport>> {
+    let server = &state.convo_server;
+    let data = data.into_inner();
+    let export = server.client_export_account(data.user_id, data.proof)?;
+    Ok(Json(export))
+}
+
+// POST /connect (json containing name and user_id)
+// the account must exist already (see create_account)
+#[post("/connect", data = "<data>")]
+pub async fn connect(data: Body<Connect>, state: &State<ServerState>) -> ApiResult<()> {
+    let server = &state.convo_server;
+    let data = data.into_inner();
+    server.client_connect(data.user_id, data.serialized_key_package)?;
+    Ok(())
+}
+
+// POST /upload_key_packages (json or cbor containing user_id, key_packages and maybe a last_resort)
+// invalid or expired packages are rejected
+// returns how many one-time key packages the user has left
+#[post("/upload_key_packages", data = "<data>")]
+pub async fn upload_key_packages(
+    data: Body<UploadKeyPackages>,
+    state: &State<ServerState>,
+) -> ApiResult<Wire<KeyPackageStock>> {
+    let server = &state.convo_server;
+    let data = data.into_inner();
+    let stock = server.client_upload_key_packages(
+        data.user_id,
+        data.key_packages,
+        data.last_resort,
+        data.replace,
+    )?;
+    Ok(Wire(stock))
+}
+
+// GET /list_users
+#[get("/list_users")]
+pub async fn list_users(state: &State<ServerState>) -> Json<UsersResponse> {
+    let server = &state.convo_server;
+    let users = server.client_list_users();
+    Json(UsersResponse { users })
+}
+
+// POST /register (json containing user_id, handle, display_name and profile fields)
+// returns the stored profile, conflict if the handle belongs to someone else
+#[post("/register", data = "<data>")]
+pub async fn register(
+    data: Body<Register>,
+    state: &State<ServerState>,
+) -> ApiResult<Wire<Profile>> {
+    let server = &state.convo_server;
+    let data = data.into_inner();
+    let profile =
+        server.client_register(data.user_id, data.handle, data.display_name, data.fields)?;
+    Ok(Wire(profile))
+}
+
+// POST /search_users (json containing query, offset and limit)
+// returns a page of profiles (no key packages, fetch those with get_user_keys)
+#[post("/search_users", data = "<data>")]
+pub async fn search_users(
+    data: Body<SearchUsers>,
+    state: &State<ServerState>,
+) -> Wire<SearchUsersResponse> {
+    let server = &state.convo_server;
+    let data = data.into_inner();
+    Wire(server.client_search_users(data.query, data.offset, data.limit))
+}
+
+// POST /get_profiles (json containing list of user_ids)
+#[post("/get_profiles", data = "<data>")]
+pub async fn get_profiles(
+    data: Body<GetProfiles>,
+    state: &State<ServerState>,
+) -> Wire<ProfilesResponse> {
+    let server = &state.convo_server;
+    let users = server.client_get_profiles(data.into_inner().user_ids);
+    Wire(ProfilesResponse { users })
+}
+
+// POST /presence (json containing user_id and status)
+#[post("/presence", data = "<data>")]
+pub async fn set_presence(data: Body<SetPresence>, state: &State<ServerState>) -> ApiResult<()> {
+    let server = &state.convo_server;
+    let data = data.into_inner();
+    server.client_set_presence(data.user_id, data.status)?;
+    Ok(())
+}
+
+// POST /get_presence (json containing list of user_ids)
+// returns the status and last seen time of each user the server knows about
+#[post("/get_presence", data = "<data>")]
+pub async fn get_presence(
+    data: Body<GetPresence>,
+    state: &State<ServerState>,
+) -> Wire<PresenceResponse> {
+    let server = &state.convo_server;
+    let presence = server.client_get_presence(data.into_inner().user_ids);
+    Wire(PresenceResponse { presence })
+}
+
+// GET /events?user_id=<user_id>[&timestamp=<timestamp>&signature=<signature>]
+// server-sent events, one json `PushEvent` per event, for as long as the client stays connected.
+// with an account proof the user counts as online while it's open, see `OpenEvents`
+#[get("/events?<user_id>&<timestamp>&<signature>")]
+pub async fn events(
+    user_id: &str,
+    timestamp: Option<u64>,
+    signature: Option<&str>,
+    state: &State<ServerState>,
+    mut end: Shutdown,
+) -> ApiResult<EventStream![]> {
+    let server = &state.convo_server;
+    let signature = signature
+        .map(BufferConverter::from_base64)
+        .transpose()
+        .map_err(|_| anyhow::Error::from(error::ServerError::BadRequest("Invalid signature".to_string())))?;
+    let mut receiver = server.client_open_events(&OpenEvents {
+        user_id: user_id.to_string(),
+        timestamp,
+        signature,
+    })?;
+    // owned, the stream outlives the request:
+    let server = Arc::clone(server);
+    let user_id = user_id.to_string();
+
+    Ok(EventStream! {
+        loop {
+            let event: PushEvent = select! {
+                event = receiver.recv() => match event {
+                    Ok(event) => event,
+                    // a slow client just misses some, presence can always be re-queried:
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                },
+                _ = &mut end => break,
+            };
+            if !server.event_visible_to(&event, &user_id) {
+                continue;
+            }
+
+            yield Event::json(&event);
+        }
+    })
+}
+
+// POST /send_message (json or cbor containing group_id, message)
+// only members of the group can post to it
+#[post("/send_message", data = "<data>")]
+pub async fn send_message(data: Body<SendMessage>, state: &State<ServerState>) -> ApiResult<()> {
+    let server = &state.convo_server;
+    let data = data.into_inner();
+    server.client_send_message(data.group_id, data.sender_id, data.message, data.global_index)?;
+    Ok(())
+}
+
+// POST /create_group (json containing group_id and group_name)
+// the sender becomes the group's creator and first member
+#[post("/create_group", data = "<data>")]
+pub async fn create_group(data: Body<CreateGroup>, state: &State<ServerState>) -> ApiResult<()> {
+    let server = &state.convo_server;
+    let data = data.into_inner();
+    server.client_create_group(data.group_id, data.group_name, data.sender_id)?;
+    Ok(())
+}
+
+// POST /get_user_keys (json containing list of user_ids, return map of serialized_key_packages)
+// every package returned is used up, so only ask for the users you're about to invite. if any
+// of them has no package nothing is handed out
+#[post("/get_user_keys", data = "<data>")]
+pub async fn get_user_keys(
+    data: Body<GetUserKeys>,
+    state: &State<ServerState>,
+) -> ApiResult<Json<UserKeysResponse>> {
+    let server = &state.convo_server;
+    let data = data.into_inner();
+    let key_packages = server.client_get_user_keys(data.user_ids, data.sender_id)?;
+    Ok(Json(UserKeysResponse { key_packages }))
+}
+
+// POST /get_new_messages (json or cbor containing group_id and index)
+// returns the messages after index, only members can read a group
+#[post("/get_new_messages", data = "<data>")]
+pub async fn get_new_messages(
+    data: Body<GetMessages>,
+    state: &State<ServerState>,
+) -> ApiResult<Wire<MessagesResponse>> {
+    let server = &state.convo_server;
+    let data = data.into_inner();
+    let messages = server.client_get_new_messages(data.group_id, data.sender_id, data.index)?;
+    Ok(Wire(MessagesResponse { messages }))
+}
+
+// POST /sync (json or cbor containing sender_id and a map of group_id -> last seen index)
+// returns the new messages for each of those groups plus the sender's mailbox
+#[post("/sync", data = "<data>")]
+pub async fn sync(data: Body<Sync>, state: &State<ServerState>) -> ApiResult<Wire<SyncResponse>> {
+    let server = &state.convo_server;
+    let data = data.into_inner();
+    let response = server.client_sync(data.sender_id, data.groups)?;
+    Ok(Wire(response))
+}
+
+// same as get_new_messages, but every message is a base64 encoded json string
+// (kept in this shape for older mobile clients, newer ones ask get_new_messages for cbor)
+#[post("/get_new_messages_bin", data = "<data>")]
+pub async fn get_new_messages_bin(
+    data: Body<GetMessages>,
+    state: &State<ServerState>,
+) -> ApiResult<Json<Vec<EncodedBase64>>> {
+    let server = &state.convo_server;
+    let data = data.into_inner();
+    let messages = server.client_get_new_messages(data.group_id, data.sender_id, data.index)?;
+
+    if messages.is_empty() {
+        return Ok(Json(vec![]));
+    }
+
+    // Convert each message to a base64 encoded JSON string
+    let base64_messages = messages
+        .into_iter()
+        .map(|m| BufferConverter::to_base64_json(&m).unwrap_or_default())
+        .collect::<Vec<_>>();
+
+    Ok(Json(base64_messages))
+}
+
+// POST /invite_user (json containing group_id, user_id, and welcome_message)
+// only members can invite, the receiver can then accept_invite
+#[post("/invite_user", data = "<data>")]
+pub async fn invite_user(data: Body<InviteUser>, state: &State<ServerState>) -> ApiResult<()> {
+    let server = &state.convo_server;
+    let data = data.into_inner();
+    server.client_invite_user(
+        data.group_id,
+        data.sender_id,
+        data.receiver_id,
+        data.welcome_message,
+        data.ratchet_tree,
+        data.fanned,
+    )?;
+    Ok(())
+}
+
+// POST /remove_user (json containing group_id, sender_id, user_id and the remove commit)
+#[post("/remove_user", data = "<data>")]
+pub async fn remove_user(data: Body<RemoveUser>, state: &State<ServerState>) -> ApiResult<()> {
+    let server = &state.convo_server;
+    let data = data.into_inner();
+    server.client_remove_user(data.group_id, data.sender_id, data.user_id, data.fanned)?;
+    Ok(())
+}
+
+// POST /request_rejoin (json containing group_id, sender_id and a fresh key package)
+#[post("/request_rejoin", data = "<data>")]
+pub async fn request_rejoin(data: Body<RequestRejoin>, state: &State<ServerState>) -> ApiResult<()> {
+    let server = &state.convo_server;
+    let data = data.into_inner();
+    server.client_request_rejoin(data.group_id, data.sender_id, data.key_package)?;
+    Ok(())
+}
+
+// POST /readd_user (json containing group_id, sender_id, user_id, the welcome and the commit)
+#[post("/readd_user", data = "<data>")]
+pub async fn readd_user(data: Body<ReaddUser>, state: &State<ServerState>) -> ApiResult<()> {
+    let server = &state.convo_server;
+    let data = data.into_inner();
+    server.client_readd_user(
+        data.group_id,
+        data.sender_id,
+        data.user_id,
+        data.welcome_message,
+        data.fanned,
+    )?;
+    Ok(())
+}
+
+// POST /accept_invite (json containing group_id, user_id, )
+#[post("/accept_invite", data = "<data>")]
+pub async fn accept_invite(data: Body<AcceptInvite>, state: &State<ServerState>) -> ApiResult<()> {
+    let server = &state.convo_server;
+    let data = data.into_inner();
+    server.client_accept_invite(data.group_id, data.sender_id)?;
+    Ok(())
+}
+
+// POST /group_index (json containing group_id and sender_id)
+#[post("/group_index", data = "<data>")]
+pub async fn group_index(
+    data: Body<GetGroupIndex>,
+    state: &State<ServerState>,
+) -> ApiResult<Json<GroupIndexResponse>> {
+    let server = &state.convo_server;
+    let data = data.into_inner();
+    let global_index = server.client_get_group_index(data.group_id, data.sender_id)?;
+    Ok(Json(GroupIndexResponse { global_index }))
+}
@@ -1 +1 @@
-cargo run --example server
+cargo run --bin skychat_server -- "$@"
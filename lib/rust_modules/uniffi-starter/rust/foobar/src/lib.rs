@@ -6,6 +6,8 @@ uniffi::setup_scaffolding!();
 pub mod wrappers;
 use crate::wrappers::*;
 use skychat_core::manager::*;
+use skychat_protocol::codec::{self, WireFormat};
+use skychat_protocol::{MessagesResponse, Sync, SyncResponse, Versioned};
 // use skychat_core::*;
 
 use skychat_core::utils::BufferConverter;
@@ -82,12 +84,62 @@ impl ConvoManager {
     ) -> Result<ConvoInviteWrapper, ConvoError> {
         let mut inner = self.inner.lock().expect("Error locking inner");
 
-        let invite = inner
+        // the commit stays pending until `group_merge_pending_commit` (the server accepted it),
+        // `group_discard_pending_commit` (it turned it down) or `group_commit_unconfirmed`
+        // (no answer either way):
+        let mut invite = inner
             .create_invite(group_id, key_package)
-            .expect("Error creating invite");
+            .map_err(|e| ConvoError::ProcessingError(format!("{:#}", e)))?;
+        // the welcome carries the tree now, apps still expect the field:
+        invite.ratchet_tree.get_or_insert_with(Vec::new);
         Ok(invite.into())
     }
 
+    // answers another member's rejoin request, staged like `create_invite`
+    pub fn readd_member(
+        &self,
+        group_id: &GroupId,
+        user_id: String,
+        key_package: Vec<u8>,
+    ) -> Result<ConvoInviteWrapper, ConvoError> {
+        let mut inner = self.inner.lock().expect("Error locking inner");
+
+        inner
+            .readd_member(group_id, &user_id, key_package)
+            .map(Into::into)
+            .map_err(|e| ConvoError::ProcessingError(format!("{:#}", e)))
+    }
+
+    pub fn group_merge_pending_commit(&self, group_id: GroupId) -> Result<(), ConvoError> {
+        let mut inner = self.inner.lock().expect("Error locking inner");
+
+        inner
+            .group_merge_pending_commit(&group_id)
+            .map_err(|e| ConvoError::ProcessingError(format!("{:#}", e)))
+    }
+
+    pub fn group_discard_pending_commit(&self, group_id: GroupId) -> Result<(), ConvoError> {
+        let mut inner = self.inner.lock().expect("Error locking inner");
+
+        inner
+            .group_discard_pending_commit(&group_id)
+            .map_err(|e| ConvoError::ProcessingError(format!("{:#}", e)))
+    }
+
+    // posting the pending commit got no answer, the next `process_sync_bin` settles it.
+    // `commit` is what was posted (the invite's `fanned`)
+    pub fn group_commit_unconfirmed(
+        &self,
+        group_id: GroupId,
+        commit: Vec<u8>,
+    ) -> Result<(), ConvoError> {
+        let mut inner = self.inner.lock().expect("Error locking inner");
+
+        inner
+            .group_commit_unconfirmed(&group_id, commit)
+            .map_err(|e| ConvoError::ProcessingError(format!("{:#}", e)))
+    }
+
     pub fn process_raw_invite(
         &self,
         group_name: String,
@@ -134,57 +186,100 @@ impl ConvoManager {
     pub fn process_convo_messages(
         &self,
         messages: Vec<ConvoMessageWrapper>,
-        group_id: Option<GroupId>,
     ) -> Result<(), ConvoError> {
         let mut inner = self.inner.lock().expect("Error locking inner");
 
-        // convert Option<GroupId> to Option<&GroupId>
-        let group_id_ref = group_id.as_ref();
         inner
-            .process_convo_messages(
-                messages.into_iter().map(|m| m.into()).collect(),
-                group_id_ref,
-            )
-            .expect("Error processing convo messages");
-        Ok(())
+            .process_convo_messages(messages.into_iter().map(|m| m.into()).collect())
+            .map_err(|e| ConvoError::ProcessingError(format!("{:#}", e)))
     }
 
-    pub fn process_convo_messages_bin(
-        &self,
-        messages: Vec<EncodedBase64>,
-        group_id: Option<GroupId>,
-    ) -> Result<u64, ConvoError> {
-        if messages.is_empty() {
+    // takes the raw body of a cbor get_new_messages response:
+    pub fn process_convo_messages_bin(&self, body: Vec<u8>) -> Result<u64, ConvoError> {
+        if body.is_empty() {
             return Ok(0); // don't bother
         }
 
+        let response: MessagesResponse = codec::decode(&body, WireFormat::Cbor)
+            .map_err(|e| ConvoError::ProcessingError(format!("{:#}", e)))?;
+
+        if response.messages.is_empty() {
+            return Ok(0);
+        }
+
         let mut inner = self.inner.lock().expect("Error locking inner");
 
-        // convert Option<GroupId> to Option<&GroupId>
-        let group_id_ref = group_id.as_ref();
-        // convert Vec<EncodedBase64> to Vec<ConvoMessage>
+        let count = response.messages.len() as u64;
 
-        // convert Vec<EncodedBase64> to Vec<ConvoMessage>
-        let messages_bin: Vec<ConvoMessage> = messages
-            .into_iter()
-            .filter_map(
-                |encoded| match BufferConverter::from_base64_json(&encoded) {
-                    Ok(message) => Some(message),
-                    // Err(err) => anyhow::bail!(err),
-                    Err(err) => {
-                        println!("Error from_base64_json: {}", err);
-                        None
-                    }
-                },
-            )
-            .collect();
+        inner
+            .process_convo_messages(response.messages)
+            .map_err(|e| ConvoError::ProcessingError(format!("{:#}", e)))?;
+
+        // return the number of messages just so we know we processed them:
+        Ok(count)
+    }
+
+    // a cbor encoded sync request for every group we're in, ready to POST to /sync:
+    pub fn create_sync_request_bin(&self, sender_id: String) -> Result<Vec<u8>, ConvoError> {
+        let inner = self.inner.lock().expect("Error locking inner");
+
+        let request = Versioned::new(Sync {
+            sender_id,
+            groups: inner.group_indices(),
+        });
+
+        codec::encode(&request, WireFormat::Cbor)
+            .map_err(|e| ConvoError::ProcessingError(format!("{:#}", e)))
+    }
+
+    // takes the raw body of a cbor sync response, returns the number of messages processed:
+    pub fn process_sync_bin(&self, body: Vec<u8>) -> Result<u64, ConvoError> {
+        let response: SyncResponse = codec::decode(&body, WireFormat::Cbor)
+            .map_err(|e| ConvoError::ProcessingError(format!("{:#}", e)))?;
+
+        let count = response
+            .groups
+            .values()
+            .map(|group| group.messages.len() as u64)
+            .sum::<u64>()
+            + response.mailbox.len() as u64;
+
+        let mut inner = self.inner.lock().expect("Error locking inner");
 
         inner
-            .process_convo_messages(messages_bin.clone(), group_id_ref)
-            .expect("Error processing convo messages");
+            .process_sync_response(response)
+            .map_err(|e| ConvoError::ProcessingError(format!("{:#}", e)))?;
+
+        Ok(count)
+    }
+
+    // same as process_sync_bin, but returns what changed (new messages, invites, members
+    // joining / leaving, ...) so the app doesn't have to diff chats itself:
+    pub fn process_sync_events_bin(
+        &self,
+        body: Vec<u8>,
+    ) -> Result<Vec<SyncEventWrapper>, ConvoError> {
+        let response: SyncResponse = codec::decode(&body, WireFormat::Cbor)
+            .map_err(|e| ConvoError::ProcessingError(format!("{:#}", e)))?;
+
+        let mut inner = self.inner.lock().expect("Error locking inner");
+
+        let before = inner.snapshot();
+        let processed = inner.process_sync_response(response);
+
+        // whatever was processed before a failure still counts:
+        let mut events: Vec<SyncEventWrapper> = inner
+            .changes_since(&before)
+            .into_iter()
+            .map(Into::into)
+            .collect();
+        if let Err(e) = processed {
+            events.push(SyncEventWrapper::SyncError {
+                error: format!("{:#}", e),
+            });
+        }
 
-        // return the size of the messages_bin just so we know we processed them:
-        Ok(messages_bin.clone().len() as u64)
+        Ok(events)
     }
 
     pub fn get_chats(&self) -> Result<Vec<ConvoChatWrapper>, ConvoError> {
@@ -263,6 +358,43 @@ impl ConvoManager {
         Ok(key_package)
     }
 
+    // one-time key packages to upload to the server, the private keys stay in our state:
+    pub fn generate_key_packages(&self, count: u32) -> Result<Vec<Vec<u8>>, ConvoError> {
+        let inner = self.inner.lock().expect("Error locking inner");
+
+        inner
+            .generate_key_packages(count as usize)
+            .map_err(|e| ConvoError::ProcessingError(format!("{:#}", e)))
+    }
+
+    pub fn get_last_resort_key_package(&self) -> Result<Vec<u8>, ConvoError> {
+        let inner = self.inner.lock().expect("Error locking inner");
+
+        inner
+            .get_last_resort_key_package()
+            .map_err(|e| ConvoError::ProcessingError(format!("{:#}", e)))
+    }
+
+    // records packages the server took. `replace` for a rotation, which also notes the time:
+    pub fn key_packages_published(
+        &self,
+        key_packages: Vec<Vec<u8>>,
+        replace: bool,
+    ) -> Result<(), ConvoError> {
+        let mut inner = self.inner.lock().expect("Error locking inner");
+
+        inner
+            .key_packages_published(&key_packages, replace)
+            .map_err(|e| ConvoError::ProcessingError(format!("{:#}", e)))
+    }
+
+    // unix seconds, 0 if we've never rotated:
+    pub fn last_key_package_rotation(&self) -> u64 {
+        let inner = self.inner.lock().expect("Error locking inner");
+
+        inner.last_key_package_rotation
+    }
+
     pub fn group_set_index(&self, group_id: GroupId, index: u64) -> Result<(), ConvoError> {
         let mut inner = self.inner.lock().expect("Error locking inner");
 
@@ -15,8 +15,8 @@ pub struct ConvoInviteWrapper {
     pub fanned: Option<Vec<u8>>,
 }
 
-impl From<skychat_core::manager::ConvoInvite> for ConvoInviteWrapper {
-    fn from(invite: skychat_core::manager::ConvoInvite) -> Self {
+impl From<skychat_protocol::ConvoInvite> for ConvoInviteWrapper {
+    fn from(invite: skychat_protocol::ConvoInvite) -> Self {
         Self {
             group_name: invite.group_name,
             welcome_message: BufferConverter::to_base64(&invite.welcome_message),
@@ -27,7 +27,7 @@ impl From<skychat_core::manager::ConvoInvite> for ConvoInviteWrapper {
     }
 }
 
-impl From<ConvoInviteWrapper> for skychat_core::manager::ConvoInvite {
+impl From<ConvoInviteWrapper> for skychat_protocol::ConvoInvite {
     fn from(wrapper: ConvoInviteWrapper) -> Self {
         Self {
             group_name: wrapper.group_name,
@@ -42,6 +42,7 @@ impl From<ConvoInviteWrapper> for skychat_core::manager::ConvoInvite {
 // Wrapper for ProcessedResults
 #[derive(uniffi::Record)]
 pub struct ProcessedResultsWrapper {
+    pub group_id: Vec<u8>,
     pub message: Option<String>,
     pub invite: Option<ConvoInviteWrapper>,
 }
@@ -49,6 +50,7 @@ pub struct ProcessedResultsWrapper {
 impl From<skychat_core::manager::ProcessedResults> for ProcessedResultsWrapper {
     fn from(results: skychat_core::manager::ProcessedResults) -> Self {
         Self {
+            group_id: results.group_id,
             message: results.message,
             invite: results.invite.map(Into::into), // Convert ConvoInvite to ConvoInviteWrapper
         }
@@ -58,18 +60,137 @@ impl From<skychat_core::manager::ProcessedResults> for ProcessedResultsWrapper {
 impl From<ProcessedResultsWrapper> for skychat_core::manager::ProcessedResults {
     fn from(wrapper: ProcessedResultsWrapper) -> Self {
         Self {
+            group_id: wrapper.group_id,
             message: wrapper.message,
             invite: wrapper.invite.map(Into::into), // Convert ConvoInviteWrapper to ConvoInvite
         }
     }
 }
 
+// Wrapper for DeliveryState
+#[derive(uniffi::Enum)]
+pub enum DeliveryStateWrapper {
+    Composing,
+    Queued,
+    Sent { global_index: u64 },
+    Failed { reason: String },
+    Received,
+}
+
+impl From<skychat_core::manager::DeliveryState> for DeliveryStateWrapper {
+    fn from(state: skychat_core::manager::DeliveryState) -> Self {
+        use skychat_core::manager::DeliveryState;
+        match state {
+            DeliveryState::Composing => Self::Composing,
+            DeliveryState::Queued => Self::Queued,
+            DeliveryState::Sent { global_index } => Self::Sent { global_index },
+            DeliveryState::Failed { reason } => Self::Failed { reason },
+            DeliveryState::Received => Self::Received,
+        }
+    }
+}
+
+// Wrapper for SyncEvent
+#[derive(uniffi::Enum)]
+pub enum SyncEventWrapper {
+    NewMessage {
+        group_id: Vec<u8>,
+        sender_id: String,
+        text: String,
+        timestamp: u64,
+    },
+    InviteReceived {
+        group_name: String,
+        welcome_message: Vec<u8>,
+    },
+    MemberJoined { group_id: Vec<u8>, user_id: String },
+    MemberLeft { group_id: Vec<u8>, user_id: String },
+    EpochChanged { group_id: Vec<u8>, epoch: u64 },
+    GroupRenamed { group_id: Vec<u8>, name: String },
+    Delivery {
+        group_id: Vec<u8>,
+        local_id: u64,
+        state: DeliveryStateWrapper,
+    },
+    MessageDropped {
+        group_id: Vec<u8>,
+        global_index: u64,
+        reason: String,
+    },
+    ForkDetected {
+        group_id: Vec<u8>,
+        epoch: u64,
+        messages: u64,
+    },
+    SyncError { error: String },
+}
+
+impl From<skychat_core::changes::SyncEvent> for SyncEventWrapper {
+    fn from(event: skychat_core::changes::SyncEvent) -> Self {
+        use skychat_core::changes::SyncEvent;
+        match event {
+            SyncEvent::NewMessage {
+                group_id,
+                sender_id,
+                text,
+                timestamp,
+            } => Self::NewMessage {
+                group_id,
+                sender_id,
+                text,
+                timestamp,
+            },
+            SyncEvent::InviteReceived {
+                group_name,
+                welcome_message,
+            } => Self::InviteReceived {
+                group_name,
+                welcome_message,
+            },
+            SyncEvent::MemberJoined { group_id, user_id } => Self::MemberJoined { group_id, user_id },
+            SyncEvent::MemberLeft { group_id, user_id } => Self::MemberLeft { group_id, user_id },
+            SyncEvent::EpochChanged { group_id, epoch } => Self::EpochChanged { group_id, epoch },
+            SyncEvent::GroupRenamed { group_id, name } => Self::GroupRenamed { group_id, name },
+            SyncEvent::Delivery {
+                group_id,
+                local_id,
+                state,
+            } => Self::Delivery {
+                group_id,
+                local_id,
+                state: state.into(),
+            },
+            SyncEvent::MessageDropped {
+                group_id,
+                global_index,
+                reason,
+            } => Self::MessageDropped {
+                group_id,
+                global_index,
+                reason,
+            },
+            SyncEvent::ForkDetected {
+                group_id,
+                epoch,
+                messages,
+            } => Self::ForkDetected {
+                group_id,
+                epoch,
+                messages: messages as u64,
+            },
+            SyncEvent::SyncError { error } => Self::SyncError { error },
+        }
+    }
+}
+
 // Wrapper for MessageItem
 #[derive(uniffi::Record)]
 pub struct MessageItemWrapper {
     pub text: String,
     pub sender_id: String,
     pub timestamp: u64,
+    pub state: DeliveryStateWrapper,
+    pub local_id: Option<u64>,
 }
 
 impl From<skychat_core::manager::MessageItem> for MessageItemWrapper {
@@ -78,6 +199,8 @@ impl From<skychat_core::manager::MessageItem> for MessageItemWrapper {
             text: item.text,
             sender_id: item.sender_id,
             timestamp: item.timestamp,
+            state: item.state.into(),
+            local_id: item.local_id,
         }
     }
 }
@@ -89,6 +212,8 @@ impl From<&skychat_core::manager::MessageItem> for MessageItemWrapper {
             text: item.text.clone(),
             sender_id: item.sender_id.clone(),
             timestamp: item.timestamp,
+            state: item.state.clone().into(),
+            local_id: item.local_id,
         }
     }
 }
@@ -109,6 +234,14 @@ pub struct SerializedCredentialsWrapper {
     pub group_names: Vec<String>,
     pub group_name_to_id: HashMap<String, Vec<u8>>,
     pub serialized_credential_with_key: Vec<u8>,
+    pub used_init_keys: Vec<Vec<u8>>,
+    // JSON, where we are in each group (see `GroupProgress`):
+    pub group_progress: Vec<u8>,
+    // JSON, so the keys of retired key packages are still deleted after a reload:
+    pub published_key_packages: Vec<u8>,
+    pub last_key_package_rotation: u64,
+    // the client's unsent messages, passed through as is:
+    pub outbox: Vec<u8>,
 }
 
 impl From<skychat_core::manager::SerializedCredentials> for SerializedCredentialsWrapper {
@@ -119,6 +252,13 @@ impl From<skychat_core::manager::SerializedCredentials> for SerializedCredential
             storage: state.storage,
             group_names: state.group_names,
             group_name_to_id: state.group_name_to_id,
+            used_init_keys: state.used_init_keys,
+            group_progress: serde_json::to_vec(&state.group_progress)
+                .expect("Failed to serialize group progress"),
+            published_key_packages: serde_json::to_vec(&state.published_key_packages)
+                .expect("Failed to serialize published key packages"),
+            last_key_package_rotation: state.last_key_package_rotation,
+            outbox: state.outbox,
         }
     }
 }
@@ -131,6 +271,13 @@ impl From<SerializedCredentialsWrapper> for skychat_core::manager::SerializedCre
             storage: wrapper.storage,
             group_names: wrapper.group_names,
             group_name_to_id: wrapper.group_name_to_id,
+            used_init_keys: wrapper.used_init_keys,
+            // empty for state saved before these were kept:
+            group_progress: serde_json::from_slice(&wrapper.group_progress).unwrap_or_default(),
+            published_key_packages: serde_json::from_slice(&wrapper.published_key_packages)
+                .unwrap_or_default(),
+            last_key_package_rotation: wrapper.last_key_package_rotation,
+            outbox: wrapper.outbox,
         }
     }
 }
@@ -152,8 +299,8 @@ pub struct ConvoMessageWrapper {
     pub invite: Option<ConvoInviteWrapper>,
 }
 
-impl From<skychat_core::manager::ConvoMessage> for ConvoMessageWrapper {
-    fn from(message: skychat_core::manager::ConvoMessage) -> Self {
+impl From<skychat_protocol::ConvoMessage> for ConvoMessageWrapper {
+    fn from(message: skychat_protocol::ConvoMessage) -> Self {
         Self {
             global_index: message.global_index,
             unix_timestamp: message.unix_timestamp,
@@ -163,7 +310,7 @@ impl From<skychat_core::manager::ConvoMessage> for ConvoMessageWrapper {
     }
 }
 
-impl From<ConvoMessageWrapper> for skychat_core::manager::ConvoMessage {
+impl From<ConvoMessageWrapper> for skychat_protocol::ConvoMessage {
     fn from(wrapper: ConvoMessageWrapper) -> Self {
         Self {
             global_index: wrapper.global_index,
@@ -8,6 +8,11 @@ type GroupId = ArrayBuffer;
 type SerializedMessage = ArrayBuffer;
 type EncodedGroupId = string;
 
+// how many one-time key packages we keep on the server:
+const KEY_PACKAGE_POOL_SIZE = 20;
+// how often (seconds) the whole pool and last resort package are replaced:
+const KEY_PACKAGE_ROTATION = 60 * 60 * 24 * 7;
+
 interface MessageItem {
   text: string;
   senderId: string;
@@ -190,7 +195,8 @@ export class ConvoClient {
         "Content-Type": "application/json"
       },
       body: JSON.stringify({
-        user_ids: userIds
+        user_ids: userIds,
+        sender_id: this.id
       })
     });
 
@@ -228,6 +234,23 @@ export class ConvoClient {
     //   console.log("error: ", error);
     // }
 
+    // accounts are created explicitly now, a conflict just means it's ours from an earlier session:
+    const accountResponse = await fetch(`${address}/api/v1/create_account`, {
+      method: "POST",
+      headers: {
+        "Content-Type": "application/json"
+      },
+      body: JSON.stringify({
+        user_id: this.id,
+        serialized_key_package: this.toUrlSafeB64(this.manager.getKeyPackage())
+      })
+    });
+
+    if (!accountResponse.ok && accountResponse.status !== 409) {
+      console.error("Failed to create account", accountResponse);
+      throw new Error("Failed to create account");
+    }
+
     const response = await fetch(`${address}/api/connect`, {
       method: "POST",
       headers: {
@@ -249,12 +272,58 @@ export class ConvoClient {
       throw new Error("Failed to connect to server");
     }
 
+    // key packages are single use, give the server a pool to hand out to inviters. the whole
+    // pool is only replaced when a rotation is due (or we've never published one), otherwise
+    // it's topped up:
+    const lastRotation = Number(this.manager.lastKeyPackageRotation());
+    const now = Math.floor(Date.now() / 1000);
+    if (lastRotation === 0 || now - lastRotation >= KEY_PACKAGE_ROTATION) {
+      await this.uploadKeyPackages(KEY_PACKAGE_POOL_SIZE, true);
+    } else {
+      const stock = await this.uploadKeyPackages(0, false);
+      if (stock.remaining < KEY_PACKAGE_POOL_SIZE) {
+        await this.uploadKeyPackages(KEY_PACKAGE_POOL_SIZE - stock.remaining, false);
+      }
+    }
+
     // every 10 seconds, get any new messages and process them
     setInterval(() => {
       this.checkIncomingMessages();
     }, 10000);
   }
 
+  // with `rotate` the packages replace everything uploaded before, along with a new last resort
+  // package. returns how many one-time packages the server has left
+  async uploadKeyPackages(count: number, rotate: boolean): Promise<{ remaining: number; low: boolean }> {
+    const keyPackages = this.manager.generateKeyPackages(count);
+    const lastResort = rotate ? this.manager.getLastResortKeyPackage() : undefined;
+
+    const response = await fetch(`${this.serverAddress}/api/v1/upload_key_packages`, {
+      method: "POST",
+      headers: {
+        "Content-Type": "application/json"
+      },
+      body: JSON.stringify({
+        user_id: this.id,
+        key_packages: keyPackages.map((keyPackage) => this.toUrlSafeB64(keyPackage)),
+        last_resort: lastResort ? this.toUrlSafeB64(lastResort) : undefined,
+        replace: rotate
+      })
+    });
+
+    if (!response.ok) {
+      console.error("Failed to upload key packages", response);
+      throw new Error("Failed to upload key packages");
+    }
+
+    // a rotation's time is kept with the manager state, as are the new packages' private keys:
+    this.manager.keyPackagesPublished(lastResort ? [...keyPackages, lastResort] : keyPackages, rotate);
+    const state = this.manager.saveState();
+    await saveManagerStateToStorage(state);
+
+    return await response.json();
+  }
+
   // async listUsers(): Promise<ConvoUser[]> {
   //   if (!this.serverAddress) {
   //     throw new Error("Server address is not set");
@@ -275,24 +344,39 @@ export class ConvoClient {
 
     console.log(`inviting user to group: ${receiverId}, ${groupId.byteLength}, ${serializedKeyPackage.byteLength}`);
 
+    // the commit adding them stays pending until we know whether the server took it:
     const groupInvite = this.manager.createInvite(groupId, serializedKeyPackage);
 
-    const response = await fetch(`${this.serverAddress}/api/invite_user`, {
-      method: "POST",
-      headers: {
-        "Content-Type": "application/json"
-      },
-      body: JSON.stringify({
-        group_id: this.toUrlSafeB64(groupId),
-        sender_id: this.id,
-        receiver_id: receiverId,
-        // welcome_message: this.toUrlSafeB64(groupInvite.welcomeMessage),// TODO: this is broken
-        ratchet_tree: this.toUrlSafeB64(groupInvite.ratchetTree!),
-        fanned: this.toUrlSafeB64(groupInvite.fanned!)
-      })
-    });
+    let response: Response;
+    try {
+      response = await fetch(`${this.serverAddress}/api/invite_user`, {
+        method: "POST",
+        headers: {
+          "Content-Type": "application/json"
+        },
+        body: JSON.stringify({
+          group_id: this.toUrlSafeB64(groupId),
+          sender_id: this.id,
+          receiver_id: receiverId,
+          // welcome_message: this.toUrlSafeB64(groupInvite.welcomeMessage),// TODO: this is broken
+          ratchet_tree: this.toUrlSafeB64(groupInvite.ratchetTree!),
+          fanned: this.toUrlSafeB64(groupInvite.fanned!)
+        })
+      });
+    } catch (error) {
+      // no answer, it may have gone through. the next sync finds out:
+      this.manager.groupCommitUnconfirmed(groupId, groupInvite.fanned!);
+      throw error;
+    }
 
-    if (!response.ok) {
+    if (response.ok) {
+      this.manager.groupMergePendingCommit(groupId);
+    } else if (response.status < 500) {
+      // turned down (e.g. someone else committed first), nobody has seen it:
+      this.manager.groupDiscardPendingCommit(groupId);
+      throw new Error("Failed to send invite");
+    } else {
+      this.manager.groupCommitUnconfirmed(groupId, groupInvite.fanned!);
       throw new Error("Failed to send invite");
     }
   }
@@ -327,11 +411,32 @@ export class ConvoClient {
   //   this.manager.pendingInvites = [];
   // }
 
-  // async acceptPendingInvite(welcomeMessage: ArrayBuffer): Promise<ArrayBuffer> {
-  //   const groupId = this.manager.acceptPendingInvite(welcomeMessage);
-  //   await this.syncGroup(groupId);
-  //   return groupId;
-  // }
+  async acceptPendingInvite(welcomeMessage: string): Promise<ArrayBuffer> {
+    if (!this.serverAddress) {
+      throw new Error("Server address is not set");
+    }
+
+    const groupId = this.manager.acceptPendingInvite(welcomeMessage);
+
+    // the server only lets members read the group, so tell it we've joined:
+    const response = await fetch(`${this.serverAddress}/api/accept_invite`, {
+      method: "POST",
+      headers: {
+        "Content-Type": "application/json"
+      },
+      body: JSON.stringify({
+        group_id: this.toUrlSafeB64(groupId),
+        sender_id: this.id
+      })
+    });
+
+    if (!response.ok) {
+      throw new Error(`Failed to accept invite: ${response.status}`);
+    }
+
+    await this.syncGroup(groupId);
+    return groupId;
+  }
 
   // async rejectPendingInvite(welcomeMessage: ArrayBuffer): Promise<void> {
   //   const groupId = this.manager.rejectPendingInvite(welcomeMessage);
@@ -348,10 +453,12 @@ export class ConvoClient {
       index = Number(await this.manager.groupGetIndex(groupId));
     }
 
-    const response = await fetch(`${this.serverAddress}/api/get_new_messages_bin`, {
+    // ask for a cbor body, the binary fields stay raw bytes instead of base64:
+    const response = await fetch(`${this.serverAddress}/api/v1/get_new_messages`, {
       method: "POST",
       headers: {
-        "Content-Type": "application/json"
+        "Content-Type": "application/json",
+        Accept: "application/cbor"
       },
       body: JSON.stringify({
         group_id: groupId ? this.toUrlSafeB64(groupId) : undefined,
@@ -361,15 +468,53 @@ export class ConvoClient {
       })
     });
 
-    const messages: string[] = await response.json();
+    const body = await response.arrayBuffer();
 
-    if (messages.length > 0) {
-      console.log("messages: ", messages);
+    // body is a cbor encoded MessagesResponse:
+    try {
+      const count = this.manager.processConvoMessagesBin(body);
+      if (count > 0) {
+        console.log("messages: ", count);
+      }
+    } catch (error) {
+      console.error("error: ", error);
+    }
+
+    // save the manager state:
+    const state = this.manager.saveState();
+    await saveManagerStateToStorage(state);
+  }
+
+  // catch up on every group plus the mailbox in a single request:
+  async sync(): Promise<void> {
+    if (!this.serverAddress) {
+      throw new Error("Server address is not set");
+    }
+
+    // the request body (group id -> last seen index) is built and encoded on the rust side:
+    const request = this.manager.createSyncRequestBin(this.id);
+
+    const response = await fetch(`${this.serverAddress}/api/v1/sync`, {
+      method: "POST",
+      headers: {
+        "Content-Type": "application/cbor",
+        Accept: "application/cbor"
+      },
+      body: request
+    });
+
+    if (!response.ok) {
+      throw new Error(`Failed to sync: ${response.status}`);
     }
 
-    // messages is a list of base64 encoded strings:
+    const body = await response.arrayBuffer();
+
+    // body is a cbor encoded SyncResponse:
     try {
-      this.manager.processConvoMessagesBin(messages, groupId);
+      const count = this.manager.processSyncBin(body);
+      if (count > 0) {
+        console.log("messages: ", count);
+      }
     } catch (error) {
       console.error("error: ", error);
     }
@@ -71,7 +71,12 @@ const prepareCredentialsForStorage = (wrapper: SerializedCredentialsWrapper): an
     storage: serializeMap(wrapper.storage),
     groupNames: wrapper.groupNames,
     groupNameToId: serializeMap(wrapper.groupNameToId),
-    serializedCredentialWithKey: arrayBufferToBase64(wrapper.serializedCredentialWithKey)
+    serializedCredentialWithKey: arrayBufferToBase64(wrapper.serializedCredentialWithKey),
+    usedInitKeys: wrapper.usedInitKeys.map(arrayBufferToBase64),
+    groupProgress: arrayBufferToBase64(wrapper.groupProgress),
+    publishedKeyPackages: arrayBufferToBase64(wrapper.publishedKeyPackages),
+    lastKeyPackageRotation: wrapper.lastKeyPackageRotation.toString(),
+    outbox: arrayBufferToBase64(wrapper.outbox)
   };
 };
 
@@ -84,7 +89,17 @@ const restoreCredentialsFromStorage = (data: any): SerializedCredentialsWrapper
     storage: deserializeMap(data.storage, true), // true means values are ArrayBuffers
     groupNames: data.groupNames,
     groupNameToId: deserializeMap(data.groupNameToId, true), // true means values are ArrayBuffers
-    serializedCredentialWithKey: base64ToArrayBuffer(data.serializedCredentialWithKey)
+    serializedCredentialWithKey: base64ToArrayBuffer(data.serializedCredentialWithKey),
+    // state saved before used init keys were tracked won't have any:
+    usedInitKeys: (data.usedInitKeys ?? []).map(base64ToArrayBuffer),
+    // without it every group is refetched from the start:
+    groupProgress: base64ToArrayBuffer(data.groupProgress ?? ""),
+    // the manager treats an empty list as nothing published yet:
+    publishedKeyPackages: base64ToArrayBuffer(data.publishedKeyPackages ?? ""),
+    // 0 (never) makes the next connect rotate:
+    lastKeyPackageRotation: BigInt(data.lastKeyPackageRotation ?? 0),
+    // empty means no unsent messages:
+    outbox: base64ToArrayBuffer(data.outbox ?? "")
   };
 };
 